@@ -1,11 +1,30 @@
+use embedded_time::fraction::Fraction;
 use embedded_time::rate::Extensions;
+use embedded_time::{Clock, Instant};
 use lc::animations::{Animatable, Animation};
 use lc::{default_animations, LightingController, LogicalStrip};
 use lighting_controller as lc;
 use smart_leds::colors::*;
 
+/// A stand-in for the platform's hardware timer, counting microseconds since startup.
+struct SystemClock {
+    start: std::time::Instant,
+}
+
+impl Clock for SystemClock {
+    type T = u64;
+    const SCALING_FACTOR: Fraction = Fraction::new(1, 1_000_000);
+
+    fn try_now(&self) -> Result<Instant<Self>, embedded_time::clock::Error> {
+        Ok(Instant::new(self.start.elapsed().as_micros() as u64))
+    }
+}
+
 fn main() {
     let frame_rate = 60.Hz();
+    let clock = SystemClock {
+        start: std::time::Instant::now(),
+    };
     let color_buffer = &mut [BLACK; 16];
     let mut ls = LogicalStrip::new(color_buffer);
     let a1 = &mut Animation::<16>::new(default_animations::ANI_DEFAULT, frame_rate);
@@ -13,7 +32,9 @@ fn main() {
     let mut lc = LightingController::new(animations, frame_rate);
 
     loop {
-        //this should have a check to limit updates to match the frame_rate:
-        lc.update(&mut ls);
+        let now = clock.try_now().unwrap();
+        if lc.tick(now, &mut ls) {
+            // this is where the logical strip would be written out to the leds
+        }
     }
 }
@@ -0,0 +1,57 @@
+use embedded_time::rate::Extensions;
+use lc::animations::{
+    background, foreground, trigger, Animatable, Animation, AnimationParameters, Direction,
+    RainbowDir,
+};
+use lc::colors::{Pixel, RGBW8};
+use lc::{default_animations, LightingController, LogicalStrip};
+use lighting_controller as lc;
+use rgb::RGB8;
+
+fn main() {
+    let frame_rate = 60.Hz();
+    let color_buffer = &mut [RGBW8::default(); 16];
+    let mut ls = LogicalStrip::new(color_buffer);
+
+    // The default animations use RGB rainbows, and the white channel is extracted automatically
+    // when they are composited onto the RGBW strip:
+    let a1 = &mut Animation::<16>::new(default_animations::ANI_DEFAULT, frame_rate);
+    let animations: [&mut dyn Animatable; 1] = [a1];
+    let mut rgb_lc = LightingController::new(animations, frame_rate);
+    rgb_lc.update(&mut ls);
+
+    // Animations can also be rendered natively in RGBW with their own RGBW rainbows:
+    let warm_white = RGBW8::from_rgb(RGB8::new(255, 200, 150));
+    let rainbow = [warm_white, RGBW8::default()];
+    let parameters = AnimationParameters {
+        bg: background::Parameters {
+            mode: background::Mode::FillRainbowRotate,
+            rainbow: &rainbow,
+            direction: Direction::Positive,
+            rainbow_dir: RainbowDir::Forward,
+            duration_ns: 5_000_000_000,
+            subdivisions: 1,
+            blend_mode: Default::default(),
+        },
+        fg: foreground::Parameters {
+            mode: foreground::Mode::NoForeground,
+            rainbow: &rainbow,
+            direction: Direction::Stopped,
+            rainbow_dir: RainbowDir::Forward,
+            duration_ns: 0,
+            step_time_ns: 0,
+            subdivisions: 1,
+            pixels_per_pixel_group: 1,
+            blend_mode: Default::default(),
+        },
+        trigger: trigger::GlobalParameters {
+            rainbow: &rainbow,
+            rainbow_dir: RainbowDir::Forward,
+            duration_ns: 0,
+        },
+    };
+    let a2 = &mut Animation::<16, RGBW8>::new(parameters, frame_rate);
+    let animations: [&mut dyn Animatable<RGBW8>; 1] = [a2];
+    let mut rgbw_lc = LightingController::new(animations, frame_rate);
+    rgbw_lc.update(&mut ls);
+}
@@ -1,7 +1,12 @@
 pub mod background;
+pub mod external;
 pub mod foreground;
 pub mod trigger;
 
+use crate::colors::{BlendMode, Pixel};
+use crate::error::{self, Error};
+use crate::layout::positions::PositionMap;
+use crate::layout::{Grid, Order};
 use crate::utility::{
     convert_ns_to_frames, default_translation_array, Progression, StatefulRainbow,
 };
@@ -31,70 +36,172 @@ pub enum RainbowDir {
 }
 
 /// Denotes the main types of animations, e.g. Foreground, Background, or Trigger:
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum AnimationType {
     Background,
     Foreground,
     Trigger,
 }
 
+/// This controls how an animation's segment is composited onto the LogicalStrip by the
+/// LightingController. Animations are drawn from the lowest `z_order` to the highest, with ties
+/// drawn in the order they appear in the controller. Each animation is blended over the animations
+/// below it using its `blend_mode`, and then mixed in with its `opacity`, where 0 is invisible and
+/// 255 is fully opaque. The `brightness` dims the animation's colors before they are blended
+/// without changing the animation's own state.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Layer {
+    pub z_order: i8,
+    pub opacity: u8,
+    pub brightness: u8,
+    pub blend_mode: BlendMode,
+}
+
+impl Default for Layer {
+    fn default() -> Self {
+        Layer {
+            z_order: 0,
+            opacity: u8::MAX,
+            brightness: u8::MAX,
+            blend_mode: BlendMode::Replace,
+        }
+    }
+}
+
 /// This holds the parameters that define everything needed to set up an animation. It's a struct
 /// holding the parameters for the foreground animation, the background animation, and the global
 /// information for trigger animations (such as the trigger Rainbow)
-pub struct AnimationParameters<'a> {
-    pub bg: background::Parameters<'a>,
-    pub fg: foreground::Parameters<'a>,
-    pub trigger: trigger::GlobalParameters<'a>,
+#[derive(Copy, Clone)]
+pub struct AnimationParameters<'a, P = RGB8> {
+    pub bg: background::Parameters<'a, P>,
+    pub fg: foreground::Parameters<'a, P>,
+    pub trigger: trigger::GlobalParameters<'a, P>,
+}
+
+impl<'a, P> AnimationParameters<'a, P> {
+    /// Checks for parameters that can't be drawn the way they describe, such as an empty rainbow,
+    /// which is useful before applying parameters received over a remote protocol. Every mode has
+    /// a fallback for these, so an animation never panics because of them: empty rainbows are
+    /// unlit, and rainbows with too many colors give each offset the next color.
+    pub fn validate(&self) -> Result<(), Error> {
+        check_rainbows(
+            self.bg.rainbow.len(),
+            self.bg.subdivisions,
+            self.fg.rainbow.len(),
+            self.trigger.rainbow.len(),
+        )
+    }
 }
 
 /// This struct contains all the fixed parameters of an animation, as well as the state of the
 /// foreground, background, and active trigger animations. It is updated by the LightingController
 /// that it is attached to at the LightingController's frame rate based on the parameters provided.
 /// To make a new animation,
-pub struct Animation<'a, const N_LED: usize> {
+pub struct Animation<'a, const N_LED: usize, P = RGB8> {
     translation_array: [usize; N_LED],
-    segment: [RGB8; N_LED],
-    fg_state: foreground::Foreground<'a>,
-    bg_state: background::Background<'a>,
-    triggers: trigger::TriggerCollection<'a, MAX_NUM_ACTIVE_TRIGGERS>,
+    segment: [P; N_LED],
+    layer: Layer,
+    fg_state: foreground::Foreground<'a, P>,
+    bg_state: background::Background<'a, P>,
+    triggers: trigger::TriggerCollection<'a, MAX_NUM_ACTIVE_TRIGGERS, P>,
 }
 
-pub trait Animatable<'a> {
+pub trait Animatable<'a, P = RGB8> {
     fn update(&mut self);
+    /// Moves the animation to where it would be `frame` frames after it started with its current
+    /// parameters.
+    fn seek(&mut self, frame: usize);
     fn set_offset(&mut self, a_type: AnimationType, offset: u16);
-    fn trigger(&mut self, params: &trigger::Parameters, frame_rate: Hertz);
-    fn segment(&self) -> &[RGB8];
+    fn trigger(&mut self, params: &trigger::Parameters<P>, frame_rate: Hertz);
+    fn segment(&self) -> &[P];
+    /// Gives direct access to the segment's colors, such as for writing colors received from a
+    /// lighting console. Most animations redraw their whole segment on every update, so this is
+    /// mainly useful for an `external::ExternalAnimation`.
+    fn segment_mut(&mut self) -> &mut [P];
+    /// Tells the animation that new colors have been written into its segment, which keeps an
+    /// `external::ExternalAnimation` from timing out. Other animations ignore this.
+    fn mark_received(&mut self) {}
     fn translation_array(&self) -> &[usize];
+    fn layer(&self) -> Layer;
+    fn is_transparent(&self) -> bool;
+
+    /// Checks that the animation can be drawn onto a strip of `strip_len` leds without panicking:
+    /// the segment has pixels and every one of them is translated onto the strip.
+    fn validate(&self, strip_len: usize) -> Result<(), Error> {
+        error::check_segment(self.segment().len(), self.translation_array(), strip_len)
+    }
 
     fn update_translation_array(&mut self, new_array: &[usize]);
+    fn update_layer(&mut self, new_layer: Layer);
 
+    fn update_bg_blend_mode(&mut self, new_mode: BlendMode);
     fn update_bg_direction(&mut self, new_direction: Direction);
     fn update_bg_duration_ns(&mut self, new_time: u64, frame_rate: Hertz);
-    fn update_bg_mode(&mut self, new_mode: background::Mode);
-    fn update_bg_rainbow(&mut self, new_rainbow: &'a [RGB8], rainbow_dir: RainbowDir);
+    fn update_bg_mode(&mut self, new_mode: background::Mode<P>);
+    fn update_bg_rainbow(&mut self, new_rainbow: &'a [P], rainbow_dir: RainbowDir);
     fn update_bg_subdivisions(&mut self, new_value: usize);
 
+    fn update_fg_blend_mode(&mut self, new_mode: BlendMode);
     fn update_fg_direction(&mut self, new_direction: Direction);
     fn update_fg_duration_ns(&mut self, new_time: u64, frame_rate: Hertz);
-    fn update_fg_mode(&mut self, new_mode: foreground::Mode);
+    fn update_fg_mode(&mut self, new_mode: foreground::Mode<P>);
     fn update_fg_pixels_per_pixel_group(&mut self, new_value: usize);
-    fn update_fg_rainbow(&mut self, new_rainbow: &'a [RGB8], rainbow_dir: RainbowDir);
+    fn update_fg_rainbow(&mut self, new_rainbow: &'a [P], rainbow_dir: RainbowDir);
     fn update_fg_step_time_ns(&mut self, new_time: u64, frame_rate: Hertz);
     fn update_fg_subdivisions(&mut self, new_value: usize);
 
     fn update_trig_duration_ns(&mut self, new_time: u64, frame_rate: Hertz);
-    fn update_trig_fade_rainbow(&mut self, new_rainbow: &'a [RGB8], rainbow_dir: RainbowDir);
-    fn update_trig_incremental_rainbow(&mut self, new_rainbow: &'a [RGB8], rainbow_dir: RainbowDir);
+    fn update_trig_fade_rainbow(&mut self, new_rainbow: &'a [P], rainbow_dir: RainbowDir);
+    fn update_trig_incremental_rainbow(&mut self, new_rainbow: &'a [P], rainbow_dir: RainbowDir);
+
+    /// Applies a whole set of parameters using the individual `update_*` functions, leaving the
+    /// translation array and layer alone.
+    fn update_parameters(&mut self, parameters: &AnimationParameters<'a, P>, frame_rate: Hertz)
+    where
+        P: Pixel,
+    {
+        let bg = &parameters.bg;
+        self.update_bg_mode(bg.mode);
+        self.update_bg_rainbow(bg.rainbow, bg.rainbow_dir);
+        self.update_bg_direction(bg.direction);
+        self.update_bg_duration_ns(bg.duration_ns, frame_rate);
+        self.update_bg_subdivisions(bg.subdivisions);
+        self.update_bg_blend_mode(bg.blend_mode);
+
+        let fg = &parameters.fg;
+        self.update_fg_mode(fg.mode);
+        self.update_fg_rainbow(fg.rainbow, fg.rainbow_dir);
+        self.update_fg_direction(fg.direction);
+        self.update_fg_duration_ns(fg.duration_ns, frame_rate);
+        self.update_fg_step_time_ns(fg.step_time_ns, frame_rate);
+        self.update_fg_subdivisions(fg.subdivisions);
+        self.update_fg_pixels_per_pixel_group(fg.pixels_per_pixel_group);
+        self.update_fg_blend_mode(fg.blend_mode);
+
+        let trigger = &parameters.trigger;
+        self.update_trig_duration_ns(trigger.duration_ns, frame_rate);
+        self.update_trig_fade_rainbow(trigger.rainbow, trigger.rainbow_dir);
+        self.update_trig_incremental_rainbow(trigger.rainbow, trigger.rainbow_dir);
+    }
 }
 
-impl<'a, const N_LED: usize> Animatable<'a> for Animation<'a, N_LED> {
+impl<'a, const N_LED: usize, P: Pixel> Animatable<'a, P> for Animation<'a, N_LED, P> {
     fn update(&mut self) {
+        // Each layer blends over the one below it, starting from an unlit segment:
+        self.segment.fill(P::default());
+
         // Update all three states
         self.bg_state.update(&mut self.segment);
         self.fg_state.update(&mut self.segment);
         self.triggers.update(&mut self.segment);
     }
 
+    fn seek(&mut self, frame: usize) {
+        self.bg_state.seek(frame);
+        self.fg_state.seek(frame);
+        self.triggers.seek(frame);
+    }
+
     fn set_offset(&mut self, a_type: AnimationType, offset: u16) {
         match a_type {
             AnimationType::Background => {
@@ -109,7 +216,7 @@ impl<'a, const N_LED: usize> Animatable<'a> for Animation<'a, N_LED> {
         }
     }
 
-    fn trigger(&mut self, params: &trigger::Parameters, frame_rate: Hertz) {
+    fn trigger(&mut self, params: &trigger::Parameters<P>, frame_rate: Hertz) {
         match params.mode {
             trigger::Mode::NoTrigger => {}
             trigger::Mode::Background => {
@@ -122,14 +229,41 @@ impl<'a, const N_LED: usize> Animatable<'a> for Animation<'a, N_LED> {
         }
     }
 
-    fn segment(&self) -> &[RGB8] {
+    fn segment(&self) -> &[P] {
         &self.segment[..]
     }
 
+    fn segment_mut(&mut self) -> &mut [P] {
+        &mut self.segment[..]
+    }
+
     fn translation_array(&self) -> &[usize] {
         &self.translation_array[..]
     }
 
+    fn layer(&self) -> Layer {
+        self.layer
+    }
+
+    fn is_transparent(&self) -> bool {
+        self.bg_state.is_transparent
+    }
+
+    fn validate(&self, strip_len: usize) -> Result<(), Error> {
+        error::check_segment(N_LED, &self.translation_array, strip_len)?;
+        let triggers = &self.triggers;
+        check_rainbows(
+            self.bg_state.rainbow.backer.len(),
+            self.bg_state.subdivisions,
+            self.fg_state.rainbow.backer.len(),
+            triggers
+                .fade_rainbow
+                .backer
+                .len()
+                .min(triggers.incremental_rainbow.backer.len()),
+        )
+    }
+
     // universal settings functions: apply to all animation types - bg, fg, and triggers:
 
     fn update_translation_array(&mut self, new_array: &[usize]) {
@@ -140,8 +274,16 @@ impl<'a, const N_LED: usize> Animatable<'a> for Animation<'a, N_LED> {
         }
     }
 
+    fn update_layer(&mut self, new_layer: Layer) {
+        self.layer = new_layer;
+    }
+
     // bg settings functions - for setting the bg animation parameters:
 
+    fn update_bg_blend_mode(&mut self, new_mode: BlendMode) {
+        self.bg_state.blend_mode = new_mode;
+    }
+
     fn update_bg_direction(&mut self, new_direction: Direction) {
         self.bg_state.direction = new_direction;
     }
@@ -151,11 +293,12 @@ impl<'a, const N_LED: usize> Animatable<'a> for Animation<'a, N_LED> {
         self.bg_state.frames = Progression::new(frame_count);
     }
 
-    fn update_bg_mode(&mut self, new_mode: background::Mode) {
+    fn update_bg_mode(&mut self, new_mode: background::Mode<P>) {
         self.bg_state.updater = new_mode.get_updater();
+        self.bg_state.is_transparent = new_mode.is_transparent();
     }
 
-    fn update_bg_rainbow(&mut self, new_rainbow: &'a [RGB8], rainbow_dir: RainbowDir) {
+    fn update_bg_rainbow(&mut self, new_rainbow: &'a [P], rainbow_dir: RainbowDir) {
         self.bg_state.rainbow = StatefulRainbow::new(new_rainbow, rainbow_dir);
     }
 
@@ -165,6 +308,10 @@ impl<'a, const N_LED: usize> Animatable<'a> for Animation<'a, N_LED> {
 
     // fg settings functions - for setting the fg animation parameters:
 
+    fn update_fg_blend_mode(&mut self, new_mode: BlendMode) {
+        self.fg_state.blend_mode = new_mode;
+    }
+
     fn update_fg_direction(&mut self, new_direction: Direction) {
         self.fg_state.direction = new_direction;
     }
@@ -174,7 +321,7 @@ impl<'a, const N_LED: usize> Animatable<'a> for Animation<'a, N_LED> {
         self.fg_state.frames = Progression::new(frame_count);
     }
 
-    fn update_fg_mode(&mut self, new_mode: foreground::Mode) {
+    fn update_fg_mode(&mut self, new_mode: foreground::Mode<P>) {
         self.fg_state.updater = new_mode.get_updater();
     }
 
@@ -182,7 +329,7 @@ impl<'a, const N_LED: usize> Animatable<'a> for Animation<'a, N_LED> {
         self.fg_state.pixels_per_pixel_group = new_value;
     }
 
-    fn update_fg_rainbow(&mut self, new_rainbow: &'a [RGB8], rainbow_dir: RainbowDir) {
+    fn update_fg_rainbow(&mut self, new_rainbow: &'a [P], rainbow_dir: RainbowDir) {
         self.fg_state.rainbow = StatefulRainbow::new(new_rainbow, rainbow_dir);
     }
 
@@ -202,23 +349,19 @@ impl<'a, const N_LED: usize> Animatable<'a> for Animation<'a, N_LED> {
         self.triggers.frames = Progression::new(frame_count);
     }
 
-    fn update_trig_fade_rainbow(&mut self, new_rainbow: &'a [RGB8], rainbow_dir: RainbowDir) {
+    fn update_trig_fade_rainbow(&mut self, new_rainbow: &'a [P], rainbow_dir: RainbowDir) {
         self.triggers.fade_rainbow = StatefulRainbow::new(new_rainbow, rainbow_dir);
     }
 
-    fn update_trig_incremental_rainbow(
-        &mut self,
-        new_rainbow: &'a [RGB8],
-        rainbow_dir: RainbowDir,
-    ) {
+    fn update_trig_incremental_rainbow(&mut self, new_rainbow: &'a [P], rainbow_dir: RainbowDir) {
         self.triggers.incremental_rainbow = StatefulRainbow::new(new_rainbow, rainbow_dir);
     }
 }
 
-impl<'a, const N_LED: usize> Animation<'a, N_LED> {
-    pub fn new(parameters: AnimationParameters<'a>, frame_rate: Hertz) -> Self {
+impl<'a, const N_LED: usize, P: Pixel> Animation<'a, N_LED, P> {
+    pub fn new(parameters: AnimationParameters<'a, P>, frame_rate: Hertz) -> Self {
         let translation_array = default_translation_array(0);
-        let segment = [RGB8::default(); N_LED];
+        let segment = [P::default(); N_LED];
         let fg_state = foreground::Foreground::new(&parameters.fg, frame_rate);
         let bg_state = background::Background::new(&parameters.bg, frame_rate);
         let triggers = trigger::TriggerCollection::new(&parameters.trigger, frame_rate);
@@ -226,12 +369,26 @@ impl<'a, const N_LED: usize> Animation<'a, N_LED> {
         Animation {
             translation_array,
             segment,
+            layer: Layer::default(),
             fg_state,
             bg_state,
             triggers,
         }
     }
 
+    /// Creates an animation like `new()`, but returns an error instead of an animation that would
+    /// panic when it's drawn, such as one with no pixels or an empty rainbow.
+    pub fn try_new(
+        parameters: AnimationParameters<'a, P>,
+        frame_rate: Hertz,
+    ) -> Result<Self, Error> {
+        if N_LED == 0 {
+            return Err(Error::EmptySegment);
+        }
+        parameters.validate()?;
+        Ok(Self::new(parameters, frame_rate))
+    }
+
     // universal settings functions: apply to all animation types - bg, fg, and triggers:
 
     pub fn set_translation_array(mut self, new_array: [usize; N_LED]) -> Self {
@@ -239,8 +396,50 @@ impl<'a, const N_LED: usize> Animation<'a, N_LED> {
         self
     }
 
+    pub fn set_layer(mut self, new_layer: Layer) -> Self {
+        self.update_layer(new_layer);
+        self
+    }
+
+    /// Sets the number of leds in each row of a matrix, which the 2D modes use to draw the
+    /// segment row by row from the top left. A width of 0 draws the segment as a single row.
+    pub fn set_width(mut self, width: usize) -> Self {
+        self.update_width(width);
+        self
+    }
+
+    pub fn update_width(&mut self, width: usize) {
+        self.bg_state.width = width;
+        self.fg_state.width = width;
+        self.triggers.width = width;
+    }
+
+    /// Gives each pixel of the segment a position in space for the spatial modes.
+    pub fn set_positions(mut self, positions: PositionMap<'a>) -> Self {
+        self.update_positions(positions);
+        self
+    }
+
+    pub fn update_positions(&mut self, positions: PositionMap<'a>) {
+        self.bg_state.positions = Some(positions);
+        self.fg_state.positions = Some(positions);
+        self.triggers.positions = Some(positions);
+    }
+
+    /// Lays the segment across a matrix row by row, setting both the width used by the 2D modes
+    /// and the translation array, so the 2D modes are drawn the right way up on the LogicalStrip.
+    pub fn set_layout(self, layout: &impl Grid) -> Self {
+        self.set_width(layout.width())
+            .set_translation_array(layout.translation_array(Order::Rows))
+    }
+
     // bg settings functions - for setting the bg animation parameters:
 
+    pub fn set_bg_blend_mode(mut self, new_mode: BlendMode) -> Self {
+        self.update_bg_blend_mode(new_mode);
+        self
+    }
+
     pub fn set_bg_direction(mut self, new_direction: Direction) -> Self {
         self.update_bg_direction(new_direction);
         self
@@ -251,12 +450,12 @@ impl<'a, const N_LED: usize> Animation<'a, N_LED> {
         self
     }
 
-    pub fn set_bg_mode(mut self, new_mode: background::Mode) -> Self {
+    pub fn set_bg_mode(mut self, new_mode: background::Mode<P>) -> Self {
         self.update_bg_mode(new_mode);
         self
     }
 
-    pub fn set_bg_rainbow(mut self, new_rainbow: &'a [RGB8], rainbow_dir: RainbowDir) -> Self {
+    pub fn set_bg_rainbow(mut self, new_rainbow: &'a [P], rainbow_dir: RainbowDir) -> Self {
         self.update_bg_rainbow(new_rainbow, rainbow_dir);
         self
     }
@@ -268,6 +467,11 @@ impl<'a, const N_LED: usize> Animation<'a, N_LED> {
 
     // fg settings functions - for setting the fg animation parameters:
 
+    pub fn set_fg_blend_mode(mut self, new_mode: BlendMode) -> Self {
+        self.update_fg_blend_mode(new_mode);
+        self
+    }
+
     pub fn set_fg_direction(mut self, new_direction: Direction) -> Self {
         self.update_fg_direction(new_direction);
         self
@@ -278,12 +482,12 @@ impl<'a, const N_LED: usize> Animation<'a, N_LED> {
         self
     }
 
-    pub fn set_fg_mode(mut self, new_mode: foreground::Mode) -> Self {
+    pub fn set_fg_mode(mut self, new_mode: foreground::Mode<P>) -> Self {
         self.update_fg_mode(new_mode);
         self
     }
 
-    pub fn set_fg_rainbow(mut self, new_rainbow: &'a [RGB8], rainbow_dir: RainbowDir) -> Self {
+    pub fn set_fg_rainbow(mut self, new_rainbow: &'a [P], rainbow_dir: RainbowDir) -> Self {
         self.update_fg_rainbow(new_rainbow, rainbow_dir);
         self
     }
@@ -310,21 +514,40 @@ impl<'a, const N_LED: usize> Animation<'a, N_LED> {
         self
     }
 
-    pub fn set_trig_fade_rainbow(
-        mut self,
-        new_rainbow: &'a [RGB8],
-        rainbow_dir: RainbowDir,
-    ) -> Self {
+    pub fn set_trig_fade_rainbow(mut self, new_rainbow: &'a [P], rainbow_dir: RainbowDir) -> Self {
         self.update_trig_fade_rainbow(new_rainbow, rainbow_dir);
         self
     }
 
     pub fn set_trig_incremental_rainbow(
         mut self,
-        new_rainbow: &'a [RGB8],
+        new_rainbow: &'a [P],
         rainbow_dir: RainbowDir,
     ) -> Self {
         self.update_trig_incremental_rainbow(new_rainbow, rainbow_dir);
         self
     }
 }
+
+/// Checks that every rainbow has colors, and that repeating the background rainbow
+/// `bg_subdivisions` times leaves each of its colors at least one offset. The other rainbows are
+/// stepped through a color at a time, so they can be any length.
+fn check_rainbows(
+    bg_len: usize,
+    bg_subdivisions: usize,
+    fg_len: usize,
+    trigger_len: usize,
+) -> Result<(), Error> {
+    let lengths = [
+        (bg_len, AnimationType::Background),
+        (fg_len, AnimationType::Foreground),
+        (trigger_len, AnimationType::Trigger),
+    ];
+    if let Some(&(_, a_type)) = lengths.iter().find(|(len, _)| *len == 0) {
+        return Err(Error::EmptyRainbow(a_type));
+    }
+    if bg_len.saturating_mul(bg_subdivisions.max(1)) > MAX_OFFSET as usize {
+        return Err(Error::TooManyRainbowColors(AnimationType::Background));
+    }
+    Ok(())
+}
@@ -1,21 +1,32 @@
-use crate::animations::{Direction, MAX_OFFSET, RainbowDir};
-use crate::colors::{color_lerp, Rainbow};
+use crate::animations::{Direction, RainbowDir, MAX_OFFSET};
+use crate::colors::{color_lerp, BlendMode, ManipulatableColor, Pixel, Rainbow};
+use crate::layout::positions::{PositionMap, Space};
 use crate::utility::{
-    self, convert_ns_to_frames, get_random_offset, FadeRainbow, MarchingRainbow,
-    MarchingRainbowMut, Progression, StatefulRainbow,
+    self, convert_ns_to_frames, cosine, get_random_offset, sine, FadeRainbow, MarchingRainbow,
+    MarchingRainbowMut, Plane, Progression, StatefulRainbow,
 };
 use embedded_time::rate::Hertz;
 use rgb::RGB8;
-use smart_leds::colors::*;
-type BgUpdater = fn(&mut Background, &mut [RGB8]);
+
+pub type BgUpdater<P> = fn(&mut Background<P>, &mut [P]);
 
 /// Background Modes are rendered onto the animation LEDs first before any Foreground or Trigger
-/// animations. The other types of animation will overwrite any pixel data from the background that
-/// is effected by their animation.
-pub enum Mode {
+/// animations. The background is blended over an unlit segment using its `BlendMode`, and the other
+/// types of animation are blended over any pixel data from the background that is effected by
+/// their animation.
+#[derive(Copy, Clone)]
+pub enum Mode<P = RGB8> {
     /// This turns off all the leds in the animation for the background layer.
     NoBackground,
 
+    /// This leaves the background unlit like `NoBackground`, but the LightingController will treat
+    /// any leds that are still unlit after the foreground and triggers are drawn as transparent, so
+    /// that animations below this one show through. This is useful for sparse animations such as
+    /// triggers only, which should be layered over another animation instead of painting it black.
+    /// Partially lit leds are still drawn, so an `Additive` or `Lighten` layer gives the smoothest
+    /// fades over the animations below.
+    Transparent,
+
     /// This shows a solid unchanging color on all the leds in the background. The color will be the
     /// first in the rainbow. You can step to other colors in the rainbow by external trigger,
     /// otherwise it does not change.
@@ -36,52 +47,83 @@ pub enum Mode {
     /// When externally triggered, it moves to a random offset.
     FillRainbowRotate,
 
+    /// This will fill a matrix with swirling blobs of rainbow colors, made by adding together waves
+    /// that run across, down and out from the center of the matrix. The waves move through one
+    /// cycle per `duration_ns`, in the direction set.
+    /// When externally triggered, it moves to a random offset.
+    Plasma,
+
+    /// This will fill a matrix with rings of rainbow colors around its center, with the whole
+    /// rainbow spread from the center to the corners. The rings move outward or inward over time
+    /// like `FillRainbowRotate`.
+    /// When externally triggered, it moves to a random offset.
+    RadialRainbow,
+
+    /// This will fill a matrix with a rainbow spread in straight stripes from one side to the
+    /// other, and turn the stripes around the center once per `duration_ns`. When stopped, the
+    /// rainbow runs from left to right.
+    /// When externally triggered, it moves to a random offset.
+    LinearGradient,
+
+    /// This will spread a rainbow along the axis of the animation's position map, from the pixel
+    /// farthest back along the axis to the farthest forward, and move it along the axis over time
+    /// like `FillRainbowRotate`. Without a position map, the axis runs across the matrix.
+    /// When externally triggered, it moves to a random offset.
+    AxisGradient,
+
     /// This will use the function provided with the enum to do the update
-    Custom(BgUpdater),
+    Custom(BgUpdater<P>),
 }
 
-impl Mode {
-    pub fn get_updater(&self) -> Option<BgUpdater> {
+impl<P: Pixel> Mode<P> {
+    pub fn get_updater(&self) -> Option<BgUpdater<P>> {
         match *self {
             Mode::NoBackground => Some(no_background),
+            Mode::Transparent => Some(no_background),
             Mode::Solid => Some(solid),
             Mode::SolidFade => Some(solid_fade),
             Mode::FillRainbow => Some(fill_rainbow),
             Mode::FillRainbowRotate => Some(fill_rainbow_rotate),
+            Mode::Plasma => Some(plasma),
+            Mode::RadialRainbow => Some(radial_rainbow),
+            Mode::LinearGradient => Some(linear_gradient),
+            Mode::AxisGradient => Some(axis_gradient),
             Mode::Custom(u) => Some(u),
         }
     }
+
+    pub fn is_transparent(&self) -> bool {
+        matches!(self, Mode::Transparent)
+    }
 }
 
 /// Sets all LEDs to off
-fn no_background(bg: &mut Background, segment: &mut [RGB8]) {
-    bg.fill_solid(BLACK, segment);
+fn no_background<P: Pixel>(bg: &mut Background<P>, segment: &mut [P]) {
+    bg.fill_solid(P::default(), segment);
 }
 
 /// Sets all LEDs to the current rainbow color. Note that in this mode the color will only
 /// change when an external trigger of type `Background` is received.
-fn solid(bg: &mut Background, segment: &mut [RGB8]) {
+fn solid<P: Pixel>(bg: &mut Background<P>, segment: &mut [P]) {
     handle_solid_trigger(bg);
     bg.fill_solid(bg.current_rainbow_color(), segment);
 }
 
-fn solid_fade(bg: &mut Background, segment: &mut [RGB8]) {
+fn solid_fade<P: Pixel>(bg: &mut Background<P>, segment: &mut [P]) {
     handle_solid_trigger(bg);
-    for led in segment {
-        *led = bg.calculate_fade_color();
-    }
-    if bg.frames.get_current() == (bg.frames.total - 1) {
+    bg.fill_solid(bg.calculate_fade_color(), segment);
+    if bg.frames.get_current() + 1 == bg.frames.total {
         bg.rainbow.increment();
     }
 }
 
 /// Fills the rainbow based on whatever value the offset is currently set to:
-fn fill_rainbow(bg: &mut Background, segment: &mut [RGB8]) {
+fn fill_rainbow<P: Pixel>(bg: &mut Background<P>, segment: &mut [P]) {
     handle_rainbow_trigger(bg);
     bg.fill_rainbow(bg.offset, segment);
 }
 
-fn fill_rainbow_rotate(bg: &mut Background, segment: &mut [RGB8]) {
+fn fill_rainbow_rotate<P: Pixel>(bg: &mut Background<P>, segment: &mut [P]) {
     handle_rainbow_trigger(bg);
 
     // This mode will take the value that the offset is set to and then adjust based on the
@@ -92,8 +134,95 @@ fn fill_rainbow_rotate(bg: &mut Background, segment: &mut [RGB8]) {
     bg.fill_rainbow(color_start_offset, segment);
 }
 
+/// Adds up four waves, across, down, diagonally, and outward from the center, and uses their sum
+/// to pick the color of each pixel from the rainbow.
+fn plasma<P: Pixel>(bg: &mut Background<P>, segment: &mut [P]) {
+    handle_rainbow_trigger(bg);
+
+    let plane = Plane::new(bg.width, segment.len());
+    let radius = plane.radius();
+    let phase = utility::shift_offset(0, bg.frames, bg.direction);
+    // Each wave spans its dimension of the matrix once, and the phase moves them through a cycle:
+    let wave = |position: usize, length: usize| (position * MAX_OFFSET as usize / length) as u16;
+    let max_sum = 4 * i16::MAX as i64;
+
+    for (index, led) in segment.iter_mut().enumerate() {
+        let (x, y) = plane.position(index);
+        let (dx, dy) = plane.from_center(index);
+        let distance = (dx * dx + dy * dy) as u32;
+
+        let sum = sine(wave(x, plane.width).wrapping_add(phase))
+            + sine(wave(y, plane.height).wrapping_sub(phase))
+            + sine(wave(x + y, plane.width + plane.height).wrapping_add(phase.wrapping_mul(2)))
+            + cosine(wave(distance.isqrt() as usize, radius as usize).wrapping_sub(phase));
+
+        let position = (sum as i64 + max_sum) * MAX_OFFSET as i64 / (2 * max_sum);
+        let color = bg.rainbow_color_at(position as usize + bg.offset as usize);
+        *led = led.blend_with(color, bg.blend_mode);
+    }
+}
+
+/// Picks the color of each pixel from the rainbow by its distance from the center of the matrix.
+fn radial_rainbow<P: Pixel>(bg: &mut Background<P>, segment: &mut [P]) {
+    handle_rainbow_trigger(bg);
+
+    let max_offset = MAX_OFFSET as usize;
+    let plane = Plane::new(bg.width, segment.len());
+    let radius = plane.radius() as usize;
+    let start_offset = utility::shift_offset(bg.offset, bg.frames, bg.direction) as usize;
+
+    for (index, led) in segment.iter_mut().enumerate() {
+        let (dx, dy) = plane.from_center(index);
+        let distance = ((dx * dx + dy * dy) as u32).isqrt() as usize;
+        // Moving the start of the rainbow forward makes the rings move outward:
+        let position = distance * max_offset / (radius + 1);
+        let color = bg.rainbow_color_at(position + max_offset - start_offset);
+        *led = led.blend_with(color, bg.blend_mode);
+    }
+}
+
+/// Picks the color of each pixel from the rainbow by how far along the pixel is in the direction
+/// of the current angle, which turns once over the duration.
+fn linear_gradient<P: Pixel>(bg: &mut Background<P>, segment: &mut [P]) {
+    handle_rainbow_trigger(bg);
+
+    let plane = Plane::new(bg.width, segment.len());
+    let radius = plane.radius() as i64;
+    let angle = utility::shift_offset(0, bg.frames, bg.direction);
+    let (cos, sin) = (cosine(angle) as i64, sine(angle) as i64);
+
+    for (index, led) in segment.iter_mut().enumerate() {
+        let (dx, dy) = plane.from_center(index);
+        // The distance along the gradient, from -radius to radius:
+        let along = (dx as i64 * cos + dy as i64 * sin) / i16::MAX as i64;
+        let position = (along + radius).clamp(0, 2 * radius) * MAX_OFFSET as i64 / (2 * radius + 1);
+        let color =
+            bg.rainbow_color_at(position as usize + MAX_OFFSET as usize - bg.offset as usize);
+        *led = led.blend_with(color, bg.blend_mode);
+    }
+}
+
+/// Picks the color of each pixel from the rainbow by how far along the axis it is in space.
+fn axis_gradient<P: Pixel>(bg: &mut Background<P>, segment: &mut [P]) {
+    handle_rainbow_trigger(bg);
+
+    let max_offset = MAX_OFFSET as i64;
+    let space = Space::new(bg.positions, bg.width, segment.len());
+    let axis = space.axis();
+    let (low, high) = space.extent();
+    let start_offset = utility::shift_offset(bg.offset, bg.frames, bg.direction) as i64;
+
+    for (index, led) in segment.iter_mut().enumerate() {
+        if let Some(point) = space.point(index) {
+            let position = (point.along(axis) - low) * max_offset / (high - low + 1);
+            let color = bg.rainbow_color_at((position + max_offset - start_offset) as usize);
+            *led = led.blend_with(color, bg.blend_mode);
+        }
+    }
+}
+
 /// Sets the background to a random offset then resets the trigger
-fn handle_rainbow_trigger(bg: &mut Background) {
+fn handle_rainbow_trigger<P: Pixel>(bg: &mut Background<P>) {
     if bg.has_been_triggered {
         bg.offset = get_random_offset();
         bg.reset_trigger();
@@ -101,7 +230,7 @@ fn handle_rainbow_trigger(bg: &mut Background) {
 }
 
 /// Advances the rainbow which concurrently resets the frame count
-fn handle_solid_trigger(bg: &mut Background) {
+fn handle_solid_trigger<P: Pixel>(bg: &mut Background<P>) {
     if bg.has_been_triggered {
         bg.advance_rainbow_color();
         bg.reset_trigger();
@@ -110,30 +239,38 @@ fn handle_solid_trigger(bg: &mut Background) {
 
 /// This contains all the information necessary to set up and run a background animation. All
 /// aspects of the animation can be derived from these parameters.
-pub struct Parameters<'a> {
-    pub mode: Mode,
-    pub rainbow: Rainbow<'a>,
+#[derive(Copy, Clone)]
+pub struct Parameters<'a, P = RGB8> {
+    pub mode: Mode<P>,
+    pub rainbow: Rainbow<'a, P>,
     pub direction: Direction,
     pub rainbow_dir: RainbowDir,
     pub duration_ns: u64,
     pub subdivisions: usize,
+    pub blend_mode: BlendMode,
 }
 
-pub struct Background<'a> {
+pub struct Background<'a, P = RGB8> {
     // state
     pub offset: u16,
     pub frames: Progression,
     pub has_been_triggered: bool,
 
     // parameters
-    pub rainbow: StatefulRainbow<'a>,
+    pub rainbow: StatefulRainbow<'a, P>,
     pub direction: Direction,
     pub subdivisions: usize,
-    pub updater: Option<BgUpdater>,
+    /// The number of leds in each row of a matrix for the 2D modes, or 0 for a single row.
+    pub width: usize,
+    /// Where each pixel is in space for the spatial modes.
+    pub positions: Option<PositionMap<'a>>,
+    pub is_transparent: bool,
+    pub blend_mode: BlendMode,
+    pub updater: Option<BgUpdater<P>>,
 }
 
-impl<'a> Background<'a> {
-    pub fn new(init: &Parameters<'a>, frame_rate: Hertz) -> Self {
+impl<'a, P: Pixel> Background<'a, P> {
+    pub fn new(init: &Parameters<'a, P>, frame_rate: Hertz) -> Self {
         let frame_count = convert_ns_to_frames(init.duration_ns, frame_rate);
 
         Self {
@@ -143,11 +280,15 @@ impl<'a> Background<'a> {
             rainbow: StatefulRainbow::new(init.rainbow, init.rainbow_dir),
             direction: init.direction,
             subdivisions: init.subdivisions,
+            width: 0,
+            positions: None,
+            is_transparent: init.mode.is_transparent(),
+            blend_mode: init.blend_mode,
             updater: init.mode.get_updater(),
         }
     }
 
-    pub fn update(&mut self, segment: &mut [RGB8]) {
+    pub fn update(&mut self, segment: &mut [P]) {
         if let Some(f) = self.updater {
             f(self, segment);
         }
@@ -158,64 +299,83 @@ impl<'a> Background<'a> {
         self.has_been_triggered = false;
     }
 
-    fn fill_solid(&mut self, color: RGB8, segment: &mut [RGB8]) {
-        segment.iter_mut().for_each(|led| *led = color);
+    /// Moves the animation to where it would be `frame` frames after it started, including how
+    /// many times the rainbow has advanced.
+    pub fn seek(&mut self, frame: usize) {
+        self.frames.set_current(frame);
+        self.rainbow
+            .position
+            .set_current(frame / self.frames.total.max(1));
+    }
+
+    fn fill_solid(&mut self, color: P, segment: &mut [P]) {
+        let blend_mode = self.blend_mode;
+        segment
+            .iter_mut()
+            .for_each(|led| *led = led.blend_with(color, blend_mode));
     }
 
-    fn fill_rainbow(&mut self, start_offset: u16, segment: &mut [RGB8]) {
+    fn fill_rainbow(&mut self, start_offset: u16, segment: &mut [P]) {
         let start_offset = start_offset as usize;
         let max_offset = MAX_OFFSET as usize;
         let led_count = segment.len();
-        let rainbow = &self.rainbow.backer;
         // Generate the LED Position. The LED positions are distributed evenly over
         // the entire range from 0..MAX_OFFSET, to increase the effective supersampling resolution of
         // the animation.
         let get_position = |led_index| led_index * (max_offset / led_count);
 
+        for (led_index, led) in segment.iter_mut().enumerate() {
+            // move the led position by offset rather than the rainbow itself
+            let shifted_position = get_position(led_index) + max_offset - start_offset;
+            let color = self.rainbow_color_at(shifted_position);
+            *led = led.blend_with(color, self.blend_mode);
+        }
+    }
+
+    /// Returns the color at `position` along the rainbow, blending between the rainbow's colors,
+    /// where the rainbow is repeated `subdivisions` times from 0 to MAX_OFFSET. Positions past
+    /// MAX_OFFSET wrap around. An empty rainbow is unlit, and when the rainbow is repeated more
+    /// times than there are offsets, each offset gets the next color.
+    fn rainbow_color_at(&self, position: usize) -> P {
+        let max_offset = MAX_OFFSET as usize;
+        let rainbow = &self.rainbow.backer;
         let rainbow_length = rainbow.len();
+        if rainbow.is_empty() {
+            return P::default();
+        }
 
         // We will need to know the distance between each color of the rainbow, and this will need
         // to take into account that the rainbow will be repeated by the number of subdivisions in
         // the bg parameters:
-        let total_num_rainbow_colors = rainbow_length * 1.max(self.subdivisions);
-        let distance_between_colors = max_offset / total_num_rainbow_colors;
-
-        let led_iterator = segment
-            .iter_mut()
-            .enumerate()
-            .map(|(i, c)| (get_position(i), c));
+        let total_num_rainbow_colors = rainbow_length.saturating_mul(1.max(self.subdivisions));
+        let distance_between_colors = (max_offset / total_num_rainbow_colors).max(1);
 
-        for (led_position, led) in led_iterator {
-            // move the led position by offset rather than the rainbow itself
-            let shifted_position = (led_position as usize + max_offset - start_offset) % max_offset;
-
-            // all positions from one color to just before the next map to a rainbow bucket index
-            let rainbow_bucket = shifted_position / distance_between_colors;
-            let bucket_start = rainbow_bucket * distance_between_colors;
+        let position = position % max_offset;
 
-            let factor = shifted_position - bucket_start;
+        // all positions from one color to just before the next map to a rainbow bucket index
+        let rainbow_bucket = position / distance_between_colors;
+        let bucket_start = rainbow_bucket * distance_between_colors;
 
-            let start_color_index = rainbow_bucket % rainbow_length;
-            let start_color = rainbow[start_color_index];
+        let factor = position - bucket_start;
 
-            let end_color_index = (rainbow_bucket + 1) % rainbow_length;
-            let end_color = rainbow[end_color_index];
+        let start_color_index = rainbow_bucket % rainbow_length;
+        let start_color = rainbow[start_color_index];
 
-            let mid_color = color_lerp(
-                factor as i32,
-                0,
-                distance_between_colors as i32,
-                start_color,
-                end_color,
-            );
+        let end_color_index = (rainbow_bucket + 1) % rainbow_length;
+        let end_color = rainbow[end_color_index];
 
-            *led = mid_color;
-        }
+        color_lerp(
+            factor as i32,
+            0,
+            distance_between_colors as i32,
+            start_color,
+            end_color,
+        )
     }
 }
 
-impl<'a> MarchingRainbow for Background<'a> {
-    fn rainbow(&self) -> &StatefulRainbow<'_> {
+impl<'a, P: Pixel> MarchingRainbow<P> for Background<'a, P> {
+    fn rainbow(&self) -> &StatefulRainbow<'_, P> {
         &self.rainbow
     }
     fn frames(&self) -> &Progression {
@@ -223,8 +383,8 @@ impl<'a> MarchingRainbow for Background<'a> {
     }
 }
 
-impl<'a> MarchingRainbowMut<'a> for Background<'a> {
-    fn rainbow_mut(&mut self) -> &mut StatefulRainbow<'a> {
+impl<'a, P: Pixel> MarchingRainbowMut<'a, P> for Background<'a, P> {
+    fn rainbow_mut(&mut self) -> &mut StatefulRainbow<'a, P> {
         &mut self.rainbow
     }
     fn frames_mut(&mut self) -> &mut Progression {
@@ -232,8 +392,8 @@ impl<'a> MarchingRainbowMut<'a> for Background<'a> {
     }
 }
 
-impl<'a> FadeRainbow for Background<'a> {
-    fn rainbow(&self) -> &StatefulRainbow<'_> {
+impl<'a, P: Pixel> FadeRainbow<P> for Background<'a, P> {
+    fn rainbow(&self) -> &StatefulRainbow<'_, P> {
         &self.rainbow
     }
     fn frames(&self) -> &Progression {
@@ -0,0 +1,167 @@
+//! An ExternalAnimation shows colors that are sent from outside the controller, such as DMX data
+//! from a lighting console or frames streamed from a PC, so that they can be composited with the
+//! local animations like any other layer.
+//!
+//! Colors are written into the animation's segment with `Animatable::segment_mut()`, and are held
+//! until new colors arrive. Each time new colors are written, `Animatable::mark_received()` should
+//! be called. If it isn't called for longer than the timeout, the segment is cleared so a source
+//! that has gone away doesn't leave the strip frozen on its last frame.
+
+use crate::animations::{
+    background, foreground, trigger, Animatable, AnimationType, Direction, Layer, RainbowDir,
+};
+use crate::colors::{BlendMode, Pixel};
+use crate::utility::{convert_ns_to_frames, default_translation_array};
+use embedded_time::rate::Hertz;
+use rgb::RGB8;
+
+pub struct ExternalAnimation<const N_LED: usize, P = RGB8> {
+    translation_array: [usize; N_LED],
+    segment: [P; N_LED],
+    layer: Layer,
+    is_transparent: bool,
+    timeout_frames: usize,
+    frames_since_data: usize,
+}
+
+impl<const N_LED: usize, P: Pixel> ExternalAnimation<N_LED, P> {
+    /// Creates an unlit external animation that never times out.
+    pub fn new() -> Self {
+        ExternalAnimation {
+            translation_array: default_translation_array(0),
+            segment: [P::default(); N_LED],
+            layer: Layer::default(),
+            is_transparent: false,
+            timeout_frames: 0,
+            // Nothing has been received yet:
+            frames_since_data: usize::MAX,
+        }
+    }
+
+    pub fn set_translation_array(mut self, new_array: [usize; N_LED]) -> Self {
+        self.update_translation_array(&new_array);
+        self
+    }
+
+    pub fn set_layer(mut self, new_layer: Layer) -> Self {
+        self.update_layer(new_layer);
+        self
+    }
+
+    /// Treats unlit leds as transparent, the same as `background::Mode::Transparent`, so the
+    /// animations below show through wherever the source sends black, and everywhere once the
+    /// source has timed out.
+    pub fn set_transparent(mut self, is_transparent: bool) -> Self {
+        self.update_transparent(is_transparent);
+        self
+    }
+
+    /// Clears the segment when no colors have been received for `timeout_ns`. A timeout of 0 holds
+    /// the last colors forever.
+    pub fn set_timeout_ns(mut self, timeout_ns: u64, frame_rate: Hertz) -> Self {
+        self.update_timeout_ns(timeout_ns, frame_rate);
+        self
+    }
+
+    pub fn update_transparent(&mut self, is_transparent: bool) {
+        self.is_transparent = is_transparent;
+    }
+
+    pub fn update_timeout_ns(&mut self, timeout_ns: u64, frame_rate: Hertz) {
+        self.timeout_frames = match timeout_ns {
+            0 => 0,
+            timeout_ns => convert_ns_to_frames(timeout_ns, frame_rate).max(1),
+        };
+    }
+
+    /// Returns true if colors have been received within the timeout, or ever if there's no timeout.
+    pub fn is_receiving(&self) -> bool {
+        match self.timeout_frames {
+            0 => self.frames_since_data != usize::MAX,
+            timeout_frames => self.frames_since_data < timeout_frames,
+        }
+    }
+}
+
+impl<const N_LED: usize, P: Pixel> Default for ExternalAnimation<N_LED, P> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Only the segment, translation array and layer mean anything to an external animation, so the
+/// rest of the settings are ignored.
+impl<'a, const N_LED: usize, P: Pixel> Animatable<'a, P> for ExternalAnimation<N_LED, P> {
+    fn update(&mut self) {
+        if self.timeout_frames == 0 || self.frames_since_data > self.timeout_frames {
+            return;
+        }
+        self.frames_since_data += 1;
+        if self.frames_since_data == self.timeout_frames {
+            self.segment.fill(P::default());
+        }
+    }
+
+    fn seek(&mut self, _frame: usize) {}
+
+    fn set_offset(&mut self, _a_type: AnimationType, _offset: u16) {}
+
+    fn trigger(&mut self, _params: &trigger::Parameters<P>, _frame_rate: Hertz) {}
+
+    fn segment(&self) -> &[P] {
+        &self.segment[..]
+    }
+
+    fn segment_mut(&mut self) -> &mut [P] {
+        &mut self.segment[..]
+    }
+
+    fn mark_received(&mut self) {
+        self.frames_since_data = 0;
+    }
+
+    fn translation_array(&self) -> &[usize] {
+        &self.translation_array[..]
+    }
+
+    fn layer(&self) -> Layer {
+        self.layer
+    }
+
+    fn is_transparent(&self) -> bool {
+        self.is_transparent
+    }
+
+    fn update_translation_array(&mut self, new_array: &[usize]) {
+        let source = new_array.iter().copied();
+        let dest = self.translation_array.iter_mut();
+        for (source, dest) in source.zip(dest) {
+            *dest = source;
+        }
+    }
+
+    fn update_layer(&mut self, new_layer: Layer) {
+        self.layer = new_layer;
+    }
+
+    fn update_bg_blend_mode(&mut self, _new_mode: BlendMode) {}
+    fn update_bg_direction(&mut self, _new_direction: Direction) {}
+    fn update_bg_duration_ns(&mut self, _new_time: u64, _frame_rate: Hertz) {}
+    fn update_bg_mode(&mut self, _new_mode: background::Mode<P>) {}
+    fn update_bg_rainbow(&mut self, _new_rainbow: &'a [P], _rainbow_dir: RainbowDir) {}
+    fn update_bg_subdivisions(&mut self, _new_value: usize) {}
+
+    fn update_fg_blend_mode(&mut self, _new_mode: BlendMode) {}
+    fn update_fg_direction(&mut self, _new_direction: Direction) {}
+    fn update_fg_duration_ns(&mut self, _new_time: u64, _frame_rate: Hertz) {}
+    fn update_fg_mode(&mut self, _new_mode: foreground::Mode<P>) {}
+    fn update_fg_pixels_per_pixel_group(&mut self, _new_value: usize) {}
+    fn update_fg_rainbow(&mut self, _new_rainbow: &'a [P], _rainbow_dir: RainbowDir) {}
+    fn update_fg_step_time_ns(&mut self, _new_time: u64, _frame_rate: Hertz) {}
+    fn update_fg_subdivisions(&mut self, _new_value: usize) {}
+
+    fn update_trig_duration_ns(&mut self, _new_time: u64, _frame_rate: Hertz) {}
+    fn update_trig_fade_rainbow(&mut self, _new_rainbow: &'a [P], _rainbow_dir: RainbowDir) {}
+    fn update_trig_incremental_rainbow(&mut self, _new_rainbow: &'a [P], _rainbow_dir: RainbowDir) {
+    }
+}
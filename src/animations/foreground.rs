@@ -1,21 +1,22 @@
+use crate::layout::positions::{PositionMap, Space};
 use crate::{
-    animations::{Direction, MAX_OFFSET, RainbowDir},
-    colors::Rainbow,
+    animations::{Direction, RainbowDir, MAX_OFFSET},
+    colors::{BlendMode, ManipulatableColor, Pixel, Rainbow},
     utility::{
-        convert_ns_to_frames, FadeRainbow, MarchingRainbow, MarchingRainbowMut, Progression,
-        StatefulRainbow,
+        convert_ns_to_frames, shift_offset, FadeRainbow, MarchingRainbow, MarchingRainbowMut,
+        Plane, Progression, StatefulRainbow,
     },
 };
 use embedded_time::rate::Hertz;
 use rgb::RGB8;
-use smart_leds::colors::BLACK;
 
-type FgUpdater = fn(&mut Foreground, &mut [RGB8]);
+pub type FgUpdater<P> = fn(&mut Foreground<P>, &mut [P]);
 
 /// Foreground modes are rendered second, and will animate over the background animation layer but
-/// below the trigger animations. Any trigger animations will overwrite the pixel data from the
-/// foreground that is effected by their animation.
-pub enum Mode {
+/// below the trigger animations. Foreground pixels are blended over the background using the
+/// foreground `BlendMode`, so a marquee can tint the background instead of replacing it.
+#[derive(Copy, Clone)]
+pub enum Mode<P = RGB8> {
     /// This is a mode that has no additional foreground animation over the background animation.
     NoForeground,
 
@@ -41,12 +42,24 @@ pub enum Mode {
     /// the offset value alone.
     VUMeter,
 
+    /// This will scroll bars `pixels_per_pixel_group` columns wide across a matrix, with gaps of
+    /// the same width between them, moving one matrix width per `duration_ns`. Each bar is the
+    /// next color of the rainbow, and the foreground trigger will advance the colors.
+    ScrollingBars,
+
+    /// This will sweep a plane through the animation's position map along its axis, lighting the
+    /// pixels it passes through in a color that fades through the rainbow. The plane crosses once
+    /// per `duration_ns`, and is `pixels_per_pixel_group` sixteenths of the way across thick.
+    /// When stopped, the plane stays where the offset puts it. Without a position map, the plane
+    /// sweeps across the matrix. The foreground trigger will advance to the next color.
+    PlaneSweep,
+
     /// This will use the function provided with the enum to do the update
-    Custom(FgUpdater),
+    Custom(FgUpdater<P>),
 }
 
-impl Mode {
-    pub fn get_updater(&self) -> Option<FgUpdater> {
+impl<P: Pixel> Mode<P> {
+    pub fn get_updater(&self) -> Option<FgUpdater<P>> {
         match *self {
             Mode::NoForeground => None,
             Mode::MarqueeSolid => Some(marquee_solid),
@@ -54,53 +67,103 @@ impl Mode {
             Mode::MarqueeFade => Some(marquee_fade),
             Mode::MarqueeFadeFixed => Some(marquee_fade_fixed),
             Mode::VUMeter => Some(vu_meter),
+            Mode::ScrollingBars => Some(scrolling_bars),
+            Mode::PlaneSweep => Some(plane_sweep),
             Mode::Custom(u) => Some(u),
         }
     }
 }
 
-fn marquee_solid(fg: &mut Foreground, segment: &mut [RGB8]) {
+fn marquee_solid<P: Pixel>(fg: &mut Foreground<P>, segment: &mut [P]) {
     handle_marquee_trigger(fg);
     fg.increment_marquee_step();
     fg.fill_marquee(fg.current_fade_color(), segment);
 }
 
-fn marquee_solid_fixed(fg: &mut Foreground, segment: &mut [RGB8]) {
+fn marquee_solid_fixed<P: Pixel>(fg: &mut Foreground<P>, segment: &mut [P]) {
     handle_marquee_trigger(fg);
     set_marquee_toggle(fg, segment.len());
     fg.fill_marquee(fg.current_fade_color(), segment);
 }
 
-fn marquee_fade(fg: &mut Foreground, segment: &mut [RGB8]) {
+fn marquee_fade<P: Pixel>(fg: &mut Foreground<P>, segment: &mut [P]) {
     handle_marquee_trigger(fg);
     fg.increment_marquee_step();
     let color = fg.calculate_fade_color();
     fg.fill_marquee(color, segment);
 }
 
-fn marquee_fade_fixed(fg: &mut Foreground, segment: &mut [RGB8]) {
+fn marquee_fade_fixed<P: Pixel>(fg: &mut Foreground<P>, segment: &mut [P]) {
     handle_marquee_trigger(fg);
     set_marquee_toggle(fg, segment.len());
     let color = fg.calculate_fade_color();
     fg.fill_marquee(color, segment);
 }
 
-fn vu_meter(fg: &mut Foreground, segment: &mut [RGB8]) {
+fn vu_meter<P: Pixel>(fg: &mut Foreground<P>, segment: &mut [P]) {
     fg.current_fade_color();
     let led_count = segment.len();
-    let last_on_led = fg.offset as usize / led_count;
+    let last_on_led = (fg.offset as usize / led_count.max(1)).min(led_count);
+    // Written directly, since blending black over the background does nothing in most modes:
     for led in &mut segment[last_on_led..] {
-        *led = BLACK;
+        *led = P::default();
+    }
+}
+
+fn scrolling_bars<P: Pixel>(fg: &mut Foreground<P>, segment: &mut [P]) {
+    handle_marquee_trigger(fg);
+
+    let plane = Plane::new(fg.width, segment.len());
+    let bar_width = fg.pixels_per_pixel_group.max(1);
+    let offset = shift_offset(fg.offset, fg.frames, fg.direction) as usize;
+    let scrolled_columns = offset * plane.width / (MAX_OFFSET as usize + 1);
+    let rainbow = &fg.rainbow;
+    if rainbow.backer.is_empty() {
+        return;
+    }
+
+    for (index, led) in segment.iter_mut().enumerate() {
+        let (x, _) = plane.position(index);
+        let column = (x + plane.width - scrolled_columns) % plane.width;
+        let bar = column / bar_width;
+        // every other bar is a gap:
+        if bar.is_multiple_of(2) {
+            let color_index = (bar / 2 + rainbow.position.get_current()) % rainbow.backer.len();
+            *led = led.blend_with(rainbow.backer[color_index], fg.blend_mode);
+        }
     }
 }
 
-fn set_marquee_toggle(fg: &mut Foreground, led_count: usize) {
-    let pip_distance = (MAX_OFFSET as usize / led_count) * fg.pixels_per_pixel_group.max(1);
+fn plane_sweep<P: Pixel>(fg: &mut Foreground<P>, segment: &mut [P]) {
+    handle_marquee_trigger(fg);
+
+    let space = Space::new(fg.positions, fg.width, segment.len());
+    let axis = space.axis();
+    let (low, high) = space.extent();
+    let span = high - low;
+    let half_thickness = (span * fg.pixels_per_pixel_group.clamp(1, 32) as i64 / 32).max(1);
+    let offset = shift_offset(fg.offset, fg.frames, fg.direction) as i64;
+    let plane = low + offset * span / MAX_OFFSET as i64;
+    let color = fg.calculate_fade_color();
+
+    for (index, led) in segment.iter_mut().enumerate() {
+        let is_in_plane = space
+            .point(index)
+            .is_some_and(|point| (point.along(axis) - plane).abs() < half_thickness);
+        if is_in_plane {
+            *led = led.blend_with(color, fg.blend_mode);
+        }
+    }
+}
+
+fn set_marquee_toggle<P: Pixel>(fg: &mut Foreground<P>, led_count: usize) {
+    let pip_distance =
+        (MAX_OFFSET as usize / led_count.max(1)).saturating_mul(fg.pixels_per_pixel_group.max(1));
     let led_bucket = fg.offset as usize / pip_distance.max(1);
-    fg.marquee_position_toggle = led_bucket % 2 == 0;
+    fg.marquee_position_toggle = led_bucket.is_multiple_of(2);
 }
 
-fn handle_marquee_trigger(fg: &mut Foreground) {
+fn handle_marquee_trigger<P: Pixel>(fg: &mut Foreground<P>) {
     if fg.has_been_triggered {
         fg.advance_rainbow_color();
         fg.reset_trigger();
@@ -109,19 +172,21 @@ fn handle_marquee_trigger(fg: &mut Foreground) {
 
 /// This contains all the information necessary to set up and run a foreground animation. All
 /// aspects of the animation can be derived from these parameters.
-pub struct Parameters<'a> {
-    pub mode: Mode,
-    pub rainbow: Rainbow<'a>,
+#[derive(Copy, Clone)]
+pub struct Parameters<'a, P = RGB8> {
+    pub mode: Mode<P>,
+    pub rainbow: Rainbow<'a, P>,
     pub direction: Direction,
     pub rainbow_dir: RainbowDir,
     pub duration_ns: u64,
     pub step_time_ns: u64,
     pub subdivisions: usize,
     pub pixels_per_pixel_group: usize,
+    pub blend_mode: BlendMode,
 }
 
 #[allow(dead_code)]
-pub struct Foreground<'a> {
+pub struct Foreground<'a, P = RGB8> {
     // state
     pub offset: u16,
     pub frames: Progression,
@@ -130,15 +195,20 @@ pub struct Foreground<'a> {
     pub has_been_triggered: bool,
 
     // parameters
-    pub rainbow: StatefulRainbow<'a>,
+    pub rainbow: StatefulRainbow<'a, P>,
     pub direction: Direction,
     pub subdivisions: usize,
     pub pixels_per_pixel_group: usize,
-    pub updater: Option<FgUpdater>,
+    /// The number of leds in each row of a matrix for the 2D modes, or 0 for a single row.
+    pub width: usize,
+    /// Where each pixel is in space for the spatial modes.
+    pub positions: Option<PositionMap<'a>>,
+    pub blend_mode: BlendMode,
+    pub updater: Option<FgUpdater<P>>,
 }
 
-impl<'a> Foreground<'a> {
-    pub fn new(init: &Parameters<'a>, frame_rate: Hertz) -> Self {
+impl<'a, P: Pixel> Foreground<'a, P> {
+    pub fn new(init: &Parameters<'a, P>, frame_rate: Hertz) -> Self {
         let frame_count = convert_ns_to_frames(init.duration_ns, frame_rate);
         let step_frame_count = convert_ns_to_frames(init.step_time_ns, frame_rate);
 
@@ -152,11 +222,14 @@ impl<'a> Foreground<'a> {
             direction: init.direction,
             subdivisions: init.subdivisions,
             pixels_per_pixel_group: init.pixels_per_pixel_group,
+            width: 0,
+            positions: None,
+            blend_mode: init.blend_mode,
             updater: init.mode.get_updater(),
         }
     }
 
-    pub fn update(&mut self, segment: &mut [RGB8]) {
+    pub fn update(&mut self, segment: &mut [P]) {
         if let Some(f) = self.updater {
             f(self, segment);
         }
@@ -170,6 +243,17 @@ impl<'a> Foreground<'a> {
         self.has_been_triggered = false;
     }
 
+    /// Moves the animation to where it would be `frame` frames after it started, including how
+    /// many times the rainbow and the marquee have advanced.
+    pub fn seek(&mut self, frame: usize) {
+        self.frames.set_current(frame);
+        self.rainbow
+            .position
+            .set_current(frame / self.frames.total.max(1));
+        self.step_frames.set_current(frame);
+        self.marquee_position_toggle = (frame / self.step_frames.total.max(1)) % 2 == 1;
+    }
+
     fn increment_marquee_step(&mut self) {
         // Increment and check to see if the color rolls over:
         let did_roll = self.step_frames.checked_increment();
@@ -179,26 +263,26 @@ impl<'a> Foreground<'a> {
         }
     }
 
-    fn fill_marquee(&mut self, color: RGB8, segment: &mut [RGB8]) {
+    fn fill_marquee(&mut self, color: P, segment: &mut [P]) {
         for (led_index, led) in segment.iter_mut().enumerate() {
             // every time the index is evenly divisible by the number of subpixels, toggle the state
             // that the pixels should be set to:
-            let px_per_pip = self.pixels_per_pixel_group;
+            let px_per_pip = self.pixels_per_pixel_group.max(1);
             let toggle = self.marquee_position_toggle;
-            let subpip_number = led_index % (px_per_pip * 2);
+            let subpip_number = led_index % px_per_pip.saturating_mul(2);
 
             if subpip_number < px_per_pip && toggle {
-                *led = color;
+                *led = led.blend_with(color, self.blend_mode);
             }
             if subpip_number >= px_per_pip && !toggle {
-                *led = color;
+                *led = led.blend_with(color, self.blend_mode);
             }
         }
     }
 }
 
-impl<'a> MarchingRainbow for Foreground<'a> {
-    fn rainbow(&self) -> &StatefulRainbow<'_> {
+impl<'a, P: Pixel> MarchingRainbow<P> for Foreground<'a, P> {
+    fn rainbow(&self) -> &StatefulRainbow<'_, P> {
         &self.rainbow
     }
     fn frames(&self) -> &Progression {
@@ -206,8 +290,8 @@ impl<'a> MarchingRainbow for Foreground<'a> {
     }
 }
 
-impl<'a> MarchingRainbowMut<'a> for Foreground<'a> {
-    fn rainbow_mut(&mut self) -> &mut StatefulRainbow<'a> {
+impl<'a, P: Pixel> MarchingRainbowMut<'a, P> for Foreground<'a, P> {
+    fn rainbow_mut(&mut self) -> &mut StatefulRainbow<'a, P> {
         &mut self.rainbow
     }
     fn frames_mut(&mut self) -> &mut Progression {
@@ -215,8 +299,8 @@ impl<'a> MarchingRainbowMut<'a> for Foreground<'a> {
     }
 }
 
-impl<'a> FadeRainbow for Foreground<'a> {
-    fn rainbow(&self) -> &StatefulRainbow<'_> {
+impl<'a, P: Pixel> FadeRainbow<P> for Foreground<'a, P> {
+    fn rainbow(&self) -> &StatefulRainbow<'_, P> {
         &self.rainbow
     }
     fn frames(&self) -> &Progression {
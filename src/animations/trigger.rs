@@ -1,24 +1,25 @@
 use crate::animations::{Direction, RainbowDir, MAX_OFFSET};
 use crate::colors;
-use crate::colors::ManipulatableColor;
+use crate::colors::{BlendMode, ManipulatableColor, Pixel};
+use crate::error::Error;
+use crate::layout::positions::{PositionMap, Space};
 use crate::utility::{
     convert_ns_to_frames, get_random_offset, shift_offset, FadeRainbow, MarchingRainbow,
-    MarchingRainbowMut, Progression, StatefulRainbow, TimedRainbows,
+    MarchingRainbowMut, Plane, Progression, StatefulRainbow, TimedRainbows,
 };
-use arrayvec::ArrayVec;
 use embedded_time::rate::Hertz;
 use rgb::RGB8;
 
-pub type TriggerInit = fn(&mut Trigger, &mut TimedRainbows);
-pub type TriggerUpdater = fn(&mut Trigger, &mut [RGB8]);
-pub type TriggerBehavior = (Option<TriggerInit>, Option<TriggerUpdater>);
+pub type TriggerInit<P> = fn(&mut Trigger<P>, &mut TimedRainbows<P>);
+pub type TriggerUpdater<P> = fn(&mut Trigger<P>, &mut [P]);
+pub type TriggerBehavior<P> = (Option<TriggerInit<P>>, Option<TriggerUpdater<P>>);
 
 /// These are the types of triggered animation effects that are possible with an animation. They can
 /// be mixed and matched at any time over any combination of foreground and background animations.
-/// The trigger animation colors will override any foreground or background pixel data on the pixels
-/// it effects.
+/// The trigger animation colors are blended over any foreground or background pixel data on the
+/// pixels it effects using the trigger's `BlendMode`.
 #[derive(Copy, Clone)]
-pub enum Mode {
+pub enum Mode<P = RGB8> {
     /// This is a fallback value that doesn't have any trigger effect.
     NoTrigger,
 
@@ -76,11 +77,43 @@ pub enum Mode {
     /// Each flash will be a new color in the order of the rainbow.
     FlashRainbow,
 
-    Custom(TriggerBehavior),
+    /// This will send a ring of a single color out from the starting offset across a matrix,
+    /// reaching the farthest corner at the end of the trigger, or in from the farthest corner if
+    /// the direction is negative. The ring is `pixels_per_pixel_group` leds thick.
+    /// Fade in and out times can be adjusted separately.
+    ExpandingRing,
+
+    /// This will send out a ring like above, in a fading color that matches the animation's global
+    /// trigger fade speed setting.
+    /// Fade in and out times can be adjusted separately.
+    ExpandingRingFade,
+
+    /// This will send out rings like above, with a new color for each ring, in the order of the
+    /// colors of a rainbow.
+    ExpandingRingRainbow,
+
+    /// This will send a sphere of a single color out through the animation's position map from
+    /// the pixel at the starting offset, reaching the farthest pixel at the end of the trigger, or
+    /// in from the farthest pixel if the direction is negative. The shell of the sphere is
+    /// `pixels_per_pixel_group` sixteenths of that distance thick. Without a position map, the
+    /// sphere spreads across the matrix.
+    /// Fade in and out times can be adjusted separately.
+    ExpandingSphere,
+
+    /// This will send out a sphere like above, in a fading color that matches the animation's
+    /// global trigger fade speed setting.
+    /// Fade in and out times can be adjusted separately.
+    ExpandingSphereFade,
+
+    /// This will send out spheres like above, with a new color for each sphere, in the order of the
+    /// colors of a rainbow.
+    ExpandingSphereRainbow,
+
+    Custom(TriggerBehavior<P>),
 }
 
-impl Mode {
-    pub fn get_behavior(&self) -> TriggerBehavior {
+impl<P: Pixel> Mode<P> {
+    pub fn get_behavior(&self) -> TriggerBehavior<P> {
         match *self {
             Mode::NoTrigger => (None, None),
             Mode::Background => (None, None),
@@ -94,45 +127,65 @@ impl Mode {
             Mode::Flash => (Some(init_flash), Some(flash)),
             Mode::FlashFade => (Some(init_flash_fade), Some(flash)),
             Mode::FlashRainbow => (Some(init_flash_rainbow), Some(flash)),
+            Mode::ExpandingRing => (Some(init_expanding_ring), Some(expanding_ring)),
+            Mode::ExpandingRingFade => (Some(init_expanding_ring_fade), Some(expanding_ring)),
+            Mode::ExpandingRingRainbow => (Some(init_expanding_ring_rainbow), Some(expanding_ring)),
+            Mode::ExpandingSphere => (Some(init_expanding_ring), Some(expanding_sphere)),
+            Mode::ExpandingSphereFade => (Some(init_expanding_ring_fade), Some(expanding_sphere)),
+            Mode::ExpandingSphereRainbow => {
+                (Some(init_expanding_ring_rainbow), Some(expanding_sphere))
+            }
             Mode::Custom((i, u)) => (i, u),
         }
     }
 }
 
 /// All triggers share a single rainbow / fade speed, which is configured in this struct
-pub struct GlobalParameters<'a> {
-    pub rainbow: colors::Rainbow<'a>,
+#[derive(Copy, Clone)]
+pub struct GlobalParameters<'a, P = RGB8> {
+    pub rainbow: colors::Rainbow<'a, P>,
     pub rainbow_dir: RainbowDir,
     pub duration_ns: u64,
 }
 
 /// This holds all triggers and contains the variables that apply to all triggers simultaneously, and not just to
 /// individual running triggers.
-pub struct TriggerCollection<'a, const N: usize> {
-    pub fade_rainbow: StatefulRainbow<'a>,
-    pub incremental_rainbow: StatefulRainbow<'a>,
+pub struct TriggerCollection<'a, const N: usize, P = RGB8> {
+    pub fade_rainbow: StatefulRainbow<'a, P>,
+    pub incremental_rainbow: StatefulRainbow<'a, P>,
     pub frames: Progression,
-    triggers: ArrayVec<Trigger, N>,
+    /// The number of leds in each row of a matrix for the 2D modes, or 0 for a single row.
+    pub width: usize,
+    /// Where each pixel is in space for the spatial modes.
+    pub positions: Option<PositionMap<'a>>,
+    // Running triggers are kept at the front, oldest first. This is an array rather than an
+    // ArrayVec because a collection with a destructor would need the position map to strictly
+    // outlive the animation, which the LightingController's borrows don't allow.
+    triggers: [Option<Trigger<'a, P>>; N],
 }
 
-impl<'a, const N: usize> TriggerCollection<'a, N> {
-    pub fn new(init: &GlobalParameters<'a>, frame_rate: Hertz) -> Self {
+impl<'a, const N: usize, P: Pixel> TriggerCollection<'a, N, P> {
+    pub fn new(init: &GlobalParameters<'a, P>, frame_rate: Hertz) -> Self {
         let fade_rainbow = StatefulRainbow::new(init.rainbow, init.rainbow_dir);
         let incremental_rainbow = StatefulRainbow::new(init.rainbow, init.rainbow_dir);
         let frames = Progression::new(convert_ns_to_frames(init.duration_ns, frame_rate));
-        let triggers = ArrayVec::new();
+        let triggers = core::array::from_fn(|_| None);
 
         Self {
             fade_rainbow,
             incremental_rainbow,
             frames,
+            width: 0,
+            positions: None,
             triggers,
         }
     }
 
-    pub fn add_trigger(&mut self, init: &Parameters, frame_rate: Hertz) {
+    pub fn add_trigger(&mut self, init: &Parameters<P>, frame_rate: Hertz) {
         let (initializer, updater) = init.mode.get_behavior();
         let mut new_trigger = Trigger::new(init, self.current_rainbow_color(), frame_rate);
+        new_trigger.width = self.width;
+        new_trigger.positions = self.positions;
 
         if let Some(initialize) = initializer {
             initialize(
@@ -144,18 +197,37 @@ impl<'a, const N: usize> TriggerCollection<'a, N> {
                 },
             );
         }
+        new_trigger.color = new_trigger.color.scaled(init.brightness);
         new_trigger.updater = updater;
 
-        let _ = self.triggers.try_push(new_trigger);
+        if let Some(slot) = self.triggers.iter_mut().find(|slot| slot.is_none()) {
+            *slot = Some(new_trigger);
+        }
     }
 
-    pub fn update(&mut self, segment: &mut [RGB8]) {
-        for trigger in self.triggers.iter_mut() {
-            trigger.update(segment)
-        }
+    /// Moves the fade rainbow to where it would be `frame` frames after the animation started.
+    /// The incremental rainbow only advances when triggered, so it goes back to its first color as
+    /// if nothing had been triggered yet. Triggers that are already running are left alone.
+    pub fn seek(&mut self, frame: usize) {
+        self.frames.set_current(frame);
+        self.incremental_rainbow.reset();
+        self.fade_rainbow
+            .position
+            .set_current(frame / self.frames.total.max(1));
+    }
 
-        self.triggers
-            .retain(|t| t.frames.get_current() < t.frames.total - 1);
+    pub fn update(&mut self, segment: &mut [P]) {
+        let mut running = 0;
+        for index in 0..N {
+            let Some(mut trigger) = self.triggers[index].take() else {
+                break;
+            };
+            trigger.update(segment);
+            if trigger.frames.get_current() + 1 < trigger.frames.total {
+                self.triggers[running] = Some(trigger);
+                running += 1;
+            }
+        }
         let did_roll = self.frames.checked_increment();
         if did_roll {
             self.fade_rainbow.increment();
@@ -167,31 +239,51 @@ impl<'a, const N: usize> TriggerCollection<'a, N> {
 /// aspects of the animation can be derived from these parameters and the
 /// AnimationGlobalTriggerParameters struct's parameters. Some parameters will not have an
 /// effect depending on the mode.
-pub struct Parameters {
-    pub mode: Mode,
+#[derive(Copy, Clone)]
+pub struct Parameters<P = RGB8> {
+    pub mode: Mode<P>,
     pub direction: Direction,
     pub fade_in_time_ns: u64,
     pub fade_out_time_ns: u64,
     pub starting_offset: u16,
     pub pixels_per_pixel_group: usize,
+    pub blend_mode: BlendMode,
+    /// Scales the trigger's color, where 255 draws it as it is in the rainbow.
+    pub brightness: u8,
+}
+
+impl<P> Parameters<P> {
+    /// Checks that a trigger with these parameters lasts at least a frame at `frame_rate`. A
+    /// shorter trigger is still drawn on the frame it starts, and is then dropped. Any
+    /// `pixels_per_pixel_group` of 0 is drawn as 1.
+    pub fn validate(&self, frame_rate: Hertz) -> Result<(), Error> {
+        let duration_ns = self.fade_in_time_ns.saturating_add(self.fade_out_time_ns);
+        match convert_ns_to_frames(duration_ns, frame_rate) {
+            0 => Err(Error::ZeroLengthTrigger),
+            _ => Ok(()),
+        }
+    }
 }
 
 /// This contains all the information needed to keep track of the current state of a trigger
 /// animation. It is updated every frame to match the current state of the animation.
-pub struct Trigger {
+pub struct Trigger<'a, P = RGB8> {
     offset: u16,
     frames: Progression,
     transition_frame: usize,
     direction: Direction,
-    color: RGB8,
-    updater: Option<TriggerUpdater>,
+    color: P,
+    updater: Option<TriggerUpdater<P>>,
     pixels_per_pixel_group: usize,
+    width: usize,
+    positions: Option<PositionMap<'a>>,
+    blend_mode: BlendMode,
 }
 
-impl Trigger {
-    pub fn new(init: &Parameters, color: RGB8, frame_rate: Hertz) -> Self {
+impl<'a, P: Pixel> Trigger<'a, P> {
+    pub fn new(init: &Parameters<P>, color: P, frame_rate: Hertz) -> Self {
         let offset = init.starting_offset;
-        let total_duration_ns = init.fade_in_time_ns + init.fade_out_time_ns;
+        let total_duration_ns = init.fade_in_time_ns.saturating_add(init.fade_out_time_ns);
 
         let frames = convert_ns_to_frames(total_duration_ns, frame_rate);
         let frames = Progression::new(frames);
@@ -201,6 +293,7 @@ impl Trigger {
         let updater = None;
 
         let pixels_per_pixel_group = init.pixels_per_pixel_group;
+        let blend_mode = init.blend_mode;
 
         Self {
             offset,
@@ -210,10 +303,13 @@ impl Trigger {
             color,
             updater,
             pixels_per_pixel_group,
+            width: 0,
+            positions: None,
+            blend_mode,
         }
     }
 
-    pub fn update(&mut self, segment: &mut [RGB8]) {
+    pub fn update(&mut self, segment: &mut [P]) {
         if let Some(f) = self.updater {
             f(self, segment);
         }
@@ -221,8 +317,8 @@ impl Trigger {
     }
 }
 
-impl<'a, const N: usize> MarchingRainbow for TriggerCollection<'a, N> {
-    fn rainbow(&self) -> &StatefulRainbow<'_> {
+impl<'a, const N: usize, P: Pixel> MarchingRainbow<P> for TriggerCollection<'a, N, P> {
+    fn rainbow(&self) -> &StatefulRainbow<'_, P> {
         &self.incremental_rainbow
     }
     fn frames(&self) -> &Progression {
@@ -230,7 +326,7 @@ impl<'a, const N: usize> MarchingRainbow for TriggerCollection<'a, N> {
     }
 }
 
-fn get_trigger_fade_progress(trigger: &mut Trigger) -> Progression {
+fn get_trigger_fade_progress<P: Pixel>(trigger: &mut Trigger<P>) -> Progression {
     let is_fade_in = trigger.frames.get_current() < trigger.transition_frame;
 
     let mut progress;
@@ -247,85 +343,175 @@ fn get_trigger_fade_progress(trigger: &mut Trigger) -> Progression {
     progress
 }
 
-fn flash(trigger: &mut Trigger, segment: &mut [RGB8]) {
+fn flash<P: Pixel>(trigger: &mut Trigger<P>, segment: &mut [P]) {
     let progress = get_trigger_fade_progress(trigger);
 
     for led in segment {
-        *led = led.lerp_with(trigger.color, progress);
+        let blended = led.blend_with(trigger.color, trigger.blend_mode);
+        *led = led.lerp_with(blended, progress);
     }
 }
 
-fn color_pulse(trigger: &mut Trigger, segment: &mut [RGB8]) {
+fn color_pulse<P: Pixel>(trigger: &mut Trigger<P>, segment: &mut [P]) {
     let progress = get_trigger_fade_progress(trigger);
 
+    if segment.is_empty() {
+        return;
+    }
+
     // the range will be always at least 1 led, up to pixels_per_pixel_group leds:
     let first_led_index = trigger.offset as usize / segment.len();
-    let shot_width = 1.max(trigger.pixels_per_pixel_group);
+    let shot_width = trigger.pixels_per_pixel_group.clamp(1, segment.len());
     let last_led_index = first_led_index + shot_width;
 
     for index in first_led_index..last_led_index {
         let corrected_index = index % segment.len();
-        segment[corrected_index] = segment[corrected_index].lerp_with(trigger.color, progress);
+        let led = segment[corrected_index];
+        let blended = led.blend_with(trigger.color, trigger.blend_mode);
+        segment[corrected_index] = led.lerp_with(blended, progress);
     }
 }
 
-fn color_shot(trigger: &mut Trigger, segment: &mut [RGB8]) {
+fn color_shot<P: Pixel>(trigger: &mut Trigger<P>, segment: &mut [P]) {
+    if segment.is_empty() {
+        return;
+    }
     let current_offset = shift_offset(trigger.offset, trigger.frames, trigger.direction) as usize;
-    let offset_distance_between_leds = MAX_OFFSET as usize / segment.len();
+    let offset_distance_between_leds = (MAX_OFFSET as usize / segment.len()).max(1);
 
     // the range will be always at least 1 led, up to pixels_per_pixel_group leds:
     let first_led_index = current_offset / offset_distance_between_leds;
-    let shot_width = 1.max(trigger.pixels_per_pixel_group);
+    let shot_width = trigger.pixels_per_pixel_group.clamp(1, segment.len());
     let last_led_index = first_led_index + shot_width;
 
     for index in first_led_index..last_led_index {
         let corrected_index = index % segment.len();
-        segment[corrected_index] = trigger.color;
+        let led = segment[corrected_index];
+        segment[corrected_index] = led.blend_with(trigger.color, trigger.blend_mode);
+    }
+}
+
+fn expanding_ring<P: Pixel>(trigger: &mut Trigger<P>, segment: &mut [P]) {
+    // Distances are measured in sixteenths of an led so the ring grows smoothly:
+    const SCALE: usize = 16;
+    let progress = get_trigger_fade_progress(trigger);
+
+    let plane = Plane::new(trigger.width, segment.len());
+    let center = trigger.offset as usize * segment.len() / (MAX_OFFSET as usize + 1);
+    let (center_x, center_y) = plane.position(center);
+    let far_x = center_x.max(plane.width - 1 - center_x);
+    let far_y = center_y.max(plane.height - 1 - center_y);
+    let max_radius = ((far_x * far_x + far_y * far_y) * SCALE * SCALE).isqrt();
+
+    let last_frame = trigger.frames.total.saturating_sub(1).max(1);
+    let frame = trigger.frames.get_current().min(last_frame);
+    let radius = match trigger.direction {
+        Direction::Negative => max_radius * (last_frame - frame) / last_frame,
+        _ => max_radius * frame / last_frame,
+    };
+    let half_thickness = trigger
+        .pixels_per_pixel_group
+        .clamp(1, segment.len().max(1))
+        * SCALE
+        / 2;
+
+    for (index, led) in segment.iter_mut().enumerate() {
+        let (x, y) = plane.position(index);
+        let (dx, dy) = (x.abs_diff(center_x), y.abs_diff(center_y));
+        let distance = ((dx * dx + dy * dy) * SCALE * SCALE).isqrt();
+        if distance.abs_diff(radius) < half_thickness {
+            let blended = led.blend_with(trigger.color, trigger.blend_mode);
+            *led = led.lerp_with(blended, progress);
+        }
+    }
+}
+
+fn expanding_sphere<P: Pixel>(trigger: &mut Trigger<P>, segment: &mut [P]) {
+    let progress = get_trigger_fade_progress(trigger);
+
+    let space = Space::new(trigger.positions, trigger.width, segment.len());
+    let center_index = trigger.offset as usize * segment.len() / (MAX_OFFSET as usize + 1);
+    let Some(center) = space.point(center_index) else {
+        return;
+    };
+    let max_radius = space.farthest_from(center) as usize;
+
+    let last_frame = trigger.frames.total.saturating_sub(1).max(1);
+    let frame = trigger.frames.get_current().min(last_frame);
+    let radius = match trigger.direction {
+        Direction::Negative => max_radius * (last_frame - frame) / last_frame,
+        _ => max_radius * frame / last_frame,
+    };
+    let half_thickness = (max_radius * trigger.pixels_per_pixel_group.clamp(1, 32) / 32).max(1);
+
+    for (index, led) in segment.iter_mut().enumerate() {
+        let is_in_shell = space.point(index).is_some_and(|point| {
+            (point.distance_to(center) as usize).abs_diff(radius) < half_thickness
+        });
+        if is_in_shell {
+            let blended = led.blend_with(trigger.color, trigger.blend_mode);
+            *led = led.lerp_with(blended, progress);
+        }
     }
 }
 
-fn init_color_pulse(trigger: &mut Trigger, _: &mut TimedRainbows) {
+fn init_color_pulse<P: Pixel>(trigger: &mut Trigger<P>, _: &mut TimedRainbows<P>) {
     trigger.direction = Direction::Stopped;
     trigger.offset = get_random_offset();
 }
 
-fn init_color_pulse_fade(trigger: &mut Trigger, global: &mut TimedRainbows) {
+fn init_color_pulse_fade<P: Pixel>(trigger: &mut Trigger<P>, global: &mut TimedRainbows<P>) {
     trigger.color = global.calculate_fade_color();
     init_color_pulse(trigger, global);
 }
 
-fn init_color_pulse_rainbow(trigger: &mut Trigger, global: &mut TimedRainbows) {
+fn init_color_pulse_rainbow<P: Pixel>(trigger: &mut Trigger<P>, global: &mut TimedRainbows<P>) {
     trigger.color = global.current_rainbow_color();
     init_color_pulse(trigger, global);
     global.advance_rainbow_color();
 }
 
-fn init_color_shot(_: &mut Trigger, _: &mut TimedRainbows) {
+fn init_color_shot<P: Pixel>(_: &mut Trigger<P>, _: &mut TimedRainbows<P>) {
     // Don't need to change anything until it's running.
 }
 
-fn init_color_shot_fade(trigger: &mut Trigger, global: &mut TimedRainbows) {
+fn init_color_shot_fade<P: Pixel>(trigger: &mut Trigger<P>, global: &mut TimedRainbows<P>) {
     trigger.color = global.calculate_fade_color();
     init_color_shot(trigger, global);
 }
 
-fn init_color_shot_rainbow(trigger: &mut Trigger, global: &mut TimedRainbows) {
+fn init_color_shot_rainbow<P: Pixel>(trigger: &mut Trigger<P>, global: &mut TimedRainbows<P>) {
     trigger.color = global.current_rainbow_color();
     init_color_shot(trigger, global);
     global.advance_rainbow_color();
 }
 
-fn init_flash(trigger: &mut Trigger, _: &mut TimedRainbows) {
+fn init_flash<P: Pixel>(trigger: &mut Trigger<P>, _: &mut TimedRainbows<P>) {
     trigger.direction = Direction::Stopped;
 }
 
-fn init_flash_fade(trigger: &mut Trigger, global: &mut TimedRainbows) {
+fn init_flash_fade<P: Pixel>(trigger: &mut Trigger<P>, global: &mut TimedRainbows<P>) {
     init_flash(trigger, global);
     trigger.color = global.calculate_fade_color();
 }
 
-fn init_flash_rainbow(trigger: &mut Trigger, global: &mut TimedRainbows) {
+fn init_flash_rainbow<P: Pixel>(trigger: &mut Trigger<P>, global: &mut TimedRainbows<P>) {
     init_flash(trigger, global);
     trigger.color = global.current_rainbow_color();
     global.advance_rainbow_color();
 }
+
+fn init_expanding_ring<P: Pixel>(_: &mut Trigger<P>, _: &mut TimedRainbows<P>) {
+    // The ring grows from the starting offset in the direction given, so nothing changes.
+}
+
+fn init_expanding_ring_fade<P: Pixel>(trigger: &mut Trigger<P>, global: &mut TimedRainbows<P>) {
+    trigger.color = global.calculate_fade_color();
+    init_expanding_ring(trigger, global);
+}
+
+fn init_expanding_ring_rainbow<P: Pixel>(trigger: &mut Trigger<P>, global: &mut TimedRainbows<P>) {
+    trigger.color = global.current_rainbow_color();
+    init_expanding_ring(trigger, global);
+    global.advance_rainbow_color();
+}
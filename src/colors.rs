@@ -1,51 +1,442 @@
 //! This library has definitions for various color types and assorted utilities for manipulating and
-//! working with the RGB8 color type. Colors are intended to be compatible with the
-//! [smart-leds](https://github.com/smart-leds-rs/smart-leds) crate, which in turn is compatible
-//! with the [rgb](https://github.com/kornelski/rust-rgb) crate.
+//! working with the RGB8, RGBW8, RGBA8 and RGBWA8 color types. Colors are intended to be compatible
+//! with the [smart-leds](https://github.com/smart-leds-rs/smart-leds) crate, which in turn is
+//! compatible with the [rgb](https://github.com/kornelski/rust-rgb) crate.
 
 use crate::utility::Progression;
-use rgb::RGB8;
+use rgb::{RGB8, RGBA8};
 use smart_leds::colors::*;
+use smart_leds::White;
 
-pub fn color_lerp(
+/// An RGB pixel with an extra white led, as used by SK6812 RGBW strips.
+pub type RGBW8 = smart_leds::RGBW<u8>;
+
+/// An RGB pixel with extra white and amber leds.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct RGBWA8 {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    pub w: u8,
+    pub a: u8,
+}
+
+/// These are the kinds of led that can make up a single pixel.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Channel {
+    Red,
+    Green,
+    Blue,
+    White,
+    Amber,
+}
+
+/// This is implemented for every color type that animations can be rendered with. All of the color
+/// math in the library works one channel at a time, so a pixel type only needs to be able to walk
+/// its channels and convert to and from plain RGB.
+///
+/// For `RGBA8` the fourth channel is treated as an amber led rather than an alpha value.
+pub trait Pixel: Copy + Default + PartialEq + 'static {
+    /// Combines each channel of `self` with the same channel of `other`.
+    fn zip_channels(self, other: Self, f: impl FnMut(Channel, u8, u8) -> u8) -> Self;
+
+    /// Visits each channel of the pixel in order.
+    fn fold_channels<T>(self, init: T, f: impl FnMut(T, Channel, u8) -> T) -> T;
+
+    /// Converts an RGB color into this pixel type. Pixels with a white led will move as much of
+    /// the color into the white channel as possible.
+    fn from_rgb(color: RGB8) -> Self;
+
+    /// Converts this pixel into the closest RGB color.
+    fn to_rgb(self) -> RGB8;
+
+    fn map_channels(self, mut f: impl FnMut(Channel, u8) -> u8) -> Self {
+        self.zip_channels(self, |channel, value, _| f(channel, value))
+    }
+
+    /// Returns the value of one channel, or `None` if the pixel doesn't have that led.
+    fn channel(self, channel: Channel) -> Option<u8> {
+        self.fold_channels(None, |found, c, value| match c == channel {
+            true => Some(value),
+            false => found,
+        })
+    }
+
+    /// Returns the pixel with one channel replaced. Channels the pixel doesn't have are ignored.
+    fn with_channel(self, channel: Channel, value: u8) -> Self {
+        self.map_channels(|c, old| if c == channel { value } else { old })
+    }
+}
+
+/// The order a protocol or a chain of leds sends each pixel's channels in. Channels that a pixel
+/// type doesn't have are skipped when they're received, and sent as 0.
+pub type ColorOrder = &'static [Channel];
+
+/// The common color orders. Most DMX fixtures and consoles use `RGB`, while WS2812 strips use `GRB`.
+pub mod order {
+    use super::Channel::*;
+    use super::ColorOrder;
+
+    pub const RGB: ColorOrder = &[Red, Green, Blue];
+    pub const RBG: ColorOrder = &[Red, Blue, Green];
+    pub const GRB: ColorOrder = &[Green, Red, Blue];
+    pub const GBR: ColorOrder = &[Green, Blue, Red];
+    pub const BRG: ColorOrder = &[Blue, Red, Green];
+    pub const BGR: ColorOrder = &[Blue, Green, Red];
+    pub const RGBW: ColorOrder = &[Red, Green, Blue, White];
+    pub const GRBW: ColorOrder = &[Green, Red, Blue, White];
+    pub const RGBA: ColorOrder = &[Red, Green, Blue, Amber];
+    pub const RGBWA: ColorOrder = &[Red, Green, Blue, White, Amber];
+}
+
+/// Moves the part of a color shared by all of red, green and blue into a white channel.
+fn extract_white(color: RGB8) -> (RGB8, u8) {
+    let white = color.r.min(color.g).min(color.b);
+    let color = RGB8::new(color.r - white, color.g - white, color.b - white);
+    (color, white)
+}
+
+/// Adds a white channel back into the red, green and blue channels.
+fn add_white(color: RGB8, white: u8) -> RGB8 {
+    color.map_channels(|_, value| value.saturating_add(white))
+}
+
+impl Pixel for RGB8 {
+    fn zip_channels(self, other: Self, mut f: impl FnMut(Channel, u8, u8) -> u8) -> Self {
+        RGB8 {
+            r: f(Channel::Red, self.r, other.r),
+            g: f(Channel::Green, self.g, other.g),
+            b: f(Channel::Blue, self.b, other.b),
+        }
+    }
+
+    fn fold_channels<T>(self, init: T, mut f: impl FnMut(T, Channel, u8) -> T) -> T {
+        let acc = f(init, Channel::Red, self.r);
+        let acc = f(acc, Channel::Green, self.g);
+        f(acc, Channel::Blue, self.b)
+    }
+
+    fn from_rgb(color: RGB8) -> Self {
+        color
+    }
+
+    fn to_rgb(self) -> RGB8 {
+        self
+    }
+}
+
+impl Pixel for RGBW8 {
+    fn zip_channels(self, other: Self, mut f: impl FnMut(Channel, u8, u8) -> u8) -> Self {
+        RGBW8 {
+            r: f(Channel::Red, self.r, other.r),
+            g: f(Channel::Green, self.g, other.g),
+            b: f(Channel::Blue, self.b, other.b),
+            a: White(f(Channel::White, self.a.0, other.a.0)),
+        }
+    }
+
+    fn fold_channels<T>(self, init: T, mut f: impl FnMut(T, Channel, u8) -> T) -> T {
+        let acc = self.rgb().fold_channels(init, &mut f);
+        f(acc, Channel::White, self.a.0)
+    }
+
+    fn from_rgb(color: RGB8) -> Self {
+        let (color, white) = extract_white(color);
+        RGBW8 {
+            r: color.r,
+            g: color.g,
+            b: color.b,
+            a: White(white),
+        }
+    }
+
+    fn to_rgb(self) -> RGB8 {
+        add_white(self.rgb(), self.a.0)
+    }
+}
+
+impl Pixel for RGBA8 {
+    fn zip_channels(self, other: Self, mut f: impl FnMut(Channel, u8, u8) -> u8) -> Self {
+        RGBA8 {
+            r: f(Channel::Red, self.r, other.r),
+            g: f(Channel::Green, self.g, other.g),
+            b: f(Channel::Blue, self.b, other.b),
+            a: f(Channel::Amber, self.a, other.a),
+        }
+    }
+
+    fn fold_channels<T>(self, init: T, mut f: impl FnMut(T, Channel, u8) -> T) -> T {
+        let acc = self.rgb().fold_channels(init, &mut f);
+        f(acc, Channel::Amber, self.a)
+    }
+
+    fn from_rgb(color: RGB8) -> Self {
+        RGBA8::new(color.r, color.g, color.b, 0)
+    }
+
+    fn to_rgb(self) -> RGB8 {
+        // Amber is roughly full red with three quarters green:
+        let amber = RGB8::new(self.a, (self.a as u16 * 3 / 4) as u8, 0);
+        self.rgb()
+            .zip_channels(amber, |_, c, a| c.saturating_add(a))
+    }
+}
+
+impl Pixel for RGBWA8 {
+    fn zip_channels(self, other: Self, mut f: impl FnMut(Channel, u8, u8) -> u8) -> Self {
+        RGBWA8 {
+            r: f(Channel::Red, self.r, other.r),
+            g: f(Channel::Green, self.g, other.g),
+            b: f(Channel::Blue, self.b, other.b),
+            w: f(Channel::White, self.w, other.w),
+            a: f(Channel::Amber, self.a, other.a),
+        }
+    }
+
+    fn fold_channels<T>(self, init: T, mut f: impl FnMut(T, Channel, u8) -> T) -> T {
+        let acc = RGB8::new(self.r, self.g, self.b).fold_channels(init, &mut f);
+        let acc = f(acc, Channel::White, self.w);
+        f(acc, Channel::Amber, self.a)
+    }
+
+    fn from_rgb(color: RGB8) -> Self {
+        let (color, w) = extract_white(color);
+        RGBWA8 {
+            r: color.r,
+            g: color.g,
+            b: color.b,
+            w,
+            a: 0,
+        }
+    }
+
+    fn to_rgb(self) -> RGB8 {
+        let rgba = RGBA8::new(self.r, self.g, self.b, self.a);
+        add_white(rgba.to_rgb(), self.w)
+    }
+}
+
+/// This converts the pixels an animation renders into the pixels a LogicalStrip holds. Rendering
+/// an RGB animation onto a strip with a white channel will automatically extract the white.
+pub trait FromPixel<P> {
+    fn from_pixel(pixel: P) -> Self;
+}
+
+macro_rules! impl_from_pixel {
+    ($($pixel:ty),+) => {
+        $(
+            impl FromPixel<$pixel> for $pixel {
+                fn from_pixel(pixel: $pixel) -> Self {
+                    pixel
+                }
+            }
+        )+
+    };
+    (rgb => $($pixel:ty),+) => {
+        $(
+            impl FromPixel<RGB8> for $pixel {
+                fn from_pixel(pixel: RGB8) -> Self {
+                    <$pixel>::from_rgb(pixel)
+                }
+            }
+
+            impl FromPixel<$pixel> for RGB8 {
+                fn from_pixel(pixel: $pixel) -> Self {
+                    pixel.to_rgb()
+                }
+            }
+        )+
+    };
+}
+
+impl_from_pixel!(RGB8, RGBW8, RGBA8, RGBWA8);
+impl_from_pixel!(rgb => RGBW8, RGBA8, RGBWA8);
+
+/// Returns the color `factor` of the way from `in_min` to `in_max` between the two colors. An
+/// empty range is already at the end color.
+pub fn color_lerp<P: Pixel>(
     factor: i32,
     in_min: i32,
     in_max: i32,
-    start_color: RGB8,
-    end_color: RGB8,
-) -> RGB8 {
-    let lerp = |start: u8, end: u8| {
-        let start = start as i32;
-        let end = end as i32;
+    start_color: P,
+    end_color: P,
+) -> P {
+    if in_max == in_min {
+        return end_color;
+    }
+    start_color.zip_channels(end_color, |_, start, end| {
+        let (start, end) = (start as i64, end as i64);
+        let (factor, in_min, in_max) = (factor as i64, in_min as i64, in_max as i64);
         ((factor - in_min) * (end - start) / (in_max - in_min) + start) as u8
-    };
-    let mut mid_color = <RGB8>::new(0, 0, 0);
-    mid_color.r = lerp(start_color.r, end_color.r);
-    mid_color.g = lerp(start_color.g, end_color.g);
-    mid_color.b = lerp(start_color.b, end_color.b);
-    mid_color
+    })
+}
+
+/// Blend modes decide how a color being drawn by an animation layer is combined with the color
+/// that is already on the led underneath it.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum BlendMode {
+    /// The new color replaces whatever was underneath it.
+    #[default]
+    Replace,
+
+    /// The new color is added to the color underneath, saturating at full brightness.
+    Additive,
+
+    /// The new color is multiplied with the color underneath, which tints and darkens it.
+    Multiply,
+
+    /// The inverse of multiply, which tints and lightens the color underneath.
+    Screen,
+
+    /// Each color channel keeps whichever value is brighter.
+    Lighten,
+
+    /// The new color is mixed over the color underneath with the provided opacity, where 0 is
+    /// fully transparent and 255 is the same as `Replace`.
+    Alpha(u8),
+}
+
+impl BlendMode {
+    pub fn blend<P: Pixel>(&self, under: P, over: P) -> P {
+        under.zip_channels(over, |_, under, over| {
+            let (u, o) = (under as u16, over as u16);
+            match *self {
+                BlendMode::Replace => over,
+                BlendMode::Additive => under.saturating_add(over),
+                BlendMode::Multiply => (u * o / 255) as u8,
+                BlendMode::Screen => (255 - (255 - u) * (255 - o) / 255) as u8,
+                BlendMode::Lighten => under.max(over),
+                BlendMode::Alpha(opacity) => {
+                    let opacity = opacity as u16;
+                    ((u * (255 - opacity) + o * opacity) / 255) as u8
+                }
+            }
+        })
+    }
+}
+
+/// Scales a single color channel by a brightness value, where 255 leaves it unchanged and 0 turns
+/// it off completely.
+pub fn scale_channel(value: u8, brightness: u8) -> u8 {
+    ((value as u16 * (brightness as u16 + 1)) >> 8) as u8
+}
+
+/// This remaps linear color values (the numbers animations use, where 127 is half brightness) to the
+/// nonlinear values that produce that perceived brightness on a typical led, using a gamma of 2.8.
+pub const GAMMA_2_8_CURVE: [u8; 256] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 2, 2, 2, 2, 2, 2, 2, 2, 3, 3, 3, 3, 3, 3, 3, 4, 4, 4, 4, 4, 5, 5, 5,
+    5, 6, 6, 6, 6, 7, 7, 7, 7, 8, 8, 8, 9, 9, 9, 10, 10, 10, 11, 11, 11, 12, 12, 13, 13, 13, 14,
+    14, 15, 15, 16, 16, 17, 17, 18, 18, 19, 19, 20, 20, 21, 21, 22, 22, 23, 24, 24, 25, 25, 26, 27,
+    27, 28, 29, 29, 30, 31, 32, 32, 33, 34, 35, 35, 36, 37, 38, 39, 39, 40, 41, 42, 43, 44, 45, 46,
+    47, 48, 49, 50, 50, 51, 52, 54, 55, 56, 57, 58, 59, 60, 61, 62, 63, 64, 66, 67, 68, 69, 70, 72,
+    73, 74, 75, 77, 78, 79, 81, 82, 83, 85, 86, 87, 89, 90, 92, 93, 95, 96, 98, 99, 101, 102, 104,
+    105, 107, 109, 110, 112, 114, 115, 117, 119, 120, 122, 124, 126, 127, 129, 131, 133, 135, 137,
+    138, 140, 142, 144, 146, 148, 150, 152, 154, 156, 158, 160, 162, 164, 167, 169, 171, 173, 175,
+    177, 180, 182, 184, 186, 189, 191, 193, 196, 198, 200, 203, 205, 208, 210, 213, 215, 218, 220,
+    223, 225, 228, 231, 233, 236, 239, 241, 244, 247, 249, 252, 255,
+];
+
+/// This is a gamma curve that leaves all values unchanged.
+pub const LINEAR_CURVE: [u8; 256] = linear_curve();
+
+const fn linear_curve() -> [u8; 256] {
+    let mut curve = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        curve[i] = i as u8;
+        i += 1;
+    }
+    curve
+}
+
+/// A lookup table with a separate gamma curve for each color channel, since the red, green and blue
+/// dies in an led rarely have the same response. The white and amber curves are only used by pixel
+/// types that have those channels.
+#[derive(Copy, Clone)]
+pub struct GammaTable {
+    pub r: [u8; 256],
+    pub g: [u8; 256],
+    pub b: [u8; 256],
+    pub w: [u8; 256],
+    pub a: [u8; 256],
 }
 
+impl GammaTable {
+    /// Makes a table from separate red, green and blue curves. The white and amber channels use the
+    /// green curve until they are set with `with_white()` and `with_amber()`.
+    pub const fn new(r: [u8; 256], g: [u8; 256], b: [u8; 256]) -> Self {
+        GammaTable {
+            r,
+            g,
+            b,
+            w: g,
+            a: g,
+        }
+    }
+
+    /// Makes a table that uses the same curve for every channel.
+    pub const fn uniform(curve: [u8; 256]) -> Self {
+        GammaTable::new(curve, curve, curve)
+    }
+
+    pub const fn with_white(mut self, curve: [u8; 256]) -> Self {
+        self.w = curve;
+        self
+    }
+
+    pub const fn with_amber(mut self, curve: [u8; 256]) -> Self {
+        self.a = curve;
+        self
+    }
+
+    pub fn correct<P: Pixel>(&self, color: P) -> P {
+        color.map_channels(|channel, value| {
+            let curve = match channel {
+                Channel::Red => &self.r,
+                Channel::Green => &self.g,
+                Channel::Blue => &self.b,
+                Channel::White => &self.w,
+                Channel::Amber => &self.a,
+            };
+            curve[value as usize]
+        })
+    }
+}
+
+/// A gamma table that is a good starting point for WS2812 style leds.
+pub const GAMMA_2_8: GammaTable = GammaTable::uniform(GAMMA_2_8_CURVE);
+
 pub trait ManipulatableColor<RgbType> {
     fn lerp_with(&self, to_color: RgbType, factor: Progression) -> RgbType;
+    fn blend_with(&self, over: RgbType, mode: BlendMode) -> RgbType;
+    fn scaled(&self, brightness: u8) -> RgbType;
     fn set_color(&mut self, c: RgbType);
 }
 
-impl ManipulatableColor<RGB8> for RGB8 {
-    fn lerp_with(&self, to_color: RGB8, factor: Progression) -> RGB8 {
+impl<P: Pixel> ManipulatableColor<P> for P {
+    fn lerp_with(&self, to_color: P, factor: Progression) -> P {
+        // Progressions too long for an i32 are scaled down to fit:
+        let scale = factor.total / i32::MAX as usize + 1;
         color_lerp(
-            factor.get_current() as i32,
+            (factor.get_current() / scale) as i32,
             0,
-            factor.total as i32,
+            (factor.total / scale) as i32,
             *self,
             to_color,
         )
     }
 
-    fn set_color(&mut self, c: RGB8) {
-        self.r = c.r;
-        self.g = c.g;
-        self.b = c.b;
+    fn blend_with(&self, over: P, mode: BlendMode) -> P {
+        mode.blend(*self, over)
+    }
+
+    fn scaled(&self, brightness: u8) -> P {
+        self.map_channels(|_, value| scale_channel(value, brightness))
+    }
+
+    fn set_color(&mut self, c: P) {
+        *self = c;
     }
 }
 
@@ -93,7 +484,7 @@ pub const T_5000K: RGB8 = RGB8 {
 
 // Use const generic rainbows to make iterable rainbows of various sizes. Rainbows contain a
 // list of colors in order, which will be used by animations as a color rainbow.
-pub type Rainbow<'a> = &'a [RGB8];
+pub type Rainbow<'a, P = RGB8> = &'a [P];
 
 pub const R_BLACK: Rainbow = &[BLACK];
 pub const R_WHITE: Rainbow = &[WHITE];
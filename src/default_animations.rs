@@ -1,5 +1,6 @@
 use crate::animations::*;
 use crate::colors as c;
+use crate::colors::BlendMode;
 
 /// This value is used as a default value for the number of subdivisions on the const animations at
 /// the end of the file. Typically this number should be 1 for shorter strips, and higher as you add
@@ -23,6 +24,7 @@ pub const BG_OFF: background::Parameters = background::Parameters {
     rainbow_dir: RainbowDir::Forward,
     duration_ns: 0,
     subdivisions: DEFAULT_NUMBER_OF_SUBDIVISIONS,
+    blend_mode: BlendMode::Replace,
 };
 
 /// This foreground parameter struct can be used to turn off all foreground effects
@@ -35,6 +37,7 @@ pub const FG_OFF: foreground::Parameters = foreground::Parameters {
     step_time_ns: 0,
     subdivisions: DEFAULT_NUMBER_OF_SUBDIVISIONS,
     pixels_per_pixel_group: DEFAULT_NUMBER_OF_PIXELS_PER_MARQUEE_PIP,
+    blend_mode: BlendMode::Replace,
 };
 
 /// This global trigger parameter struct can be used to turn off all trigger effects.
@@ -59,6 +62,7 @@ pub const BG_DEFAULT: background::Parameters = background::Parameters {
     rainbow_dir: RainbowDir::Forward,
     duration_ns: 15_000_000_000,
     subdivisions: 0,
+    blend_mode: BlendMode::Replace,
 };
 
 /// This is an animation foreground struct used for the default values unless modified
@@ -71,6 +75,7 @@ pub const FG_DEFAULT: foreground::Parameters = foreground::Parameters {
     step_time_ns: 1_000_000_000,
     subdivisions: DEFAULT_NUMBER_OF_SUBDIVISIONS,
     pixels_per_pixel_group: 1,
+    blend_mode: BlendMode::Replace,
 };
 
 /// This is an animation trigger struct used for the default values unless modified
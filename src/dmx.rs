@@ -0,0 +1,414 @@
+//! This receives DMX512 data from lighting consoles over the network, using either E1.31 (sACN) or
+//! Art-Net, and maps the channels onto pixels. The parsers work on a UDP payload, so any network
+//! stack can be used to receive the packets:
+//!
+//! - `e131::parse()` handles packets sent to port `e131::PORT`, either multicast to
+//!   `e131::multicast_address()` or unicast.
+//! - `artnet::parse()` handles packets sent to port `artnet::PORT`.
+//!
+//! Both produce a `DmxPacket`, which a `DmxInput` writes into a slice of pixels. Writing into the
+//! color buffer of a LogicalStrip drives the strip directly, but every `tick()` clears that buffer
+//! before drawing the animations, so the main loop has to stop ticking the LightingController
+//! while the console has control, the same way as for `wled`. Writing into the segment of an
+//! `ExternalAnimation` instead makes the console's output a layer composited with local animations
+//! while the controller keeps ticking:
+//!
+//! ```ignore
+//! if let Ok(packet) = dmx::e131::parse(payload) {
+//!     let external = &mut controller.animations[2];
+//!     if input.receive(&packet, now_ns, external.segment_mut()) {
+//!         external.mark_received();
+//!     }
+//! }
+//! ```
+//!
+//! Rather than sending pixels, a console can also control an animation's parameters through a
+//! `personality::Personality`, the way it would control any other fixture.
+
+pub mod artnet;
+pub mod e131;
+pub mod personality;
+
+use crate::colors::{ColorOrder, Pixel};
+
+/// The number of channels in a DMX universe.
+pub const UNIVERSE_SIZE: usize = 512;
+
+/// The start code of ordinary dimmer data. Packets with any other start code carry something else,
+/// such as text or test data, and are ignored.
+pub const NULL_START_CODE: u8 = 0;
+
+/// The priority of packets from sources that don't send one, such as Art-Net.
+pub const DEFAULT_PRIORITY: u8 = 100;
+
+/// A source that sends nothing for this long is treated as gone, so that a lower priority source
+/// can take over its universe. This is E1.31's network data loss timeout.
+pub const SOURCE_TIMEOUT_NS: u64 = 2_500_000_000;
+
+/// Packets whose sequence number is up to this far behind the last one are treated as arriving
+/// out of order, and are dropped. Anything further behind is taken as the source restarting.
+const SEQUENCE_WINDOW: i8 = 20;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DmxError {
+    /// The packet ends before its headers do.
+    TooShort,
+    /// The packet doesn't start with the protocol's identifier, so it's something else entirely.
+    BadHeader,
+    /// The packet is from the right protocol, but isn't DMX data, such as a poll or sync packet.
+    UnsupportedPacket,
+    /// The channel count is bigger than a universe, or than the packet itself.
+    InvalidLength,
+}
+
+/// A universe of DMX data, as received from either protocol.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct DmxPacket<'p> {
+    pub universe: u16,
+    /// `None` for sources that don't number their packets.
+    pub sequence: Option<u8>,
+    /// From 0 to 200. When several sources send the same universe, the highest priority wins.
+    pub priority: u8,
+    pub start_code: u8,
+    /// Preview data is meant for visualisers, not for live output.
+    pub is_preview: bool,
+    /// Set on the last packets a source sends before it stops sending the universe.
+    pub is_terminated: bool,
+    /// Identifies the source, so that packets from different sources at the same priority aren't
+    /// mistaken for each other. E1.31 sources send their CID. Art-Net packets don't say who sent
+    /// them, so this is `None` unless the caller fills it in, such as with the sender's address.
+    pub source: Option<[u8; 16]>,
+    /// The channel values, starting from channel 1. This can be shorter than a full universe.
+    pub data: &'p [u8],
+}
+
+#[derive(Copy, Clone, Default)]
+struct UniverseState {
+    last_packet_ns: Option<u64>,
+    sequence: Option<u8>,
+    priority: u8,
+    source: Option<[u8; 16]>,
+}
+
+/// This maps up to `N_UNIVERSES` consecutive universes onto a run of pixels, starting from
+/// `start_channel` (counted from 1) of `start_universe`. Each pixel takes one channel for each
+/// entry in `color_order`, and the pixels carry on into the next universe after
+/// `channels_per_universe` channels. By default that's as many whole pixels as fit in a universe,
+/// so that no pixel is split across two universes, which is what most consoles expect.
+///
+/// Packets are dropped if they arrive out of order, are preview data, or come from a lower priority
+/// source than the one currently sending their universe. Sources at the same priority aren't
+/// merged, so the most recent packet wins. Sources are told apart by `DmxPacket::source`, and only
+/// packets from the source that sent the universe last are checked for their order.
+pub struct DmxInput<const N_UNIVERSES: usize> {
+    pub start_universe: u16,
+    pub start_channel: u16,
+    pub color_order: ColorOrder,
+    pub channels_per_universe: u16,
+    universes: [UniverseState; N_UNIVERSES],
+}
+
+impl<const N_UNIVERSES: usize> DmxInput<N_UNIVERSES> {
+    pub fn new(start_universe: u16, color_order: ColorOrder) -> Self {
+        let pixels_per_universe = UNIVERSE_SIZE / color_order.len().max(1);
+        DmxInput {
+            start_universe,
+            start_channel: 1,
+            color_order,
+            channels_per_universe: (pixels_per_universe * color_order.len()) as u16,
+            universes: [UniverseState::default(); N_UNIVERSES],
+        }
+    }
+
+    pub fn set_start_channel(mut self, start_channel: u16) -> Self {
+        self.start_channel = start_channel;
+        self
+    }
+
+    /// Sets how many channels of each universe are used before moving on to the next one. Use
+    /// `UNIVERSE_SIZE` to pack the channels of every universe full, splitting pixels across them.
+    pub fn set_channels_per_universe(mut self, channels: u16) -> Self {
+        self.channels_per_universe = channels;
+        self
+    }
+
+    /// Returns how many universes it takes to hold `led_count` pixels.
+    pub fn universes_needed(&self, led_count: usize) -> usize {
+        let channels = self.first_channel() + led_count * self.color_order.len();
+        channels.div_ceil(self.channels_per_universe.max(1) as usize)
+    }
+
+    /// Returns true if any universe has been received from within the last `SOURCE_TIMEOUT_NS`.
+    pub fn is_receiving(&self, now_ns: u64) -> bool {
+        self.universes
+            .iter()
+            .any(|state| !is_timed_out(state, now_ns))
+    }
+
+    /// Writes the channels of a packet arriving at `now_ns` into `pixels`, returning true if the
+    /// packet was accepted. Only the channels in the packet are changed, so the rest of the pixels
+    /// keep their colors.
+    pub fn receive<P: Pixel>(&mut self, packet: &DmxPacket, now_ns: u64, pixels: &mut [P]) -> bool {
+        let is_accepted = self.accept(packet, now_ns);
+        if is_accepted {
+            let index = (packet.universe - self.start_universe) as usize;
+            self.write_channels(index, packet.data, pixels);
+        }
+        is_accepted
+    }
+
+    /// Returns true if a packet arriving at `now_ns` is for one of this input's universes and
+    /// shouldn't be dropped, keeping track of its source. `receive()` calls this itself, so it's
+    /// only needed for packets that aren't pixel data, such as those for a `Personality`.
+    pub fn accept(&mut self, packet: &DmxPacket, now_ns: u64) -> bool {
+        let Some(index) = packet.universe.checked_sub(self.start_universe) else {
+            return false;
+        };
+        let Some(state) = self.universes.get_mut(index as usize) else {
+            return false;
+        };
+        if packet.start_code != NULL_START_CODE || packet.is_preview {
+            return false;
+        }
+        // Each source numbers its own packets, so a source taking over starts its own sequence:
+        let is_same_source = packet.priority == state.priority && packet.source == state.source;
+        if packet.is_terminated {
+            // Let any other source take over straight away, unless some other source that isn't
+            // any lower priority has the universe:
+            if is_timed_out(state, now_ns) || is_same_source || packet.priority > state.priority {
+                *state = UniverseState::default();
+            }
+            return false;
+        }
+
+        if !is_timed_out(state, now_ns) {
+            if packet.priority < state.priority {
+                return false;
+            }
+            if let (Some(last), Some(sequence)) = (state.sequence, packet.sequence) {
+                let difference = sequence.wrapping_sub(last) as i8;
+                if is_same_source && difference <= 0 && difference > -SEQUENCE_WINDOW {
+                    return false;
+                }
+            }
+        }
+        *state = UniverseState {
+            last_packet_ns: Some(now_ns),
+            sequence: packet.sequence,
+            priority: packet.priority,
+            source: packet.source,
+        };
+        true
+    }
+
+    fn first_channel(&self) -> usize {
+        self.start_channel.saturating_sub(1) as usize
+    }
+
+    fn write_channels<P: Pixel>(&self, index: usize, data: &[u8], pixels: &mut [P]) {
+        let channels_per_pixel = self.color_order.len();
+        let universe_size = self.channels_per_universe as usize;
+        if channels_per_pixel == 0 {
+            return;
+        }
+
+        for (channel, &value) in data.iter().enumerate().take(universe_size) {
+            let Some(channel) = (index * universe_size + channel).checked_sub(self.first_channel())
+            else {
+                continue;
+            };
+            let Some(pixel) = pixels.get_mut(channel / channels_per_pixel) else {
+                break;
+            };
+            *pixel = pixel.with_channel(self.color_order[channel % channels_per_pixel], value);
+        }
+    }
+}
+
+fn is_timed_out(state: &UniverseState, now_ns: u64) -> bool {
+    state
+        .last_packet_ns
+        .is_none_or(|last| now_ns.saturating_sub(last) > SOURCE_TIMEOUT_NS)
+}
+
+fn read_u16_be(bytes: &[u8], index: usize) -> u16 {
+    u16::from_be_bytes([bytes[index], bytes[index + 1]])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::colors::order;
+    use rgb::RGB8;
+
+    fn packet(universe: u16, sequence: Option<u8>, priority: u8) -> DmxPacket<'static> {
+        DmxPacket {
+            universe,
+            sequence,
+            priority,
+            start_code: NULL_START_CODE,
+            is_preview: false,
+            is_terminated: false,
+            source: None,
+            data: &[255, 0, 0, 0, 128, 64, 1],
+        }
+    }
+
+    fn from(source: u8, sequence: u8) -> DmxPacket<'static> {
+        DmxPacket {
+            source: Some([source; 16]),
+            ..packet(1, Some(sequence), 100)
+        }
+    }
+
+    #[test]
+    fn writes_channels_into_pixels() {
+        let mut input = DmxInput::<2>::new(1, order::RGB);
+        let mut pixels = [RGB8::default(); 4];
+        assert!(input.receive(&packet(1, Some(1), 100), 0, &mut pixels));
+        assert_eq!(
+            pixels,
+            [
+                RGB8::new(255, 0, 0),
+                RGB8::new(0, 128, 64),
+                RGB8::new(1, 0, 0),
+                RGB8::default()
+            ]
+        );
+    }
+
+    #[test]
+    fn starts_from_the_start_channel() {
+        let mut input = DmxInput::<2>::new(1, order::GRB).set_start_channel(4);
+        let mut pixels = [RGB8::default(); 2];
+        assert!(input.receive(&packet(1, None, 100), 0, &mut pixels));
+        // Green comes first:
+        assert_eq!(pixels, [RGB8::new(128, 0, 64), RGB8::new(0, 1, 0)]);
+    }
+
+    #[test]
+    fn carries_pixels_on_into_the_next_universe() {
+        let mut input = DmxInput::<2>::new(1, order::RGB);
+        assert_eq!(input.channels_per_universe, 510);
+        assert_eq!(input.universes_needed(170), 1);
+        assert_eq!(input.universes_needed(171), 2);
+
+        let mut pixels = [RGB8::default(); 171];
+        assert!(input.receive(&packet(2, None, 100), 0, &mut pixels));
+        assert_eq!(pixels[169], RGB8::default());
+        assert_eq!(pixels[170], RGB8::new(255, 0, 0));
+    }
+
+    #[test]
+    fn ignores_other_universes_and_data() {
+        let mut input = DmxInput::<2>::new(1, order::RGB);
+        let mut pixels = [RGB8::default(); 4];
+        assert!(!input.receive(&packet(0, None, 100), 0, &mut pixels));
+        assert!(!input.receive(&packet(3, None, 100), 0, &mut pixels));
+        let preview = DmxPacket {
+            is_preview: true,
+            ..packet(1, None, 100)
+        };
+        assert!(!input.receive(&preview, 0, &mut pixels));
+        let text = DmxPacket {
+            start_code: 0x17,
+            ..packet(1, None, 100)
+        };
+        assert!(!input.receive(&text, 0, &mut pixels));
+        assert_eq!(pixels, [RGB8::default(); 4]);
+        assert!(!input.is_receiving(0));
+    }
+
+    #[test]
+    fn drops_packets_inside_the_sequence_window() {
+        let mut input = DmxInput::<1>::new(1, order::RGB);
+        let mut accept = |sequence| input.accept(&packet(1, Some(sequence), 100), 0);
+        assert!(accept(10));
+        assert!(!accept(10));
+        assert!(!accept(9));
+        assert!(accept(11));
+        assert!(!accept(11u8.wrapping_sub(19)));
+        // Anything further behind is the source starting over:
+        assert!(accept(11u8.wrapping_sub(20)));
+        assert!(accept(255));
+        assert!(accept(0));
+    }
+
+    #[test]
+    fn checks_the_order_of_each_source_on_its_own() {
+        let mut input = DmxInput::<1>::new(1, order::RGB);
+        assert!(input.accept(&from(1, 50), 0));
+        assert!(input.accept(&from(2, 45), 0));
+        assert!(input.accept(&from(1, 51), 0));
+        assert!(input.accept(&from(2, 46), 0));
+        assert!(!input.accept(&from(2, 46), 0));
+        assert!(!input.accept(&from(2, 40), 0));
+
+        // Another source at the same priority can't sign off for the one sending:
+        let terminated = DmxPacket {
+            is_terminated: true,
+            ..from(1, 52)
+        };
+        assert!(!input.accept(&terminated, 0));
+        assert!(input.is_receiving(0));
+        assert!(!input.accept(&packet(1, Some(1), 50), 0));
+    }
+
+    #[test]
+    fn always_accepts_unnumbered_packets() {
+        let mut input = DmxInput::<1>::new(1, order::RGB);
+        assert!(input.accept(&packet(1, None, 100), 0));
+        assert!(input.accept(&packet(1, None, 100), 0));
+    }
+
+    #[test]
+    fn follows_the_highest_priority_source() {
+        let mut input = DmxInput::<1>::new(1, order::RGB);
+        assert!(input.accept(&packet(1, Some(1), 100), 0));
+        assert!(!input.accept(&packet(1, Some(2), 50), 1_000));
+        // A higher priority source takes over with its own sequence:
+        assert!(input.accept(&packet(1, Some(200), 150), 2_000));
+        assert!(!input.accept(&packet(1, Some(3), 100), 3_000));
+        assert!(!input.accept(&packet(1, Some(4), 100), 2_000 + SOURCE_TIMEOUT_NS));
+        // Until it times out:
+        assert!(input.accept(&packet(1, Some(5), 100), 2_001 + SOURCE_TIMEOUT_NS));
+    }
+
+    #[test]
+    fn releases_terminated_universes_straight_away() {
+        let mut input = DmxInput::<1>::new(1, order::RGB);
+        assert!(input.accept(&packet(1, Some(1), 150), 0));
+        let terminated = DmxPacket {
+            is_terminated: true,
+            ..packet(1, Some(2), 150)
+        };
+        assert!(!input.accept(&terminated, 1_000));
+        assert!(!input.is_receiving(1_000));
+        assert!(input.accept(&packet(1, Some(9), 50), 2_000));
+    }
+
+    #[test]
+    fn ignores_lower_priority_sources_signing_off() {
+        let mut input = DmxInput::<1>::new(1, order::RGB);
+        assert!(input.accept(&packet(1, Some(1), 150), 0));
+        let terminated = DmxPacket {
+            is_terminated: true,
+            ..packet(1, Some(1), 100)
+        };
+        assert!(!input.accept(&terminated, 1_000));
+        assert!(input.is_receiving(1_000));
+        assert!(!input.accept(&packet(1, Some(2), 120), 2_000));
+        assert!(input.accept(&packet(1, Some(2), 150), 3_000));
+    }
+
+    #[test]
+    fn times_out_sources() {
+        let mut input = DmxInput::<2>::new(1, order::RGB);
+        assert!(!input.is_receiving(0));
+        assert!(input.accept(&packet(2, Some(1), 100), 1_000));
+        assert!(input.is_receiving(1_000 + SOURCE_TIMEOUT_NS));
+        assert!(!input.is_receiving(1_001 + SOURCE_TIMEOUT_NS));
+        // A source that comes back after timing out can start from any sequence:
+        assert!(input.accept(&packet(2, Some(1), 100), 1_001 + SOURCE_TIMEOUT_NS));
+    }
+}
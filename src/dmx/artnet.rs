@@ -0,0 +1,127 @@
+//! Art-Net sends DMX in ArtDmx packets, addressed by a 15 bit port address made of a net, sub-net
+//! and universe, which is treated here as a single universe number counted from 0.
+//!
+//! Only ArtDmx is handled. Consoles that discover nodes with ArtPoll won't find the controller on
+//! their own, so they need to be set to send to its address, or to broadcast.
+
+use crate::dmx::{
+    read_u16_be, DmxError, DmxPacket, DEFAULT_PRIORITY, NULL_START_CODE, UNIVERSE_SIZE,
+};
+
+pub const PORT: u16 = 6454;
+
+const IDENTIFIER: [u8; 8] = *b"Art-Net\0";
+const OP_DMX: u16 = 0x5000;
+
+const OP_CODE: usize = 8;
+const SEQUENCE: usize = 12;
+const SUB_UNI: usize = 14;
+const NET: usize = 15;
+const LENGTH: usize = 16;
+const DATA: usize = 18;
+
+/// Parses an ArtDmx packet. Every other kind of Art-Net packet returns
+/// `DmxError::UnsupportedPacket`.
+pub fn parse(bytes: &[u8]) -> Result<DmxPacket<'_>, DmxError> {
+    if !bytes.starts_with(&IDENTIFIER) {
+        return Err(DmxError::BadHeader);
+    }
+    if bytes.len() < OP_CODE + 2 {
+        return Err(DmxError::TooShort);
+    }
+    // Unlike every other field, the op code is little endian:
+    if u16::from_le_bytes([bytes[OP_CODE], bytes[OP_CODE + 1]]) != OP_DMX {
+        return Err(DmxError::UnsupportedPacket);
+    }
+    if bytes.len() < DATA {
+        return Err(DmxError::TooShort);
+    }
+
+    let length = read_u16_be(bytes, LENGTH) as usize;
+    if length > UNIVERSE_SIZE || DATA + length > bytes.len() {
+        return Err(DmxError::InvalidLength);
+    }
+
+    Ok(DmxPacket {
+        universe: ((bytes[NET] & 0x7F) as u16) << 8 | bytes[SUB_UNI] as u16,
+        // A sequence of 0 means the source doesn't number its packets:
+        sequence: Some(bytes[SEQUENCE]).filter(|&sequence| sequence != 0),
+        priority: DEFAULT_PRIORITY,
+        start_code: NULL_START_CODE,
+        is_preview: false,
+        is_terminated: false,
+        source: None,
+        data: &bytes[DATA..DATA + length],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // An ArtDmx packet with the first six channels of net 1, sub-net 2, universe 1:
+    const CAPTURE: [u8; 24] = [
+        // ID, OpDmx, protocol version 14, sequence 3, physical port, sub-net and universe, net,
+        // length 6, then the channels
+        0x41, 0x72, 0x74, 0x2d, 0x4e, 0x65, 0x74, 0x00, 0x00, 0x50, 0x00, 0x0e, 0x03, 0x00, 0x21,
+        0x01, 0x00, 0x06, 0xff, 0x00, 0x00, 0x00, 0x80, 0x40,
+    ];
+
+    #[test]
+    fn parses_a_captured_packet() {
+        let packet = parse(&CAPTURE).unwrap();
+        assert_eq!(
+            packet,
+            DmxPacket {
+                universe: 0x121,
+                sequence: Some(3),
+                priority: DEFAULT_PRIORITY,
+                start_code: NULL_START_CODE,
+                is_preview: false,
+                is_terminated: false,
+                source: None,
+                data: &[0xff, 0x00, 0x00, 0x00, 0x80, 0x40],
+            }
+        );
+    }
+
+    #[test]
+    fn reads_unnumbered_packets_and_port_addresses() {
+        let mut bytes = CAPTURE;
+        bytes[SEQUENCE] = 0;
+        bytes[NET] = 0xFF;
+        let packet = parse(&bytes).unwrap();
+        assert_eq!(packet.sequence, None);
+        assert_eq!(packet.universe, 0x7F21);
+    }
+
+    #[test]
+    fn rejects_other_packets() {
+        assert_eq!(parse(b"ASC-E1.17\0\0\0"), Err(DmxError::BadHeader));
+        assert_eq!(parse(&[]), Err(DmxError::BadHeader));
+        // ArtPoll:
+        let poll = *b"Art-Net\0\x00\x20\x00\x0e\x00\x00";
+        assert_eq!(parse(&poll), Err(DmxError::UnsupportedPacket));
+    }
+
+    #[test]
+    fn rejects_truncated_packets() {
+        assert_eq!(parse(&CAPTURE[..9]), Err(DmxError::TooShort));
+        assert_eq!(parse(&CAPTURE[..DATA - 1]), Err(DmxError::TooShort));
+        assert_eq!(parse(&CAPTURE[..DATA + 5]), Err(DmxError::InvalidLength));
+    }
+
+    #[test]
+    fn rejects_bad_lengths() {
+        let with_length = |length: u16| {
+            let mut bytes = CAPTURE;
+            bytes[LENGTH..LENGTH + 2].copy_from_slice(&length.to_be_bytes());
+            parse(&bytes).map(|packet| packet.data.len())
+        };
+        assert_eq!(with_length(0), Ok(0));
+        assert_eq!(with_length(5), Ok(5));
+        assert_eq!(with_length(7), Err(DmxError::InvalidLength));
+        assert_eq!(with_length(513), Err(DmxError::InvalidLength));
+        assert_eq!(with_length(u16::MAX), Err(DmxError::InvalidLength));
+    }
+}
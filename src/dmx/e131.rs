@@ -0,0 +1,188 @@
+//! E1.31, also known as streaming ACN or sACN, sends each universe in its own UDP packet, made of
+//! a root layer, a framing layer and a DMP layer. Universes are numbered from 1 to 63999.
+
+use crate::dmx::{read_u16_be, DmxError, DmxPacket, UNIVERSE_SIZE};
+
+pub const PORT: u16 = 5568;
+
+const PREAMBLE: [u8; 4] = [0x00, 0x10, 0x00, 0x00];
+const ACN_IDENTIFIER: [u8; 12] = *b"ASC-E1.17\0\0\0";
+
+const VECTOR_ROOT_E131_DATA: [u8; 4] = [0, 0, 0, 0x04];
+const VECTOR_E131_DATA_PACKET: [u8; 4] = [0, 0, 0, 0x02];
+const VECTOR_DMP_SET_PROPERTY: u8 = 0x02;
+const DMP_ADDRESS_TYPE: u8 = 0xA1;
+
+// Offsets of the fields used from each layer:
+const ROOT_VECTOR: usize = 18;
+const CID: usize = 22;
+const FRAMING_VECTOR: usize = 40;
+const PRIORITY: usize = 108;
+const SEQUENCE: usize = 111;
+const OPTIONS: usize = 112;
+const UNIVERSE: usize = 113;
+const DMP_VECTOR: usize = 117;
+const DMP_ADDRESS_TYPE_OFFSET: usize = 118;
+const PROPERTY_COUNT: usize = 123;
+const START_CODE: usize = 125;
+
+const OPTION_PREVIEW: u8 = 0x80;
+const OPTION_TERMINATED: u8 = 0x40;
+
+/// Parses an E1.31 data packet. Universe sync and discovery packets return
+/// `DmxError::UnsupportedPacket`.
+pub fn parse(bytes: &[u8]) -> Result<DmxPacket<'_>, DmxError> {
+    if !bytes.starts_with(&PREAMBLE) || bytes.get(4..16) != Some(&ACN_IDENTIFIER) {
+        return Err(DmxError::BadHeader);
+    }
+    if bytes.len() < ROOT_VECTOR + 4 {
+        return Err(DmxError::TooShort);
+    }
+    if bytes[ROOT_VECTOR..ROOT_VECTOR + 4] != VECTOR_ROOT_E131_DATA {
+        return Err(DmxError::UnsupportedPacket);
+    }
+    if bytes.len() <= START_CODE {
+        return Err(DmxError::TooShort);
+    }
+    if bytes[FRAMING_VECTOR..FRAMING_VECTOR + 4] != VECTOR_E131_DATA_PACKET
+        || bytes[DMP_VECTOR] != VECTOR_DMP_SET_PROPERTY
+        || bytes[DMP_ADDRESS_TYPE_OFFSET] != DMP_ADDRESS_TYPE
+    {
+        return Err(DmxError::UnsupportedPacket);
+    }
+
+    // The property values are the start code followed by the channels:
+    let count = read_u16_be(bytes, PROPERTY_COUNT) as usize;
+    if count == 0 || count > UNIVERSE_SIZE + 1 || START_CODE + count > bytes.len() {
+        return Err(DmxError::InvalidLength);
+    }
+
+    let options = bytes[OPTIONS];
+    Ok(DmxPacket {
+        universe: read_u16_be(bytes, UNIVERSE),
+        sequence: Some(bytes[SEQUENCE]),
+        priority: bytes[PRIORITY],
+        start_code: bytes[START_CODE],
+        is_preview: options & OPTION_PREVIEW != 0,
+        is_terminated: options & OPTION_TERMINATED != 0,
+        source: read_cid(bytes),
+        data: &bytes[START_CODE + 1..START_CODE + count],
+    })
+}
+
+fn read_cid(bytes: &[u8]) -> Option<[u8; 16]> {
+    bytes.get(CID..CID + 16)?.try_into().ok()
+}
+
+/// Returns the IPv4 multicast group a universe is sent to, which is 239.255.x.y where x and y are
+/// the high and low bytes of the universe.
+pub fn multicast_address(universe: u16) -> [u8; 4] {
+    let [high, low] = universe.to_be_bytes();
+    [239, 255, high, low]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A data packet with the first six channels of universe 1, laid out byte for byte as sent:
+    const CAPTURE: [u8; 132] = [
+        // Root layer: preamble and postamble sizes, ACN identifier, flags and length, vector, CID
+        0x00, 0x10, 0x00, 0x00, 0x41, 0x53, 0x43, 0x2d, 0x45, 0x31, 0x2e, 0x31, 0x37, 0x00, 0x00,
+        0x00, 0x70, 0x74, 0x00, 0x00, 0x00, 0x04, 0x5c, 0x0e, 0x24, 0x91, 0x3f, 0x6a, 0x4b, 0x12,
+        0x8e, 0x07, 0xd1, 0x3a, 0x62, 0x90, 0xaf, 0x1c,
+        // Framing layer: flags and length, vector, source name, priority 100, sync address,
+        // sequence 7, options, universe 1
+        0x70, 0x5e, 0x00, 0x00, 0x00, 0x02, 0x73, 0x41, 0x43, 0x4e, 0x56, 0x69, 0x65, 0x77, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x64, 0x00, 0x00, 0x07, 0x00,
+        0x00, 0x01,
+        // DMP layer: flags and length, vector, address type, first address, increment, 7 property
+        // values, start code 0, then the channels
+        0x70, 0x11, 0x02, 0xa1, 0x00, 0x00, 0x00, 0x01, 0x00, 0x07, 0x00, 0xff, 0x00, 0x00, 0x00,
+        0x80, 0x40,
+    ];
+
+    #[test]
+    fn parses_a_captured_packet() {
+        let packet = parse(&CAPTURE).unwrap();
+        assert_eq!(
+            packet,
+            DmxPacket {
+                universe: 1,
+                sequence: Some(7),
+                priority: 100,
+                start_code: 0,
+                is_preview: false,
+                is_terminated: false,
+                source: Some([
+                    0x5c, 0x0e, 0x24, 0x91, 0x3f, 0x6a, 0x4b, 0x12, 0x8e, 0x07, 0xd1, 0x3a, 0x62,
+                    0x90, 0xaf, 0x1c
+                ]),
+                data: &[0xff, 0x00, 0x00, 0x00, 0x80, 0x40],
+            }
+        );
+    }
+
+    #[test]
+    fn reads_the_options() {
+        let mut bytes = CAPTURE;
+        bytes[OPTIONS] = OPTION_PREVIEW;
+        assert!(parse(&bytes).unwrap().is_preview);
+        bytes[OPTIONS] = OPTION_TERMINATED;
+        let packet = parse(&bytes).unwrap();
+        assert!(packet.is_terminated && !packet.is_preview);
+    }
+
+    #[test]
+    fn rejects_other_protocols() {
+        assert_eq!(parse(b"Art-Net\0\x00\x50"), Err(DmxError::BadHeader));
+        assert_eq!(parse(&[]), Err(DmxError::BadHeader));
+        let mut bytes = CAPTURE;
+        bytes[4] = b'B';
+        assert_eq!(parse(&bytes), Err(DmxError::BadHeader));
+    }
+
+    #[test]
+    fn rejects_other_packets() {
+        // Universe sync:
+        let mut bytes = CAPTURE;
+        bytes[ROOT_VECTOR + 3] = 0x08;
+        assert_eq!(parse(&bytes), Err(DmxError::UnsupportedPacket));
+        let mut bytes = CAPTURE;
+        bytes[FRAMING_VECTOR + 3] = 0x01;
+        assert_eq!(parse(&bytes), Err(DmxError::UnsupportedPacket));
+        let mut bytes = CAPTURE;
+        bytes[DMP_ADDRESS_TYPE_OFFSET] = 0xA0;
+        assert_eq!(parse(&bytes), Err(DmxError::UnsupportedPacket));
+    }
+
+    #[test]
+    fn rejects_truncated_packets() {
+        assert_eq!(parse(&CAPTURE[..20]), Err(DmxError::TooShort));
+        assert_eq!(parse(&CAPTURE[..START_CODE]), Err(DmxError::TooShort));
+        assert_eq!(parse(&CAPTURE[..130]), Err(DmxError::InvalidLength));
+    }
+
+    #[test]
+    fn rejects_bad_property_counts() {
+        let with_count = |count: u16| {
+            let mut bytes = CAPTURE;
+            bytes[PROPERTY_COUNT..PROPERTY_COUNT + 2].copy_from_slice(&count.to_be_bytes());
+            parse(&bytes).map(|packet| packet.data.len())
+        };
+        assert_eq!(with_count(1), Ok(0));
+        assert_eq!(with_count(0), Err(DmxError::InvalidLength));
+        assert_eq!(with_count(8), Err(DmxError::InvalidLength));
+        assert_eq!(with_count(514), Err(DmxError::InvalidLength));
+        assert_eq!(with_count(u16::MAX), Err(DmxError::InvalidLength));
+    }
+
+    #[test]
+    fn finds_multicast_addresses() {
+        assert_eq!(multicast_address(1), [239, 255, 0, 1]);
+        assert_eq!(multicast_address(63999), [239, 255, 249, 255]);
+    }
+}
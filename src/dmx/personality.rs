@@ -0,0 +1,213 @@
+//! A Personality lets a console control an animation like any other DMX fixture, with each channel
+//! setting one of its parameters. For example:
+//!
+//! ```ignore
+//! let functions = [
+//!     ChannelFunction::BgMode(&BG_MODES),
+//!     ChannelFunction::BgRainbow(&colors::RAINBOW_ARRAY),
+//!     ChannelFunction::BgDurationNs { min_ns: 20_000_000_000, max_ns: 500_000_000 },
+//!     ChannelFunction::Offset(AnimationType::Background),
+//!     ChannelFunction::Trigger(TRIGGER),
+//! ];
+//! let mut personality = Personality::new(functions, 0).set_start_channel(1);
+//!
+//! if input.accept(&packet, now_ns) {
+//!     personality.update(packet.data, &mut controller);
+//! }
+//! ```
+//!
+//! Parameters are only applied when their channel changes, so a console sending the same frame
+//! over and over doesn't keep restarting the animation.
+
+use crate::animations::{background, foreground, trigger, AnimationType, RainbowDir, MAX_OFFSET};
+use crate::colors::Pixel;
+use crate::LightingController;
+use rgb::RGB8;
+
+/// Trigger channels fire when they cross this value on the way up.
+pub const TRIGGER_THRESHOLD: u8 = 128;
+
+/// This is what a single channel of a personality sets.
+///
+/// Channels that pick from a list use the channel value as the index into it, so 0 is the first
+/// entry, 1 is the second, and so on. Values past the end of the list pick the last entry.
+/// Durations are scaled linearly from `min_ns` at a value of 0 to `max_ns` at a value of 255, so
+/// setting `min_ns` above `max_ns` makes higher values faster, as a speed channel usually does.
+#[derive(Copy, Clone)]
+pub enum ChannelFunction<'a, P = RGB8> {
+    /// The channel is skipped, such as to leave room for a function the fixture doesn't have.
+    Unused,
+    BgMode(&'a [background::Mode<P>]),
+    FgMode(&'a [foreground::Mode<P>]),
+    /// Picks the background rainbow from a list, such as `colors::RAINBOW_ARRAY`.
+    BgRainbow(&'a [&'a [P]]),
+    FgRainbow(&'a [&'a [P]]),
+    /// Picks the rainbow triggers fade through and step through.
+    TriggerRainbow(&'a [&'a [P]]),
+    /// Sets the offset of one part of the animation across its whole range.
+    Offset(AnimationType),
+    LayerBrightness,
+    LayerOpacity,
+    BgDurationNs {
+        min_ns: u64,
+        max_ns: u64,
+    },
+    FgDurationNs {
+        min_ns: u64,
+        max_ns: u64,
+    },
+    FgStepTimeNs {
+        min_ns: u64,
+        max_ns: u64,
+    },
+    TriggerDurationNs {
+        min_ns: u64,
+        max_ns: u64,
+    },
+    /// Fires the trigger each time the channel rises past `TRIGGER_THRESHOLD`, like a flash button.
+    Trigger(trigger::Parameters<P>),
+}
+
+/// This applies the channels of a DMX universe to the animation at `slot`, starting from
+/// `start_channel`, which is counted from 1 like a fixture's DMX address.
+pub struct Personality<'a, const N_CHANNELS: usize, P = RGB8> {
+    pub slot: usize,
+    pub start_channel: u16,
+    functions: [ChannelFunction<'a, P>; N_CHANNELS],
+    values: [Option<u8>; N_CHANNELS],
+}
+
+impl<'a, const N_CHANNELS: usize, P: Pixel> Personality<'a, N_CHANNELS, P> {
+    pub fn new(functions: [ChannelFunction<'a, P>; N_CHANNELS], slot: usize) -> Self {
+        Personality {
+            slot,
+            start_channel: 1,
+            functions,
+            values: [None; N_CHANNELS],
+        }
+    }
+
+    pub fn set_start_channel(mut self, start_channel: u16) -> Self {
+        self.start_channel = start_channel;
+        self
+    }
+
+    pub fn functions(&self) -> &[ChannelFunction<'a, P>] {
+        &self.functions
+    }
+
+    /// Forgets the last values received, so every channel is applied again by the next update.
+    /// This is useful after something else has changed the animation, such as a Playlist.
+    pub fn reset(&mut self) {
+        self.values = [None; N_CHANNELS];
+    }
+
+    /// Applies every channel that has changed since the last update, where `data` holds the
+    /// universe's channels starting from channel 1. Channels past the end of `data` are left
+    /// alone. Returns true if anything was applied.
+    pub fn update<const N_ANI: usize>(
+        &mut self,
+        data: &[u8],
+        controller: &mut LightingController<'a, N_ANI, P>,
+    ) -> bool {
+        let first = self.start_channel.saturating_sub(1) as usize;
+        let channels = data.get(first..).unwrap_or(&[]);
+
+        let mut is_changed = false;
+        for ((function, previous), &value) in self
+            .functions
+            .iter()
+            .zip(self.values.iter_mut())
+            .zip(channels)
+        {
+            if *previous == Some(value) {
+                continue;
+            }
+            let last_value = previous.replace(value);
+            apply(function, last_value, value, self.slot, controller);
+            is_changed = true;
+        }
+        is_changed
+    }
+}
+
+fn apply<'a, const N_ANI: usize, P: Pixel>(
+    function: &ChannelFunction<'a, P>,
+    last_value: Option<u8>,
+    value: u8,
+    slot: usize,
+    controller: &mut LightingController<'a, N_ANI, P>,
+) {
+    let frame_rate = controller.frame_rate;
+    let duration = |min_ns: u64, max_ns: u64| {
+        let range = max_ns as i128 - min_ns as i128;
+        (min_ns as i128 + range * value as i128 / u8::MAX as i128) as u64
+    };
+    let animation = &mut controller.animations[slot];
+    let mut layer = animation.layer();
+
+    match *function {
+        ChannelFunction::Unused => {}
+        ChannelFunction::BgMode(modes) => {
+            if let Some(&mode) = pick(modes, value) {
+                animation.update_bg_mode(mode);
+            }
+        }
+        ChannelFunction::FgMode(modes) => {
+            if let Some(&mode) = pick(modes, value) {
+                animation.update_fg_mode(mode);
+            }
+        }
+        ChannelFunction::BgRainbow(rainbows) => {
+            if let Some(&rainbow) = pick(rainbows, value) {
+                animation.update_bg_rainbow(rainbow, RainbowDir::Forward);
+            }
+        }
+        ChannelFunction::FgRainbow(rainbows) => {
+            if let Some(&rainbow) = pick(rainbows, value) {
+                animation.update_fg_rainbow(rainbow, RainbowDir::Forward);
+            }
+        }
+        ChannelFunction::TriggerRainbow(rainbows) => {
+            if let Some(&rainbow) = pick(rainbows, value) {
+                animation.update_trig_fade_rainbow(rainbow, RainbowDir::Forward);
+                animation.update_trig_incremental_rainbow(rainbow, RainbowDir::Forward);
+            }
+        }
+        ChannelFunction::Offset(a_type) => {
+            let offset = (MAX_OFFSET as u32 * value as u32 / u8::MAX as u32) as u16;
+            animation.set_offset(a_type, offset);
+        }
+        ChannelFunction::LayerBrightness => {
+            layer.brightness = value;
+            animation.update_layer(layer);
+        }
+        ChannelFunction::LayerOpacity => {
+            layer.opacity = value;
+            animation.update_layer(layer);
+        }
+        ChannelFunction::BgDurationNs { min_ns, max_ns } => {
+            animation.update_bg_duration_ns(duration(min_ns, max_ns), frame_rate)
+        }
+        ChannelFunction::FgDurationNs { min_ns, max_ns } => {
+            animation.update_fg_duration_ns(duration(min_ns, max_ns), frame_rate)
+        }
+        ChannelFunction::FgStepTimeNs { min_ns, max_ns } => {
+            animation.update_fg_step_time_ns(duration(min_ns, max_ns), frame_rate)
+        }
+        ChannelFunction::TriggerDurationNs { min_ns, max_ns } => {
+            animation.update_trig_duration_ns(duration(min_ns, max_ns), frame_rate)
+        }
+        ChannelFunction::Trigger(params) => {
+            // The first value received only arms the trigger, so it can't fire on start up:
+            if last_value.is_some_and(|last| last < TRIGGER_THRESHOLD) && value >= TRIGGER_THRESHOLD
+            {
+                controller.trigger(slot, &params);
+            }
+        }
+    }
+}
+
+fn pick<T>(list: &[T], value: u8) -> Option<&T> {
+    list.get(value as usize).or(list.last())
+}
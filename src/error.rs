@@ -0,0 +1,66 @@
+//! The errors returned by the fallible `try_*` functions and the validation pass, for code that
+//! would rather handle a bad index or configuration than panic in the middle of a frame. It's
+//! usually enough to validate once at setup with `LightingController::validate()`.
+
+use crate::animations::AnimationType;
+
+/// These are the ways setting up or controlling the lighting can go wrong.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// An animation slot or strip index is past the end of the `len` items there are.
+    IndexOutOfRange { index: usize, len: usize },
+    /// The pixel at `segment_index` of an animation is translated to `strip_index`, past the end
+    /// of the strip's `strip_len` leds.
+    TranslationOutOfBounds {
+        segment_index: usize,
+        strip_index: usize,
+        strip_len: usize,
+    },
+    /// The rainbow of the background, foreground or triggers has no colors, so it's drawn unlit.
+    EmptyRainbow(AnimationType),
+    /// A rainbow is repeated by its subdivisions into more colors than there are offsets, so they
+    /// run together.
+    TooManyRainbowColors(AnimationType),
+    /// A trigger's fade in and fade out times are too short to last a single frame, so it only
+    /// flickers on the frame it starts.
+    ZeroLengthTrigger,
+    /// An animation has no pixels.
+    EmptySegment,
+    /// A logical strip has no leds.
+    EmptyStrip,
+}
+
+/// Returns `index` if it's less than `len`, so it's safe to index with.
+pub(crate) fn check_index(index: usize, len: usize) -> Result<usize, Error> {
+    if index < len {
+        Ok(index)
+    } else {
+        Err(Error::IndexOutOfRange { index, len })
+    }
+}
+
+/// Checks that a segment has pixels, and that each of them is translated onto a strip of
+/// `strip_len` leds. Translation array entries past the end of the segment aren't used, so they
+/// aren't checked.
+pub(crate) fn check_segment(
+    segment_len: usize,
+    translation_array: &[usize],
+    strip_len: usize,
+) -> Result<(), Error> {
+    if segment_len == 0 {
+        return Err(Error::EmptySegment);
+    }
+    let out_of_bounds = translation_array
+        .iter()
+        .take(segment_len)
+        .enumerate()
+        .find(|(_, &strip_index)| strip_index >= strip_len);
+    match out_of_bounds {
+        Some((segment_index, &strip_index)) => Err(Error::TranslationOutOfBounds {
+            segment_index,
+            strip_index,
+            strip_len,
+        }),
+        None => Ok(()),
+    }
+}
@@ -0,0 +1,932 @@
+//! This handles the commands Home Assistant sends to an MQTT light using its JSON schema, so an
+//! animation slot can be switched on and off, dimmed, colored and given an effect from a dashboard:
+//!
+//! ```text
+//! {"state": "ON", "brightness": 180, "color": {"r": 255, "g": 120, "b": 0}, "transition": 2}
+//! {"state": "ON", "effect": "rainbow"}
+//! {"state": "OFF", "transition": 0.5}
+//! ```
+//!
+//! Effects are named `AnimationParameters` presets. A color replaces the rainbows of the current
+//! effect with a single color, so an effect with a `Solid` background shows it as a plain color,
+//! while picking an effect goes back to its own rainbows. Rainbows have to outlive the controller,
+//! so colors are matched to the closest color in a palette, such as `COLOR_CUBE`, and a single
+//! color rainbow is borrowed from it.
+//!
+//! On, off and brightness set the layer brightness of the slot. Transitions fade the brightness,
+//! and crossfade to new effects and colors when the light has a spare animation, like a Playlist.
+//! The state to publish back is written by `HomeAssistantLight::write_state()`. Everything is
+//! parsed from and written to plain byte buffers, so any MQTT client can be used:
+//!
+//! ```ignore
+//! if let Some(payload) = mqtt.receive("lights/strip/set") {
+//!     if light.handle(payload, &mut controller).is_ok() {
+//!         let len = light.encode_state(&mut buffer).unwrap();
+//!         mqtt.publish("lights/strip/state", &buffer[..len]);
+//!     }
+//! }
+//! light.update(&mut controller);
+//! ```
+
+use crate::animations::{Animatable, AnimationParameters};
+use crate::colors::Pixel;
+use crate::transition::TransitionStyle;
+use crate::utility::convert_ns_to_frames;
+use crate::LightingController;
+use core::fmt::{self, Write};
+use rgb::RGB8;
+
+/// Values and objects nested deeper than this in a command are rejected.
+const MAX_DEPTH: usize = 8;
+
+/// Transitions longer than this, in seconds, are rejected. It's the longest Home Assistant sends.
+const MAX_TRANSITION_SECONDS: f32 = 6553.0;
+
+/// Every color with channels that are a multiple of 51, which is 6 levels of each channel. Home
+/// Assistant sends the brightness separately from the color, so this gets within 26 of each
+/// channel of any color it sends.
+pub const COLOR_CUBE: [RGB8; 216] = color_cube();
+
+const fn color_cube() -> [RGB8; 216] {
+    let mut cube = [RGB8 { r: 0, g: 0, b: 0 }; 216];
+    let mut i = 0;
+    while i < 216 {
+        cube[i] = RGB8 {
+            r: (i / 36) as u8 * 51,
+            g: (i / 6 % 6) as u8 * 51,
+            b: (i % 6) as u8 * 51,
+        };
+        i += 1;
+    }
+    cube
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum HomeAssistantError {
+    /// The command isn't valid JSON. This holds the byte offset of the problem.
+    Syntax(usize),
+    /// A value has the wrong type, or isn't one Home Assistant sends, such as a state other than
+    /// `ON` or `OFF`.
+    InvalidValue,
+    /// The effect isn't the name of any of the light's effects.
+    UnknownEffect,
+    /// The buffer the state is being encoded into is too small.
+    BufferTooSmall,
+}
+
+/// A named preset that Home Assistant can pick from the light's effect list.
+#[derive(Copy, Clone)]
+pub struct Effect<'a, P = RGB8> {
+    pub name: &'a str,
+    pub parameters: AnimationParameters<'a, P>,
+}
+
+impl<'a, P> Effect<'a, P> {
+    pub fn new(name: &'a str, parameters: AnimationParameters<'a, P>) -> Self {
+        Effect { name, parameters }
+    }
+}
+
+/// The parts of a JSON schema command this module understands. Anything a command leaves out is
+/// `None`, and keys like `color_temp` and `flash` are ignored.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct LightCommand<'j> {
+    pub state: Option<bool>,
+    pub brightness: Option<u8>,
+    pub color: Option<RGB8>,
+    pub effect: Option<&'j str>,
+    pub transition_ns: Option<u64>,
+}
+
+impl<'j> LightCommand<'j> {
+    pub fn parse(json: &'j [u8]) -> Result<Self, HomeAssistantError> {
+        let mut command = LightCommand::default();
+        let mut reader = JsonReader::new(json);
+        reader.object(0, |reader, key| {
+            match key {
+                "state" => {
+                    command.state = Some(match reader.string()? {
+                        "ON" => true,
+                        "OFF" => false,
+                        _ => return Err(HomeAssistantError::InvalidValue),
+                    })
+                }
+                "brightness" => command.brightness = Some(channel(reader.number()?)?),
+                "color" => {
+                    let (mut r, mut g, mut b) = (None, None, None);
+                    reader.object(1, |reader, key| {
+                        match key {
+                            "r" => r = Some(channel(reader.number()?)?),
+                            "g" => g = Some(channel(reader.number()?)?),
+                            "b" => b = Some(channel(reader.number()?)?),
+                            _ => reader.skip_value(2)?,
+                        }
+                        Ok(())
+                    })?;
+                    match (r, g, b) {
+                        (Some(r), Some(g), Some(b)) => command.color = Some(RGB8::new(r, g, b)),
+                        // Colors in other color modes, such as hue and saturation, aren't used:
+                        _ => return Err(HomeAssistantError::InvalidValue),
+                    }
+                }
+                "effect" => command.effect = Some(reader.string()?),
+                "transition" => {
+                    let seconds = reader.number()?;
+                    if !(0.0..=MAX_TRANSITION_SECONDS).contains(&seconds) {
+                        return Err(HomeAssistantError::InvalidValue);
+                    }
+                    // Milliseconds are as fine as an f32 stays exact over the whole range:
+                    let millis = (seconds * 1000.0 + 0.5) as u64;
+                    command.transition_ns = Some(millis * 1_000_000);
+                }
+                _ => reader.skip_value(1)?,
+            }
+            Ok(())
+        })?;
+        reader.finish()?;
+        Ok(command)
+    }
+}
+
+// A brightness fade on the layer of the light's slot:
+struct Fade {
+    from: u8,
+    to: u8,
+    frame: usize,
+    frames: usize,
+}
+
+/// This is a Home Assistant light controlling the animation at `slot` of a LightingController.
+pub struct HomeAssistantLight<'a, P = RGB8> {
+    pub slot: usize,
+    effects: &'a [Effect<'a, P>],
+    palette: &'a [P],
+    spare: Option<&'a mut dyn Animatable<'a, P>>,
+    is_on: bool,
+    brightness: u8,
+    color: Option<RGB8>,
+    effect: Option<usize>,
+    fade: Option<Fade>,
+}
+
+impl<'a, P: Pixel> HomeAssistantLight<'a, P> {
+    /// Creates a light that starts out on at full brightness, showing whatever is already in the
+    /// slot. Colors are matched to the closest color in `palette`.
+    pub fn new(effects: &'a [Effect<'a, P>], palette: &'a [P], slot: usize) -> Self {
+        HomeAssistantLight {
+            slot,
+            effects,
+            palette,
+            spare: None,
+            is_on: true,
+            brightness: u8::MAX,
+            color: None,
+            effect: None,
+            fade: None,
+        }
+    }
+
+    /// Gives the light a second animation to load effects and colors into, so transitions can
+    /// crossfade to them.
+    pub fn set_spare_animation(mut self, spare: &'a mut dyn Animatable<'a, P>) -> Self {
+        self.spare = Some(spare);
+        self
+    }
+
+    pub fn effects(&self) -> &[Effect<'a, P>] {
+        self.effects
+    }
+
+    pub fn is_on(&self) -> bool {
+        self.is_on
+    }
+
+    /// Returns the brightness the light has when it's on.
+    pub fn brightness(&self) -> u8 {
+        self.brightness
+    }
+
+    /// Returns the color last sent, or `None` if the effect is showing its own colors.
+    pub fn color(&self) -> Option<RGB8> {
+        self.color
+    }
+
+    /// Returns the name of the effect showing, or `None` before the first effect or color is set.
+    pub fn effect(&self) -> Option<&'a str> {
+        self.effect.map(|index| self.effects[index].name)
+    }
+
+    /// Parses a command and applies it to the controller.
+    pub fn handle<const N_ANI: usize>(
+        &mut self,
+        json: &[u8],
+        controller: &mut LightingController<'a, N_ANI, P>,
+    ) -> Result<(), HomeAssistantError> {
+        let command = LightCommand::parse(json)?;
+        self.apply(&command, controller)
+    }
+
+    /// Applies a command to the controller. Nothing is changed if the effect is unknown.
+    pub fn apply<const N_ANI: usize>(
+        &mut self,
+        command: &LightCommand,
+        controller: &mut LightingController<'a, N_ANI, P>,
+    ) -> Result<(), HomeAssistantError> {
+        let effect = match command.effect {
+            Some(name) => Some(
+                self.effects
+                    .iter()
+                    .position(|effect| effect.name == name)
+                    .ok_or(HomeAssistantError::UnknownEffect)?,
+            ),
+            None => None,
+        };
+        let transition_ns = command.transition_ns.unwrap_or(0);
+
+        match (effect, command.color) {
+            (Some(effect), color) => {
+                self.effect = Some(effect);
+                self.color = color;
+                self.show(transition_ns, controller);
+            }
+            (None, Some(color)) => {
+                self.color = Some(color);
+                self.show(transition_ns, controller);
+            }
+            (None, None) => {}
+        }
+
+        match command.brightness {
+            // Home Assistant sends a brightness of 0 to turn a light off:
+            Some(0) => self.is_on = false,
+            Some(brightness) => {
+                self.brightness = brightness;
+                self.is_on = true;
+            }
+            None => {}
+        }
+        if let Some(is_on) = command.state {
+            self.is_on = is_on;
+        }
+        let target = if self.is_on { self.brightness } else { 0 };
+        self.fade_to(target, transition_ns, controller);
+        Ok(())
+    }
+
+    /// This should be called once for every frame the LightingController renders, to run fades and
+    /// to take the spare animation back once a crossfade is done.
+    pub fn update<const N_ANI: usize>(
+        &mut self,
+        controller: &mut LightingController<'a, N_ANI, P>,
+    ) {
+        if self.spare.is_none() {
+            self.spare = controller.take_retired_animation(self.slot);
+        }
+        let Some(fade) = &mut self.fade else {
+            return;
+        };
+        fade.frame += 1;
+        let range = fade.to as i64 - fade.from as i64;
+        let brightness = fade.from as i64 + range * fade.frame as i64 / fade.frames as i64;
+        let is_done = fade.frame >= fade.frames;
+
+        let animation = &mut controller.animations[self.slot];
+        let mut layer = animation.layer();
+        layer.brightness = brightness as u8;
+        animation.update_layer(layer);
+        if is_done {
+            self.fade = None;
+        }
+    }
+
+    /// Writes the state JSON that Home Assistant expects on the light's state topic.
+    pub fn write_state(&self, out: &mut impl Write) -> fmt::Result {
+        write!(
+            out,
+            "{{\"state\":\"{}\",\"brightness\":{},\"color_mode\":\"rgb\"",
+            if self.is_on { "ON" } else { "OFF" },
+            self.brightness
+        )?;
+        if let Some(color) = self.color {
+            write!(
+                out,
+                ",\"color\":{{\"r\":{},\"g\":{},\"b\":{}}}",
+                color.r, color.g, color.b
+            )?;
+        }
+        if let Some(name) = self.effect() {
+            out.write_str(",\"effect\":")?;
+            write_json_string(name, out)?;
+        }
+        out.write_char('}')
+    }
+
+    /// Writes the state JSON into `buffer`, returning the number of bytes written.
+    pub fn encode_state(&self, buffer: &mut [u8]) -> Result<usize, HomeAssistantError> {
+        let mut writer = BufferWriter { buffer, len: 0 };
+        self.write_state(&mut writer)
+            .map_err(|_| HomeAssistantError::BufferTooSmall)?;
+        Ok(writer.len)
+    }
+
+    /// Applies the current effect with the current color, crossfading if there's a transition and
+    /// a spare animation to load it into.
+    fn show<const N_ANI: usize>(
+        &mut self,
+        transition_ns: u64,
+        controller: &mut LightingController<'a, N_ANI, P>,
+    ) {
+        // A color sent before any effect colors the first one:
+        let Some(index) = self.effect.or((!self.effects.is_empty()).then_some(0)) else {
+            return;
+        };
+        self.effect = Some(index);
+        let mut parameters = self.effects[index].parameters;
+        if let Some(rainbow) = self.color.and_then(|color| self.closest(color)) {
+            parameters.bg.rainbow = rainbow;
+            parameters.fg.rainbow = rainbow;
+        }
+
+        let frame_rate = controller.frame_rate;
+        match (transition_ns, self.spare.take()) {
+            (0, spare) | (_, spare @ None) => {
+                self.spare = spare;
+                controller.animations[self.slot].update_parameters(&parameters, frame_rate);
+            }
+            (_, Some(spare)) => {
+                // The spare takes the place of the animation it replaces on the strip:
+                let current = &controller.animations[self.slot];
+                spare.update_layer(current.layer());
+                spare.update_translation_array(current.translation_array());
+                spare.update_parameters(&parameters, frame_rate);
+                controller.transition_animation(
+                    self.slot,
+                    spare,
+                    TransitionStyle::Crossfade,
+                    transition_ns,
+                );
+            }
+        }
+    }
+
+    fn fade_to<const N_ANI: usize>(
+        &mut self,
+        target: u8,
+        transition_ns: u64,
+        controller: &mut LightingController<'a, N_ANI, P>,
+    ) {
+        let frames = convert_ns_to_frames(transition_ns, controller.frame_rate);
+        let animation = &mut controller.animations[self.slot];
+        let mut layer = animation.layer();
+        self.fade = None;
+        match frames {
+            0 => {
+                layer.brightness = target;
+                animation.update_layer(layer);
+            }
+            _ => {
+                self.fade = Some(Fade {
+                    from: layer.brightness,
+                    to: target,
+                    frame: 0,
+                    frames,
+                })
+            }
+        }
+    }
+
+    /// Returns a single color rainbow of the palette color closest to `color`.
+    fn closest(&self, color: RGB8) -> Option<&'a [P]> {
+        let distance = |candidate: &P| {
+            let candidate = candidate.to_rgb();
+            let square = |a: u8, b: u8| (a as i32 - b as i32).pow(2);
+            square(candidate.r, color.r)
+                + square(candidate.g, color.g)
+                + square(candidate.b, color.b)
+        };
+        let palette = self.palette;
+        let (index, _) = palette
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, candidate)| distance(candidate))?;
+        Some(&palette[index..=index])
+    }
+}
+
+/// Rounds a JSON number to a color channel or brightness.
+fn channel(value: f32) -> Result<u8, HomeAssistantError> {
+    match value {
+        _ if (0.0..=255.0).contains(&value) => Ok((value + 0.5) as u8),
+        _ => Err(HomeAssistantError::InvalidValue),
+    }
+}
+
+fn write_json_string(text: &str, out: &mut impl Write) -> fmt::Result {
+    out.write_char('"')?;
+    for c in text.chars() {
+        match c {
+            '"' | '\\' => {
+                out.write_char('\\')?;
+                out.write_char(c)?;
+            }
+            _ if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32)?,
+            _ => out.write_char(c)?,
+        }
+    }
+    out.write_char('"')
+}
+
+struct BufferWriter<'b> {
+    buffer: &'b mut [u8],
+    len: usize,
+}
+
+impl Write for BufferWriter<'_> {
+    fn write_str(&mut self, text: &str) -> fmt::Result {
+        let end = self.len + text.len();
+        self.buffer
+            .get_mut(self.len..end)
+            .ok_or(fmt::Error)?
+            .copy_from_slice(text.as_bytes());
+        self.len = end;
+        Ok(())
+    }
+}
+
+/// Just enough of a JSON reader for light commands, which reads values in place without copying.
+struct JsonReader<'j> {
+    json: &'j [u8],
+    position: usize,
+}
+
+impl<'j> JsonReader<'j> {
+    fn new(json: &'j [u8]) -> Self {
+        JsonReader { json, position: 0 }
+    }
+
+    /// Skips whitespace, returning the next byte without consuming it.
+    fn peek(&mut self) -> Option<u8> {
+        while let Some(b' ' | b'\t' | b'\r' | b'\n') = self.json.get(self.position) {
+            self.position += 1;
+        }
+        self.json.get(self.position).copied()
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<(), HomeAssistantError> {
+        match self.peek() {
+            Some(next) if next == byte => {
+                self.position += 1;
+                Ok(())
+            }
+            _ => Err(self.syntax()),
+        }
+    }
+
+    fn syntax(&self) -> HomeAssistantError {
+        HomeAssistantError::Syntax(self.position)
+    }
+
+    /// Checks that nothing but whitespace follows the value that was read.
+    fn finish(&mut self) -> Result<(), HomeAssistantError> {
+        match self.peek() {
+            None => Ok(()),
+            Some(_) => Err(self.syntax()),
+        }
+    }
+
+    /// Reads an object, calling `on_member` with each key to read its value.
+    fn object(
+        &mut self,
+        depth: usize,
+        mut on_member: impl FnMut(&mut Self, &'j str) -> Result<(), HomeAssistantError>,
+    ) -> Result<(), HomeAssistantError> {
+        if depth >= MAX_DEPTH {
+            return Err(self.syntax());
+        }
+        self.expect(b'{')?;
+        if self.peek() == Some(b'}') {
+            self.position += 1;
+            return Ok(());
+        }
+        loop {
+            let key = self.string().map_err(|_| self.syntax())?;
+            self.expect(b':')?;
+            on_member(self, key)?;
+            match self.peek() {
+                Some(b',') => self.position += 1,
+                Some(b'}') => {
+                    self.position += 1;
+                    return Ok(());
+                }
+                _ => return Err(self.syntax()),
+            }
+        }
+    }
+
+    /// Reads a string. Escapes are left as they are, so strings that use them won't match names.
+    fn string(&mut self) -> Result<&'j str, HomeAssistantError> {
+        match self.peek() {
+            Some(b'"') => {}
+            Some(_) => return Err(HomeAssistantError::InvalidValue),
+            None => return Err(self.syntax()),
+        }
+        let start = self.position + 1;
+        let mut end = start;
+        loop {
+            match self.json.get(end) {
+                Some(b'"') => break,
+                Some(b'\\') => end += 2,
+                Some(_) => end += 1,
+                None => return Err(self.syntax()),
+            }
+        }
+        let text = core::str::from_utf8(&self.json[start..end]).map_err(|_| self.syntax())?;
+        self.position = end + 1;
+        Ok(text)
+    }
+
+    fn number(&mut self) -> Result<f32, HomeAssistantError> {
+        match self.peek() {
+            Some(b'-' | b'0'..=b'9') => {}
+            Some(_) => return Err(HomeAssistantError::InvalidValue),
+            None => return Err(self.syntax()),
+        }
+        let is_negative = self.json[self.position] == b'-';
+        if is_negative {
+            self.position += 1;
+        }
+
+        let mut value = 0.0;
+        let mut scale = 1.0;
+        let mut digits = 0;
+        let mut is_fraction = false;
+        while let Some(&byte) = self.json.get(self.position) {
+            match byte {
+                b'0'..=b'9' if is_fraction => {
+                    scale /= 10.0;
+                    value += (byte - b'0') as f32 * scale;
+                }
+                b'0'..=b'9' => value = value * 10.0 + (byte - b'0') as f32,
+                b'.' if !is_fraction => is_fraction = true,
+                _ => break,
+            }
+            digits += 1;
+            self.position += 1;
+        }
+        if digits == 0 {
+            return Err(self.syntax());
+        }
+
+        if let Some(b'e' | b'E') = self.json.get(self.position) {
+            self.position += 1;
+            let is_negative_exponent = self.json.get(self.position) == Some(&b'-');
+            if let Some(b'-' | b'+') = self.json.get(self.position) {
+                self.position += 1;
+            }
+            let mut exponent = 0;
+            while let Some(&byte @ b'0'..=b'9') = self.json.get(self.position) {
+                exponent = (exponent * 10 + (byte - b'0') as i32).min(64);
+                self.position += 1;
+            }
+            for _ in 0..exponent {
+                value = match is_negative_exponent {
+                    true => value / 10.0,
+                    false => value * 10.0,
+                };
+            }
+        }
+        Ok(if is_negative { -value } else { value })
+    }
+
+    /// Skips over a value of any type.
+    fn skip_value(&mut self, depth: usize) -> Result<(), HomeAssistantError> {
+        if depth >= MAX_DEPTH {
+            return Err(self.syntax());
+        }
+        match self.peek() {
+            Some(b'"') => self.string().map(|_| ()),
+            Some(b'{') => self.object(depth, |reader, _| reader.skip_value(depth + 1)),
+            Some(b'[') => {
+                self.position += 1;
+                if self.peek() == Some(b']') {
+                    self.position += 1;
+                    return Ok(());
+                }
+                loop {
+                    self.skip_value(depth + 1)?;
+                    match self.peek() {
+                        Some(b',') => self.position += 1,
+                        Some(b']') => {
+                            self.position += 1;
+                            return Ok(());
+                        }
+                        _ => return Err(self.syntax()),
+                    }
+                }
+            }
+            Some(b't' | b'f' | b'n') => {
+                let rest = &self.json[self.position..];
+                let len = [&b"true"[..], b"false", b"null"]
+                    .iter()
+                    .find(|literal| rest.starts_with(literal))
+                    .ok_or(self.syntax())?
+                    .len();
+                self.position += len;
+                Ok(())
+            }
+            _ => self.number().map(|_| ()).map_err(|_| self.syntax()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::animations::Animation;
+    use crate::default_animations::{ANI_ALL_OFF, ANI_DEFAULT};
+    use crate::LogicalStrip;
+    use embedded_time::rate::Extensions;
+
+    const EFFECTS: [Effect; 2] = [
+        Effect {
+            name: "rainbow",
+            parameters: ANI_DEFAULT,
+        },
+        Effect {
+            name: "off",
+            parameters: ANI_ALL_OFF,
+        },
+    ];
+
+    fn state(light: &HomeAssistantLight) -> ([u8; 160], usize) {
+        let mut buffer = [0; 160];
+        let len = light.encode_state(&mut buffer).unwrap();
+        (buffer, len)
+    }
+
+    #[test]
+    fn parses_a_full_command() {
+        let json = br#"{"state": "ON", "brightness": 180, "color": {"r": 255, "g": 120, "b": 0},
+            "effect": "rainbow", "transition": 2}"#;
+        let command = LightCommand::parse(json).unwrap();
+        assert_eq!(command.state, Some(true));
+        assert_eq!(command.brightness, Some(180));
+        assert_eq!(command.color, Some(RGB8::new(255, 120, 0)));
+        assert_eq!(command.effect, Some("rainbow"));
+        assert_eq!(command.transition_ns, Some(2_000_000_000));
+    }
+
+    #[test]
+    fn parses_fractions_and_exponents() {
+        let command = LightCommand::parse(br#"{"transition":0.5,"brightness":1.27e2}"#).unwrap();
+        assert_eq!(command.transition_ns, Some(500_000_000));
+        assert_eq!(command.brightness, Some(127));
+        assert_eq!(LightCommand::parse(b"{}").unwrap(), LightCommand::default());
+    }
+
+    #[test]
+    fn skips_keys_it_does_not_use() {
+        let json = br#"{"color_temp": 300, "flash": "short", "xy": [0.1, [1, 2], []],
+            "extra": {"a": {"b": null}, "c": true, "d": false}, "state": "OFF"}"#;
+        let command = LightCommand::parse(json).unwrap();
+        assert_eq!(command.state, Some(false));
+        assert_eq!(command.brightness, None);
+    }
+
+    #[test]
+    fn rejects_malformed_json() {
+        let syntax = |json: &[u8]| {
+            matches!(
+                LightCommand::parse(json),
+                Err(HomeAssistantError::Syntax(_))
+            )
+        };
+        assert!(syntax(b""));
+        assert!(syntax(br#"{"state":"ON""#));
+        assert!(syntax(br#"{"state":"ON",}"#));
+        assert!(syntax(br#"{"state" "ON"}"#));
+        assert!(syntax(br#"{"state":"ON"} x"#));
+        assert!(syntax(br#"{"extra":tru}"#));
+        assert!(syntax(br#"{"extra":[1 2]}"#));
+        assert!(syntax(br#"{"extra":-}"#));
+        assert!(syntax(b"{\"effect\":\"\xff\"}"));
+        assert_eq!(
+            LightCommand::parse(br#"{"state":"ON"]"#),
+            Err(HomeAssistantError::Syntax(13))
+        );
+    }
+
+    #[test]
+    fn rejects_values_home_assistant_does_not_send() {
+        let invalid =
+            |json: &[u8]| LightCommand::parse(json) == Err(HomeAssistantError::InvalidValue);
+        assert!(invalid(br#"{"state":"DIM"}"#));
+        assert!(invalid(br#"{"state":1}"#));
+        assert!(invalid(br#"{"brightness":256}"#));
+        assert!(invalid(br#"{"brightness":-1}"#));
+        assert!(invalid(br#"{"brightness":"full"}"#));
+        assert!(invalid(br#"{"color":{"h":30,"s":100}}"#));
+        assert!(invalid(br#"{"transition":-1}"#));
+    }
+
+    #[test]
+    fn rejects_deep_nesting() {
+        let shallow = br#"{"extra":[[[[[[1]]]]]]}"#;
+        assert!(LightCommand::parse(shallow).is_ok());
+        let deep = br#"{"extra":[[[[[[[[[[[[[[[[[[[[1]]]]]]]]]]]]]]]]]]]]}"#;
+        assert!(matches!(
+            LightCommand::parse(deep),
+            Err(HomeAssistantError::Syntax(_))
+        ));
+        let deep_objects = br#"{"a":{"b":{"c":{"d":{"e":{"f":{"g":{"h":{"i":1}}}}}}}}}"#;
+        assert!(matches!(
+            LightCommand::parse(deep_objects),
+            Err(HomeAssistantError::Syntax(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_huge_transitions() {
+        let invalid =
+            |json: &[u8]| LightCommand::parse(json) == Err(HomeAssistantError::InvalidValue);
+        assert!(invalid(br#"{"state":"OFF","transition":7516192768}"#));
+        assert!(invalid(br#"{"transition":6554}"#));
+        assert!(invalid(br#"{"transition":1e400}"#));
+        assert!(invalid(
+            br#"{"transition":99999999999999999999999999999999999999999}"#
+        ));
+        let longest = LightCommand::parse(br#"{"transition":6553}"#).unwrap();
+        assert_eq!(longest.transition_ns, Some(6_553_000_000_000));
+        // Huge numbers in keys that aren't used are skipped:
+        assert!(LightCommand::parse(br#"{"extra":1e400}"#).is_ok());
+    }
+
+    #[test]
+    fn leaves_escapes_in_strings() {
+        let command = LightCommand::parse(br#"{"effect":"a\"b\\cA"}"#).unwrap();
+        assert_eq!(command.effect, Some(r#"a\"b\\cA"#));
+        // An escaped key doesn't match the key it spells:
+        let command = LightCommand::parse(br#"{"st\u0061te":"ON"}"#).unwrap();
+        assert_eq!(command.state, None);
+        assert!(matches!(
+            LightCommand::parse(br#"{"effect":"abc\"}"#),
+            Err(HomeAssistantError::Syntax(_))
+        ));
+        assert!(matches!(
+            LightCommand::parse(br#"{"effect":"abc\"#),
+            Err(HomeAssistantError::Syntax(_))
+        ));
+    }
+
+    #[test]
+    fn writes_state() {
+        let light = HomeAssistantLight::new(&EFFECTS, &COLOR_CUBE, 0);
+        let (buffer, len) = state(&light);
+        assert_eq!(
+            &buffer[..len],
+            br#"{"state":"ON","brightness":255,"color_mode":"rgb"}"#
+        );
+        assert_eq!(
+            light.encode_state(&mut [0; 20]),
+            Err(HomeAssistantError::BufferTooSmall)
+        );
+    }
+
+    #[test]
+    fn writes_state_after_commands() {
+        let frame_rate = 60.Hz();
+        let animation = &mut Animation::<4>::new(ANI_DEFAULT, frame_rate);
+        let animations: [&mut dyn Animatable; 1] = [animation];
+        let mut controller = LightingController::new(animations, frame_rate);
+        let mut light = HomeAssistantLight::new(&EFFECTS, &COLOR_CUBE, 0);
+
+        let json = br#"{"state":"ON","brightness":80,"color":{"r":255,"g":0,"b":0}}"#;
+        light.handle(json, &mut controller).unwrap();
+        let (buffer, len) = state(&light);
+        assert_eq!(
+            &buffer[..len],
+            br#"{"state":"ON","brightness":80,"color_mode":"rgb","color":{"r":255,"g":0,"b":0},"effect":"rainbow"}"#
+        );
+
+        light
+            .handle(br#"{"state":"OFF","effect":"off"}"#, &mut controller)
+            .unwrap();
+        let (buffer, len) = state(&light);
+        assert_eq!(
+            &buffer[..len],
+            br#"{"state":"OFF","brightness":80,"color_mode":"rgb","effect":"off"}"#
+        );
+
+        assert_eq!(
+            light.handle(br#"{"effect":"strobe"}"#, &mut controller),
+            Err(HomeAssistantError::UnknownEffect)
+        );
+        assert_eq!(light.effect(), Some("off"));
+    }
+
+    #[test]
+    fn escapes_effect_names_in_state() {
+        let effects = [Effect::new("say \"hi\"\\\n", ANI_DEFAULT)];
+        let frame_rate = 60.Hz();
+        let animation = &mut Animation::<4>::new(ANI_DEFAULT, frame_rate);
+        let animations: [&mut dyn Animatable; 1] = [animation];
+        let mut controller = LightingController::new(animations, frame_rate);
+        let mut light = HomeAssistantLight::new(&effects, &COLOR_CUBE, 0);
+
+        light
+            .handle(br#"{"color":{"r":0,"g":0,"b":255}}"#, &mut controller)
+            .unwrap();
+        let (buffer, len) = state(&light);
+        assert!(buffer[..len].ends_with(br#","effect":"say \"hi\"\\\u000a"}"#));
+    }
+
+    #[test]
+    fn fades_brightness_over_the_transition() {
+        let frame_rate = 60.Hz();
+        let animation = &mut Animation::<4>::new(ANI_DEFAULT, frame_rate);
+        let animations: [&mut dyn Animatable; 1] = [animation];
+        let mut controller = LightingController::new(animations, frame_rate);
+        let mut light = HomeAssistantLight::new(&EFFECTS, &COLOR_CUBE, 0);
+        let brightness =
+            |controller: &LightingController<1>| controller.animations[0].layer().brightness;
+
+        light
+            .handle(br#"{"state":"OFF","transition":1}"#, &mut controller)
+            .unwrap();
+        assert_eq!(brightness(&controller), 255);
+        for _ in 0..30 {
+            light.update(&mut controller);
+        }
+        assert_eq!(brightness(&controller), 128);
+        for _ in 0..30 {
+            light.update(&mut controller);
+        }
+        assert_eq!(brightness(&controller), 0);
+        assert!(light.fade.is_none());
+
+        light
+            .handle(br#"{"state":"ON","brightness":200}"#, &mut controller)
+            .unwrap();
+        assert_eq!(brightness(&controller), 200);
+    }
+
+    #[test]
+    fn fades_over_the_longest_transition() {
+        let frame_rate = 1000.Hz();
+        let animation = &mut Animation::<4>::new(ANI_DEFAULT, frame_rate);
+        let animations: [&mut dyn Animatable; 1] = [animation];
+        let mut controller = LightingController::new(animations, frame_rate);
+        let mut light = HomeAssistantLight::new(&EFFECTS, &COLOR_CUBE, 0);
+
+        light
+            .handle(br#"{"state":"OFF","transition":6553}"#, &mut controller)
+            .unwrap();
+        let fade = light.fade.as_mut().unwrap();
+        assert_eq!(fade.frames, 6_553_000);
+        fade.frame = fade.frames / 2 - 1;
+        light.update(&mut controller);
+        assert_eq!(controller.animations[0].layer().brightness, 128);
+    }
+
+    #[test]
+    fn crossfades_to_new_effects_with_a_spare_animation() {
+        let frame_rate = 60.Hz();
+        let animation = &mut Animation::<4>::new(ANI_DEFAULT, frame_rate);
+        let spare = &mut Animation::<4>::new(ANI_ALL_OFF, frame_rate);
+        let animations: [&mut dyn Animatable; 1] = [animation];
+        let mut controller = LightingController::new(animations, frame_rate);
+        let mut light =
+            HomeAssistantLight::new(&EFFECTS, &COLOR_CUBE, 0).set_spare_animation(spare);
+        let color_buffer = &mut [RGB8::default(); 4];
+        let mut strip = LogicalStrip::new(color_buffer);
+
+        light
+            .handle(br#"{"effect":"off","transition":0.5}"#, &mut controller)
+            .unwrap();
+        assert!(controller.is_transitioning(0));
+        assert!(light.spare.is_none());
+        for _ in 0..30 {
+            controller.update(&mut strip);
+            light.update(&mut controller);
+        }
+        assert!(!controller.is_transitioning(0));
+        assert!(light.spare.is_some());
+        assert!(strip.color_buffer.iter().all(|&led| led == RGB8::default()));
+
+        // Without a transition, the effect is applied straight away:
+        light
+            .handle(br#"{"effect":"rainbow"}"#, &mut controller)
+            .unwrap();
+        assert!(!controller.is_transitioning(0));
+        assert!(light.spare.is_some());
+    }
+
+    #[test]
+    fn matches_colors_to_the_palette() {
+        let light = HomeAssistantLight::new(&EFFECTS, &COLOR_CUBE, 0);
+        let rainbow = light.closest(RGB8::new(250, 130, 20)).unwrap();
+        assert_eq!(rainbow, &[RGB8::new(255, 153, 0)]);
+        let empty = HomeAssistantLight::<RGB8>::new(&EFFECTS, &[], 0);
+        assert_eq!(empty.closest(RGB8::new(250, 130, 20)), None);
+    }
+}
@@ -0,0 +1,569 @@
+//! Layouts describe how the leds of a strip are arranged, so translation arrays can be generated
+//! that lay an animation across them in a useful order, instead of being typed in by hand:
+//!
+//! ```ignore
+//! // A 16x16 panel wired in a zigzag, mounted upside down:
+//! let panel = Matrix::new(16, 16)
+//!     .set_wiring(Wiring::Serpentine)
+//!     .set_rotation(Rotation::Deg180);
+//! let animation = Animation::<256>::new(parameters, frame_rate)
+//!     .set_translation_array(panel.translation_array(Order::Diagonals));
+//! ```
+//!
+//! Matrices and tiles of matrices are a `Grid` of leds, addressed by column and row from the top
+//! left of the panel as it's seen. Rings and spirals are `Polar`, where each led is on a ring, or
+//! turn of the spiral, at an angle around it.
+//!
+//! A translation array should have an entry for every led of the layout. Any extra entries carry
+//! on in strip order past the end of the layout, like `utility::default_translation_array()`.
+//!
+//! Installations that aren't laid out in rows or rings can give each led a position in space
+//! instead, with a `positions::PositionMap`.
+
+pub mod positions;
+
+/// How the leds of a matrix are chained together.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Wiring {
+    /// Every row starts from the same side.
+    Progressive,
+    /// Every other row runs back the other way, which is also called zigzag or boustrophedon.
+    Serpentine,
+}
+
+/// How far a matrix is turned clockwise from the way it's wired.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Rotation {
+    Deg0,
+    Deg90,
+    Deg180,
+    Deg270,
+}
+
+/// The order the pixels of an animation are laid across a grid.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Order {
+    /// Left to right along each row, from the top row down.
+    Rows,
+    /// Top to bottom down each column, from the left column across.
+    Columns,
+    /// Along the diagonals running from the bottom left to the top right, from the top left
+    /// corner to the bottom right corner.
+    Diagonals,
+    /// Outward from the center, nearest leds first.
+    Radial,
+    /// Around the center like the hand of a clock, starting from the right and going clockwise.
+    Angular,
+}
+
+/// The order the pixels of an animation are laid across rings and spirals.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PolarOrder {
+    /// Ring by ring from the first ring wired, going around each in the direction it's wired.
+    Outward,
+    /// Ring by ring from the last ring wired.
+    Inward,
+    /// Around every ring at once in the direction they're wired, so each angle is a stripe
+    /// across the rings.
+    Around,
+    /// Around every ring at once against the direction they're wired.
+    AroundBackward,
+}
+
+/// A layout of leds in columns and rows.
+pub trait Grid {
+    /// Returns the number of columns, as the layout is seen.
+    fn width(&self) -> usize;
+
+    /// Returns the number of rows, as the layout is seen.
+    fn height(&self) -> usize;
+
+    /// Returns the strip index of the led in column `x` and row `y`, counted from the top left.
+    fn index(&self, x: usize, y: usize) -> usize;
+
+    /// Returns the strip index the layout starts from.
+    fn start_index(&self) -> usize;
+
+    fn len(&self) -> usize {
+        self.width() * self.height()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns a translation array that lays an animation across the grid in `order`.
+    fn translation_array<const N_LED: usize>(&self, order: Order) -> [usize; N_LED] {
+        let (width, height) = (self.width(), self.height());
+        let mut result = [0; N_LED];
+
+        // Fill in the cells, numbered along the rows, in order before looking up their leds:
+        let cells = width * height;
+        if cells == 0 {
+            return core::array::from_fn(|position| self.start_index() + position);
+        }
+        let mut cell_order = result.iter_mut();
+        match order {
+            Order::Rows | Order::Radial | Order::Angular => {
+                cell_order
+                    .zip(0..cells)
+                    .for_each(|(entry, cell)| *entry = cell);
+            }
+            Order::Columns => {
+                let columns = (0..width).flat_map(|x| (0..height).map(move |y| y * width + x));
+                cell_order
+                    .zip(columns)
+                    .for_each(|(entry, cell)| *entry = cell);
+            }
+            Order::Diagonals => {
+                for diagonal in 0..(width + height).saturating_sub(1) {
+                    let first_x = diagonal.saturating_sub(height - 1);
+                    for x in first_x..=diagonal.min(width - 1) {
+                        match cell_order.next() {
+                            Some(entry) => *entry = (diagonal - x) * width + x,
+                            None => break,
+                        }
+                    }
+                }
+            }
+        }
+
+        let used = N_LED.min(cells);
+        let center = |cell: usize| {
+            // Doubled, so the center of an even sized grid lands on a whole number:
+            let dx = (2 * (cell % width)) as i32 - (width as i32 - 1);
+            let dy = (2 * (cell / width)) as i32 - (height as i32 - 1);
+            (dx * dx + dy * dy, pseudo_angle(dx, dy))
+        };
+        match order {
+            Order::Radial => result[..used].sort_unstable_by_key(|&cell| (center(cell), cell)),
+            Order::Angular => result[..used].sort_unstable_by_key(|&cell| {
+                let (distance, angle) = center(cell);
+                (angle, distance, cell)
+            }),
+            _ => {}
+        }
+
+        for (position, entry) in result.iter_mut().enumerate() {
+            *entry = match position < used {
+                true => self.index(*entry % width, *entry / width),
+                false => self.start_index() + position,
+            };
+        }
+        result
+    }
+}
+
+/// A single panel of leds. The panel is described the way it's wired, with its first led in the
+/// top left corner and its rows running from left to right, and then turned and flipped into the
+/// way it's seen. Panels wired from another corner can be described by flipping them.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Matrix {
+    /// The number of columns, as the panel is wired.
+    pub columns: usize,
+    /// The number of rows, as the panel is wired.
+    pub rows: usize,
+    pub wiring: Wiring,
+    /// The leds are chained down the columns instead of along the rows.
+    pub is_column_major: bool,
+    pub rotation: Rotation,
+    pub is_flipped_x: bool,
+    pub is_flipped_y: bool,
+    pub start_index: usize,
+}
+
+impl Matrix {
+    /// Creates a panel of `columns` by `rows` leds, wired progressively along the rows.
+    pub fn new(columns: usize, rows: usize) -> Self {
+        Matrix {
+            columns,
+            rows,
+            wiring: Wiring::Progressive,
+            is_column_major: false,
+            rotation: Rotation::Deg0,
+            is_flipped_x: false,
+            is_flipped_y: false,
+            start_index: 0,
+        }
+    }
+
+    pub fn set_wiring(mut self, wiring: Wiring) -> Self {
+        self.wiring = wiring;
+        self
+    }
+
+    pub fn set_column_major(mut self, is_column_major: bool) -> Self {
+        self.is_column_major = is_column_major;
+        self
+    }
+
+    pub fn set_rotation(mut self, rotation: Rotation) -> Self {
+        self.rotation = rotation;
+        self
+    }
+
+    /// Mirrors the panel's wiring left to right and top to bottom. A panel wired from the top
+    /// right corner is flipped in x, and one wired from the bottom left corner is flipped in y.
+    pub fn set_flip(mut self, is_flipped_x: bool, is_flipped_y: bool) -> Self {
+        self.is_flipped_x = is_flipped_x;
+        self.is_flipped_y = is_flipped_y;
+        self
+    }
+
+    /// Sets the strip index of the panel's first led, for panels further along the strip.
+    pub fn set_start_index(mut self, start_index: usize) -> Self {
+        self.start_index = start_index;
+        self
+    }
+
+    /// Returns the position of a led in the panel's chain, without the start index.
+    fn chain_position(&self, x: usize, y: usize) -> usize {
+        // Turn the coordinates back into the way the panel is wired:
+        let (mut x, mut y) = match self.rotation {
+            Rotation::Deg0 => (x, y),
+            Rotation::Deg90 => (y, self.rows - 1 - x),
+            Rotation::Deg180 => (self.columns - 1 - x, self.rows - 1 - y),
+            Rotation::Deg270 => (self.columns - 1 - y, x),
+        };
+        if self.is_flipped_x {
+            x = self.columns - 1 - x;
+        }
+        if self.is_flipped_y {
+            y = self.rows - 1 - y;
+        }
+
+        let (line, along, line_len) = match self.is_column_major {
+            false => (y, x, self.columns),
+            true => (x, y, self.rows),
+        };
+        let along = match (self.wiring, line % 2) {
+            (Wiring::Serpentine, 1) => line_len - 1 - along,
+            _ => along,
+        };
+        line * line_len + along
+    }
+}
+
+impl Grid for Matrix {
+    fn width(&self) -> usize {
+        match self.rotation {
+            Rotation::Deg0 | Rotation::Deg180 => self.columns,
+            Rotation::Deg90 | Rotation::Deg270 => self.rows,
+        }
+    }
+
+    fn height(&self) -> usize {
+        match self.rotation {
+            Rotation::Deg0 | Rotation::Deg180 => self.rows,
+            Rotation::Deg90 | Rotation::Deg270 => self.columns,
+        }
+    }
+
+    fn index(&self, x: usize, y: usize) -> usize {
+        self.start_index + self.chain_position(x, y)
+    }
+
+    fn start_index(&self) -> usize {
+        self.start_index
+    }
+}
+
+/// Identical panels chained together into a larger grid. The panels are chained along the rows
+/// of panels, or zigzag back along every other row of panels with `Wiring::Serpentine`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Tiles {
+    /// Each panel, as it's seen. Its start index is the start index of the first panel.
+    pub panel: Matrix,
+    /// The number of panels across.
+    pub columns: usize,
+    /// The number of panels down.
+    pub rows: usize,
+    pub wiring: Wiring,
+}
+
+impl Tiles {
+    pub fn new(panel: Matrix, columns: usize, rows: usize) -> Self {
+        Tiles {
+            panel,
+            columns,
+            rows,
+            wiring: Wiring::Progressive,
+        }
+    }
+
+    pub fn set_wiring(mut self, wiring: Wiring) -> Self {
+        self.wiring = wiring;
+        self
+    }
+}
+
+impl Grid for Tiles {
+    fn width(&self) -> usize {
+        self.columns * self.panel.width()
+    }
+
+    fn height(&self) -> usize {
+        self.rows * self.panel.height()
+    }
+
+    fn index(&self, x: usize, y: usize) -> usize {
+        let (panel_width, panel_height) = (self.panel.width(), self.panel.height());
+        let (column, row) = (x / panel_width, y / panel_height);
+        let column = match (self.wiring, row % 2) {
+            (Wiring::Serpentine, 1) => self.columns - 1 - column,
+            _ => column,
+        };
+        let panel_number = row * self.columns + column;
+        self.panel.start_index
+            + panel_number * self.panel.len()
+            + self.panel.chain_position(x % panel_width, y % panel_height)
+    }
+
+    fn start_index(&self) -> usize {
+        self.panel.start_index
+    }
+}
+
+/// A layout of leds around circles.
+pub trait Polar {
+    /// Returns the number of leds in the layout.
+    fn len(&self) -> usize;
+
+    /// Returns the ring a led is on, counted in the order they're wired, and its angle around the
+    /// ring as a fraction of a turn in the direction it's wired.
+    fn position(&self, led: usize) -> (usize, f32);
+
+    /// Returns the strip index the layout starts from.
+    fn start_index(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns a translation array that lays an animation across the layout in `order`.
+    fn translation_array<const N_LED: usize>(&self, order: PolarOrder) -> [usize; N_LED] {
+        let mut result = [0; N_LED];
+        for (position, entry) in result.iter_mut().enumerate() {
+            *entry = position;
+        }
+
+        let used = N_LED.min(self.len());
+        let key = |led: usize| {
+            let (ring, angle) = self.position(led);
+            // Angles are sorted as whole numbers, since floats aren't `Ord`:
+            let angle = (angle.clamp(0.0, 1.0) * u16::MAX as f32) as u32;
+            (ring, angle)
+        };
+        match order {
+            PolarOrder::Outward => {}
+            PolarOrder::Inward => result[..used].sort_unstable_by_key(|&led| {
+                let (ring, angle) = key(led);
+                (usize::MAX - ring, angle, led)
+            }),
+            PolarOrder::Around => result[..used].sort_unstable_by_key(|&led| {
+                let (ring, angle) = key(led);
+                (angle, ring, led)
+            }),
+            PolarOrder::AroundBackward => result[..used].sort_unstable_by_key(|&led| {
+                let (ring, angle) = key(led);
+                // The start of each ring is the first led going backward too:
+                let angle = match angle {
+                    0 => 0,
+                    angle => u32::MAX - angle,
+                };
+                (angle, ring, led)
+            }),
+        }
+
+        for entry in result.iter_mut() {
+            *entry += self.start_index();
+        }
+        result
+    }
+}
+
+/// Concentric rings of leds chained one after another, with `sizes` holding the number of leds in
+/// each ring in the order they're wired. The first led of every ring should be at the same angle.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Rings<'r> {
+    pub sizes: &'r [usize],
+    pub start_index: usize,
+}
+
+impl<'r> Rings<'r> {
+    pub fn new(sizes: &'r [usize]) -> Self {
+        Rings {
+            sizes,
+            start_index: 0,
+        }
+    }
+
+    pub fn set_start_index(mut self, start_index: usize) -> Self {
+        self.start_index = start_index;
+        self
+    }
+}
+
+impl Polar for Rings<'_> {
+    fn len(&self) -> usize {
+        self.sizes.iter().sum()
+    }
+
+    fn position(&self, led: usize) -> (usize, f32) {
+        let mut first = 0;
+        for (ring, &size) in self.sizes.iter().enumerate() {
+            if led < first + size {
+                return (ring, (led - first) as f32 / size as f32);
+            }
+            first += size;
+        }
+        (self.sizes.len(), 0.0)
+    }
+
+    fn start_index(&self) -> usize {
+        self.start_index
+    }
+}
+
+/// A strip wound into a spiral or helix, such as around a pillar, with `leds_per_turn` leds in
+/// every turn. Each turn counts as a ring.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Spiral {
+    pub len: usize,
+    pub leds_per_turn: f32,
+    pub start_index: usize,
+}
+
+impl Spiral {
+    pub fn new(len: usize, leds_per_turn: f32) -> Self {
+        Spiral {
+            len,
+            leds_per_turn,
+            start_index: 0,
+        }
+    }
+
+    pub fn set_start_index(mut self, start_index: usize) -> Self {
+        self.start_index = start_index;
+        self
+    }
+}
+
+impl Polar for Spiral {
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn position(&self, led: usize) -> (usize, f32) {
+        let turns = led as f32 / self.leds_per_turn;
+        let turn = turns as usize;
+        (turn, turns - turn as f32)
+    }
+
+    fn start_index(&self) -> usize {
+        self.start_index
+    }
+}
+
+/// Returns a number that increases with the angle of a point around the origin, from 0 along the
+/// positive x axis, through 1 along the positive y axis, and on up to 4. This sorts the same as
+/// the real angle without needing trigonometry.
+fn pseudo_angle(dx: i32, dy: i32) -> u32 {
+    let (x, y) = (dx as f32, dy as f32);
+    let angle = match (dx >= 0, dy >= 0) {
+        _ if dx == 0 && dy == 0 => 0.0,
+        (true, true) => y / (x + y),
+        (false, true) => 1.0 - x / (y - x),
+        (false, false) => 2.0 - y / (-x - y),
+        (true, false) => 3.0 + x / (x - y),
+    };
+    (angle * u16::MAX as f32) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lays_rows_along_a_serpentine_matrix() {
+        let panel = Matrix::new(3, 2).set_wiring(Wiring::Serpentine);
+        assert_eq!(
+            panel.translation_array::<6>(Order::Rows),
+            [0, 1, 2, 5, 4, 3]
+        );
+    }
+
+    #[test]
+    fn lays_rows_across_a_rotated_matrix() {
+        // Turned a quarter clockwise, the first row of the panel is its right column:
+        let panel = Matrix::new(3, 2).set_rotation(Rotation::Deg90);
+        assert_eq!((panel.width(), panel.height()), (2, 3));
+        assert_eq!(
+            panel.translation_array::<6>(Order::Rows),
+            [3, 0, 4, 1, 5, 2]
+        );
+
+        let panel = Matrix::new(3, 2).set_rotation(Rotation::Deg180);
+        assert_eq!(
+            panel.translation_array::<6>(Order::Rows),
+            [5, 4, 3, 2, 1, 0]
+        );
+    }
+
+    #[test]
+    fn lays_rows_across_tiles() {
+        let tiles = Tiles::new(Matrix::new(2, 2).set_start_index(10), 2, 1);
+        assert_eq!(
+            tiles.translation_array::<8>(Order::Rows),
+            [10, 11, 14, 15, 12, 13, 16, 17]
+        );
+
+        let tiles = Tiles::new(Matrix::new(1, 1), 2, 2).set_wiring(Wiring::Serpentine);
+        assert_eq!(tiles.translation_array::<4>(Order::Rows), [0, 1, 3, 2]);
+    }
+
+    #[test]
+    fn lays_diagonals_from_the_bottom_left_to_the_top_right() {
+        let panel = Matrix::new(3, 2);
+        assert_eq!(
+            panel.translation_array::<6>(Order::Diagonals),
+            [0, 3, 1, 4, 2, 5]
+        );
+
+        let panel = Matrix::new(3, 3);
+        assert_eq!(
+            panel.translation_array::<9>(Order::Diagonals),
+            [0, 3, 1, 6, 4, 2, 7, 5, 8]
+        );
+    }
+
+    #[test]
+    fn carries_on_past_the_end_of_a_grid() {
+        let panel = Matrix::new(2, 2).set_start_index(4);
+        assert_eq!(
+            panel.translation_array::<6>(Order::Columns),
+            [4, 6, 5, 7, 8, 9]
+        );
+    }
+
+    #[test]
+    fn lays_orders_across_rings() {
+        let rings = Rings::new(&[4, 2]).set_start_index(1);
+        assert_eq!(
+            rings.translation_array::<6>(PolarOrder::Outward),
+            [1, 2, 3, 4, 5, 6]
+        );
+        assert_eq!(
+            rings.translation_array::<6>(PolarOrder::Inward),
+            [5, 6, 1, 2, 3, 4]
+        );
+        assert_eq!(
+            rings.translation_array::<6>(PolarOrder::Around),
+            [1, 5, 2, 3, 6, 4]
+        );
+        assert_eq!(
+            rings.translation_array::<6>(PolarOrder::AroundBackward),
+            [1, 5, 4, 3, 6, 2]
+        );
+    }
+}
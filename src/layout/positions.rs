@@ -0,0 +1,266 @@
+//! A position map gives each pixel of an animation a place in space, for installations that aren't
+//! a strip or a grid, like stairs, ceiling coves or sculptures. The spatial modes pick each pixel's
+//! color by where it is instead of where it is in the segment:
+//!
+//! ```ignore
+//! static POSITIONS: [Point; 3] = [
+//!     Point::new(0, 0, 0),
+//!     Point::new(120, 40, 0),
+//!     Point::new(240, 80, 30),
+//! ];
+//! let animation = Animation::<3>::new(parameters, frame_rate)
+//!     .set_positions(PositionMap::new(&POSITIONS).set_axis(Point::new(1, 1, 0)));
+//! ```
+//!
+//! Positions can be in any units, such as millimeters, as long as every axis uses the same ones.
+//! Pixels are mapped in segment order, so the first position is for the first pixel of the
+//! segment. Maps are usually measured or exported from a mapping tool as CSV, which `parse_csv()`
+//! reads without allocating, or `read_csv()` with the `std` feature.
+
+use crate::utility::Plane;
+
+/// A position in space. With a matrix seen from the front, `x` runs to the right, `y` runs down
+/// and `z` comes toward the viewer.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Point {
+    pub x: i16,
+    pub y: i16,
+    pub z: i16,
+}
+
+impl Point {
+    pub const fn new(x: i16, y: i16, z: i16) -> Self {
+        Point { x, y, z }
+    }
+
+    /// Returns how far this point is along `axis`, scaled by the length of the axis.
+    pub fn along(&self, axis: Point) -> i64 {
+        self.x as i64 * axis.x as i64
+            + self.y as i64 * axis.y as i64
+            + self.z as i64 * axis.z as i64
+    }
+
+    /// Returns the distance between two points.
+    pub fn distance_to(&self, other: Point) -> u64 {
+        let dx = (self.x as i64 - other.x as i64).unsigned_abs();
+        let dy = (self.y as i64 - other.y as i64).unsigned_abs();
+        let dz = (self.z as i64 - other.z as i64).unsigned_abs();
+        (dx * dx + dy * dy + dz * dz).isqrt()
+    }
+}
+
+/// The position of each pixel of a segment, and the axis that the spatial modes sweep and spread
+/// their rainbows along. The axis doesn't need to be any particular length, and defaults to `x`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct PositionMap<'a> {
+    positions: &'a [Point],
+    axis: Point,
+}
+
+impl<'a> PositionMap<'a> {
+    pub fn new(positions: &'a [Point]) -> Self {
+        PositionMap {
+            positions,
+            axis: Point::new(1, 0, 0),
+        }
+    }
+
+    /// Sets the axis the spatial modes run along. An axis of all zeros is ignored.
+    pub fn set_axis(mut self, axis: Point) -> Self {
+        if axis != Point::default() {
+            self.axis = axis;
+        }
+        self
+    }
+
+    pub fn positions(&self) -> &'a [Point] {
+        self.positions
+    }
+
+    pub fn axis(&self) -> Point {
+        self.axis
+    }
+
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+}
+
+/// Where the spatial modes find the pixels of a segment. Without a position map, pixels are placed
+/// on the matrix the 2D modes use, one unit apart, so spatial modes still work on a matrix or
+/// strip. Pixels past the end of a position map have no position and are left alone.
+#[derive(Copy, Clone)]
+pub(crate) struct Space<'a> {
+    map: Option<PositionMap<'a>>,
+    plane: Plane,
+    led_count: usize,
+}
+
+impl<'a> Space<'a> {
+    pub(crate) fn new(map: Option<PositionMap<'a>>, width: usize, led_count: usize) -> Self {
+        Space {
+            map,
+            plane: Plane::new(width, led_count),
+            led_count,
+        }
+    }
+
+    pub(crate) fn axis(&self) -> Point {
+        self.map.map_or(Point::new(1, 0, 0), |map| map.axis)
+    }
+
+    /// Returns the position of the pixel at `index`, if it has one.
+    pub(crate) fn point(&self, index: usize) -> Option<Point> {
+        if index >= self.led_count {
+            return None;
+        }
+        match self.map {
+            Some(map) => map.positions.get(index).copied(),
+            None => {
+                let (x, y) = self.plane.position(index);
+                Some(Point::new(x as i16, y as i16, 0))
+            }
+        }
+    }
+
+    /// Returns the lowest and highest distance along the axis of any pixel.
+    pub(crate) fn extent(&self) -> (i64, i64) {
+        let axis = self.axis();
+        (0..self.led_count)
+            .filter_map(|index| self.point(index))
+            .map(|point| point.along(axis))
+            .fold(None, |range, along| match range {
+                None => Some((along, along)),
+                Some((low, high)) => Some((along.min(low), along.max(high))),
+            })
+            .unwrap_or((0, 0))
+    }
+
+    /// Returns the distance from `center` to the pixel farthest from it.
+    pub(crate) fn farthest_from(&self, center: Point) -> u64 {
+        (0..self.led_count)
+            .filter_map(|index| self.point(index))
+            .map(|point| point.distance_to(center))
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+/// These are the ways reading a position map from CSV can fail. Lines are counted from 1.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PositionError {
+    /// A line holds something other than a number where a number belongs, or a number too big for
+    /// a `Point`.
+    InvalidNumber { line: usize },
+    /// A line doesn't have 2, 3 or 4 columns.
+    WrongColumnCount { line: usize },
+    /// A line has a different number of columns than the lines before it.
+    InconsistentColumns { line: usize },
+    /// There are more positions than fit in the buffer.
+    TooManyPositions { line: usize },
+}
+
+/// Reads a position map from CSV into `positions`, returning the number of positions read. Each
+/// line is a pixel in segment order with its `x,y` or `x,y,z` position, or an `index,x,y,z` line
+/// to list pixels in any order, in which case any pixels that aren't listed are placed at the
+/// origin. Numbers can have fractions, which are rounded. Blank lines, lines starting with `#`,
+/// and a header line before the first position are skipped.
+pub fn parse_csv(text: &str, positions: &mut [Point]) -> Result<usize, PositionError> {
+    let mut count = 0;
+    let mut columns = None;
+    let mut is_first_line = true;
+
+    for (line_index, line) in text.lines().enumerate() {
+        let line_number = line_index + 1;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let mut fields = [0; 4];
+        let mut field_count = 0;
+        let mut is_numeric = true;
+        for field in line.split(',') {
+            if field_count == fields.len() {
+                return Err(PositionError::WrongColumnCount { line: line_number });
+            }
+            match parse_coordinate(field.trim()) {
+                Some(value) => fields[field_count] = value,
+                None => is_numeric = false,
+            }
+            field_count += 1;
+        }
+
+        let was_first_line = core::mem::replace(&mut is_first_line, false);
+        if !is_numeric {
+            if was_first_line {
+                continue;
+            }
+            return Err(PositionError::InvalidNumber { line: line_number });
+        }
+        if field_count < 2 {
+            return Err(PositionError::WrongColumnCount { line: line_number });
+        }
+        if *columns.get_or_insert(field_count) != field_count {
+            return Err(PositionError::InconsistentColumns { line: line_number });
+        }
+
+        let (index, point) = match fields {
+            [x, y, z, _] if field_count < 4 => (count, Point::new(x, y, z)),
+            [index, x, y, z] => {
+                let index = usize::try_from(index)
+                    .map_err(|_| PositionError::InvalidNumber { line: line_number })?;
+                (index, Point::new(x, y, z))
+            }
+        };
+        if index >= positions.len() {
+            return Err(PositionError::TooManyPositions { line: line_number });
+        }
+        if index >= count {
+            // Pixels skipped over by an index column are placed at the origin:
+            positions[count..index].fill(Point::default());
+            count = index + 1;
+        }
+        positions[index] = point;
+    }
+    Ok(count)
+}
+
+/// Reads a position map from CSV, like `parse_csv()`, from a file or any other reader.
+#[cfg(feature = "std")]
+pub fn read_csv(mut reader: impl std::io::Read) -> std::io::Result<std::vec::Vec<Point>> {
+    use std::io::{Error, ErrorKind};
+
+    let mut text = std::string::String::new();
+    reader.read_to_string(&mut text)?;
+
+    // An index column can list pixels past the number of lines, so make room for those too:
+    let listed = text
+        .lines()
+        .filter(|line| line.split(',').count() == 4)
+        .filter_map(|line| parse_coordinate(line.split(',').next()?.trim()))
+        .map(|index| index.max(0) as usize + 1)
+        .max()
+        .unwrap_or(0);
+    let mut positions = std::vec![Point::default(); text.lines().count().max(listed)];
+    let count = parse_csv(&text, &mut positions)
+        .map_err(|error| Error::new(ErrorKind::InvalidData, std::format!("{:?}", error)))?;
+    positions.truncate(count);
+    Ok(positions)
+}
+
+fn parse_coordinate(field: &str) -> Option<i16> {
+    let value: f32 = field.parse().ok()?;
+    // f32::round() needs std, so round half away from zero by hand:
+    let rounded = if value < 0.0 {
+        value - 0.5
+    } else {
+        value + 0.5
+    };
+    let in_range = (i16::MIN as f32..i16::MAX as f32 + 1.0).contains(&rounded);
+    in_range.then_some(rounded as i16)
+}
@@ -1,49 +1,117 @@
-#![no_std]
+#![cfg_attr(not(feature = "std"), no_std)]
 
 pub mod animations;
 pub mod colors;
 pub mod default_animations;
+pub mod dmx;
+pub mod error;
+pub mod home_assistant;
+pub mod layout;
+pub mod midi;
+pub mod osc;
+pub mod playlist;
+pub mod power;
+pub mod preset;
+pub mod scene;
+pub mod serial;
+pub mod shell;
+pub mod tempo;
+pub mod transition;
 pub mod utility;
+pub mod wled;
 
 use crate::animations::{Animatable, AnimationType};
-use crate::colors::ManipulatableColor;
+use crate::colors::{scale_channel, BlendMode, FromPixel, GammaTable, ManipulatableColor, Pixel};
+use crate::error::Error;
+use crate::power::{PowerLimiter, PowerModel};
+use crate::transition::{Transition, TransitionStyle, TransitionWeight};
+use crate::utility::{convert_instant_to_ns, convert_ns_to_frames, default_translation_array};
+use embedded_time::fixed_point::FixedPoint;
 use embedded_time::rate::Hertz;
+use embedded_time::{Clock, Instant};
 use rgb::RGB8;
 
-pub struct LogicalStrip<'a> {
-    pub color_buffer: &'a mut [RGB8],
+/// This is the default number of late frames that `LightingController::tick()` will render
+/// back-to-back to catch up with the clock before it starts dropping frames instead.
+pub const DEFAULT_MAX_CATCH_UP_FRAMES: usize = 2;
+
+/// This holds the colors that will be written out to the leds. The pixel type can be any of the
+/// `colors::Pixel` types, such as `RGB8` or `colors::RGBW8`, and does not need to match the pixel
+/// type the animations are rendered with.
+pub struct LogicalStrip<'a, P = RGB8> {
+    pub color_buffer: &'a mut [P],
 }
 
-impl<'a> LogicalStrip<'a> {
-    pub fn new(color_buffer: &'a mut [RGB8]) -> Self {
+impl<'a, P: Pixel> LogicalStrip<'a, P> {
+    pub fn new(color_buffer: &'a mut [P]) -> Self {
         LogicalStrip { color_buffer }
     }
 
-    pub fn get_color_at_index(&self, index: usize) -> RGB8 {
+    /// Creates a logical strip like `new()`, but returns an error if the buffer has no leds.
+    pub fn try_new(color_buffer: &'a mut [P]) -> Result<Self, Error> {
+        if color_buffer.is_empty() {
+            return Err(Error::EmptyStrip);
+        }
+        Ok(Self::new(color_buffer))
+    }
+
+    pub fn len(&self) -> usize {
+        self.color_buffer.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.color_buffer.is_empty()
+    }
+
+    pub fn get_color_at_index(&self, index: usize) -> P {
         self.color_buffer[index]
     }
 
+    pub fn try_get_color_at_index(&self, index: usize) -> Result<P, Error> {
+        let index = error::check_index(index, self.color_buffer.len())?;
+        Ok(self.color_buffer[index])
+    }
+
     // this sets the color value in the color array at index:
-    pub fn set_color_at_index(&mut self, index: usize, color: RGB8) {
+    pub fn set_color_at_index(&mut self, index: usize, color: P) {
+        self.color_buffer[index].set_color(color);
+    }
+
+    pub fn try_set_color_at_index(&mut self, index: usize, color: P) -> Result<(), Error> {
+        let index = error::check_index(index, self.color_buffer.len())?;
         self.color_buffer[index].set_color(color);
+        Ok(())
     }
 
     // this fills the entire strip with a single color:
-    pub fn set_strip_to_solid_color(&mut self, color: RGB8) {
+    pub fn set_strip_to_solid_color(&mut self, color: P) {
         for c in &mut self.color_buffer.iter_mut() {
             c.set_color(color);
         }
     }
 }
 
-pub struct LightingController<'a, const N_ANI: usize> {
-    pub animations: [&'a mut dyn Animatable<'a>; N_ANI],
+/// The LightingController runs a set of animations rendered with pixel type `P` and composites them
+/// onto a LogicalStrip. When the strip uses a different pixel type, such as an RGB animation on an
+/// RGBW strip, each pixel is converted with `colors::FromPixel` as it is composited.
+pub struct LightingController<'a, const N_ANI: usize, P = RGB8> {
+    pub animations: [&'a mut dyn Animatable<'a, P>; N_ANI],
     pub frame_rate: Hertz,
+    transitions: [Option<Transition<'a, P>>; N_ANI],
+    retired: [Option<&'a mut dyn Animatable<'a, P>>; N_ANI],
+    max_catch_up_frames: usize,
+    next_frame_ns: Option<u64>,
+    dropped_frames: u32,
+    brightness: u8,
+    gamma: Option<&'a GammaTable>,
+    power_limiter: Option<PowerLimiter>,
+    requested_draw_ma: u32,
+    estimated_draw_ma: u32,
 }
 
-impl<'a, const N_ANI: usize> LightingController<'a, N_ANI> {
+impl<'a, const N_ANI: usize, P: Pixel> LightingController<'a, N_ANI, P> {
     pub fn new(
-        animations: [&'a mut dyn Animatable<'a>; N_ANI],
+        animations: [&'a mut dyn Animatable<'a, P>; N_ANI],
         frame_rate: impl Into<Hertz>,
     ) -> Self {
         let frame_rate = frame_rate.into();
@@ -51,32 +119,390 @@ impl<'a, const N_ANI: usize> LightingController<'a, N_ANI> {
         LightingController {
             animations,
             frame_rate,
+            transitions: core::array::from_fn(|_| None),
+            retired: core::array::from_fn(|_| None),
+            max_catch_up_frames: DEFAULT_MAX_CATCH_UP_FRAMES,
+            next_frame_ns: None,
+            dropped_frames: 0,
+            brightness: u8::MAX,
+            gamma: None,
+            power_limiter: None,
+            requested_draw_ma: 0,
+            estimated_draw_ma: 0,
         }
     }
 
-    pub fn update(&mut self, logical_strip: &mut LogicalStrip) {
-        for animation in self.animations.iter_mut() {
-            animation.update();
+    /// Creates a controller like `new()`, but first validates every animation against the strip
+    /// it will draw onto. See `validate()`.
+    pub fn try_new<S>(
+        animations: [&'a mut dyn Animatable<'a, P>; N_ANI],
+        frame_rate: impl Into<Hertz>,
+        logical_strip: &LogicalStrip<S>,
+    ) -> Result<Self, (usize, Error)> {
+        let controller = Self::new(animations, frame_rate);
+        controller.validate(logical_strip)?;
+        Ok(controller)
+    }
 
-            let segment = animation.segment();
-            let translater = animation.translation_array();
-            let translated = translater.iter().zip(segment.iter());
+    /// Checks that every animation can be drawn onto `logical_strip` without panicking, returning
+    /// the index of the first animation with a problem along with the problem. This is meant to be
+    /// called once at setup, and again after changing translation arrays or rainbows.
+    pub fn validate<S>(&self, logical_strip: &LogicalStrip<S>) -> Result<(), (usize, Error)> {
+        if logical_strip.color_buffer.is_empty() {
+            return Err((0, Error::EmptyStrip));
+        }
+        let strip_len = logical_strip.color_buffer.len();
+        self.animations
+            .iter()
+            .enumerate()
+            .try_for_each(|(index, animation)| {
+                animation
+                    .validate(strip_len)
+                    .map_err(|error| (index, error))
+            })
+    }
+
+    /// Sets how many late frames `tick()` will render back-to-back before dropping frames.
+    pub fn set_max_catch_up_frames(mut self, max_catch_up_frames: usize) -> Self {
+        self.max_catch_up_frames = max_catch_up_frames;
+        self
+    }
+
+    pub fn set_brightness(mut self, brightness: u8) -> Self {
+        self.update_brightness(brightness);
+        self
+    }
+
+    pub fn set_gamma(mut self, gamma: &'a GammaTable) -> Self {
+        self.update_gamma(Some(gamma));
+        self
+    }
+
+    /// Sets the master brightness, which is applied to the whole strip after gamma correction.
+    pub fn update_brightness(&mut self, brightness: u8) {
+        self.brightness = brightness;
+    }
+
+    /// Sets the gamma table used to correct the strip after compositing, or `None` to disable it.
+    pub fn update_gamma(&mut self, gamma: Option<&'a GammaTable>) {
+        self.gamma = gamma;
+    }
+
+    pub fn set_power_limit(mut self, model: PowerModel, budget_ma: u32) -> Self {
+        self.update_power_limit(Some(PowerLimiter::new(model, budget_ma)));
+        self
+    }
+
+    /// Sets the power limiter that is applied as the last step of every update, or `None` to
+    /// disable it.
+    pub fn update_power_limit(&mut self, power_limiter: Option<PowerLimiter>) {
+        self.power_limiter = power_limiter;
+    }
+
+    pub fn brightness(&self) -> u8 {
+        self.brightness
+    }
+
+    /// Returns the current in milliamps the last frame would have drawn without the power limiter.
+    /// This is always 0 when no power limit is set.
+    pub fn requested_draw_ma(&self) -> u32 {
+        self.requested_draw_ma
+    }
+
+    /// Returns the current in milliamps the last frame is estimated to draw after power limiting.
+    /// This is always 0 when no power limit is set.
+    pub fn estimated_draw_ma(&self) -> u32 {
+        self.estimated_draw_ma
+    }
+
+    /// Advances every animation by exactly one frame and writes the result to the strip,
+    /// regardless of how much time has passed. Use `tick()` to limit updates to the frame rate.
+    pub fn update<S: Pixel + FromPixel<P>>(&mut self, logical_strip: &mut LogicalStrip<S>) {
+        self.advance_animations(1);
+        self.render(logical_strip);
+    }
+
+    /// This is meant to be called as often as possible from the main loop with the current time.
+    /// It will only update the animations when a frame is due at the controller's frame rate, and
+    /// returns true when the logical strip has new data that should be written to the leds.
+    ///
+    /// If the caller was late and several frames are due at once, up to `max_catch_up_frames`
+    /// extra frames are advanced before rendering so the animations keep their speed. Any frames
+    /// beyond that are dropped, and the schedule stays aligned to the original frame boundaries.
+    pub fn tick<C, S>(&mut self, now: Instant<C>, logical_strip: &mut LogicalStrip<S>) -> bool
+    where
+        C: Clock,
+        S: Pixel + FromPixel<P>,
+        u64: TryFrom<C::T>,
+    {
+        let Some(now_ns) = convert_instant_to_ns(now) else {
+            return false;
+        };
+        let frame_ns = match self.frame_rate.integer() {
+            0 => return false,
+            hz => 1_000_000_000_u64 / hz as u64,
+        };
+
+        let next_frame_ns = match self.next_frame_ns {
+            // If the clock has gone backwards (e.g. it wrapped), restart the schedule from now:
+            Some(next) if now_ns + frame_ns >= next => next,
+            _ => now_ns,
+        };
+        if now_ns < next_frame_ns {
+            return false;
+        }
+
+        let frames_due = (now_ns - next_frame_ns) / frame_ns + 1;
+        let frames_to_render = frames_due.min(self.max_catch_up_frames as u64 + 1);
+        let frames_to_drop = frames_due - frames_to_render;
+
+        self.next_frame_ns = Some(next_frame_ns + frames_due * frame_ns);
+        self.dropped_frames = self.dropped_frames.saturating_add(frames_to_drop as u32);
+
+        self.advance_animations(frames_to_render as usize);
+        self.render(logical_strip);
+        true
+    }
+
+    /// Forgets the frame schedule so the next call to `tick()` renders immediately. Call this
+    /// after the main loop has been paused on purpose to avoid counting the pause as dropped frames.
+    pub fn reset_frame_timer(&mut self) {
+        self.next_frame_ns = None;
+    }
+
+    /// Returns the total number of frames `tick()` has skipped because the caller was too late.
+    pub fn dropped_frames(&self) -> u32 {
+        self.dropped_frames
+    }
 
-            for (&index, &color) in translated {
-                logical_strip.set_color_at_index(index, color);
+    fn advance_animations(&mut self, frames: usize) {
+        for _ in 0..frames {
+            for animation in self.animations.iter_mut() {
+                animation.update();
+            }
+            for (slot, retired) in self.transitions.iter_mut().zip(self.retired.iter_mut()) {
+                if let Some(transition) = slot {
+                    transition.outgoing.update();
+                    if transition.advance() {
+                        *retired = slot.take().map(|t| t.outgoing);
+                    }
+                }
             }
         }
     }
 
-    pub fn trigger(&mut self, animation_index: usize, params: &animations::trigger::Parameters) {
+    /// Composites every animation onto the strip from the lowest z-order to the highest.
+    fn render<S: Pixel + FromPixel<P>>(&mut self, logical_strip: &mut LogicalStrip<S>) {
+        logical_strip.set_strip_to_solid_color(S::default());
+
+        // Sorting on the index as well keeps animations with equal z-orders in array order:
+        let mut draw_order: [usize; N_ANI] = default_translation_array(0);
+        draw_order.sort_unstable_by_key(|&i| (self.animations[i].layer().z_order, i));
+
+        for &animation_index in draw_order.iter() {
+            let animation = &*self.animations[animation_index];
+            match &self.transitions[animation_index] {
+                None => composite(logical_strip, animation, |_, _| TransitionWeight::FULL),
+                Some(transition) => {
+                    let outgoing_weight = transition.outgoing_weight();
+                    composite(logical_strip, &*transition.outgoing, |_, _| outgoing_weight);
+                    composite(logical_strip, animation, |i, len| {
+                        transition.incoming_weight(i, len)
+                    });
+                }
+            }
+        }
+
+        self.apply_output_stage(logical_strip);
+    }
+
+    /// Gamma corrects and then dims the composited strip, then dims it further if needed to stay
+    /// within the power budget. This happens after the animations are done drawing, so it never
+    /// changes their colors for the next frame. Applying gamma first keeps dim fades from
+    /// collapsing into a handful of visible steps.
+    fn apply_output_stage<S: Pixel>(&mut self, logical_strip: &mut LogicalStrip<S>) {
+        for color in logical_strip.color_buffer.iter_mut() {
+            if let Some(gamma) = self.gamma {
+                *color = gamma.correct(*color);
+            }
+            *color = color.scaled(self.brightness);
+        }
+
+        self.requested_draw_ma = 0;
+        self.estimated_draw_ma = 0;
+        if let Some(limiter) = self.power_limiter {
+            let colors = &mut *logical_strip.color_buffer;
+            self.requested_draw_ma = limiter.model.estimate_draw_ma(colors);
+
+            let limiting_brightness = limiter.limiting_brightness(colors);
+            if limiting_brightness < u8::MAX {
+                colors
+                    .iter_mut()
+                    .for_each(|c| *c = c.scaled(limiting_brightness));
+            }
+            self.estimated_draw_ma = limiter.model.estimate_draw_ma(colors);
+        }
+    }
+
+    pub fn trigger(&mut self, animation_index: usize, params: &animations::trigger::Parameters<P>) {
         self.animations[animation_index].trigger(params, self.frame_rate);
     }
 
+    pub fn try_trigger(
+        &mut self,
+        animation_index: usize,
+        params: &animations::trigger::Parameters<P>,
+    ) -> Result<(), Error> {
+        self.check_slot(animation_index)?;
+        self.trigger(animation_index, params);
+        Ok(())
+    }
+
     pub fn set_offset(&mut self, animation_index: usize, a_type: AnimationType, offset: u16) {
         self.animations[animation_index].set_offset(a_type, offset);
     }
 
-    pub fn replace_animation(&mut self, index: usize, new_anim: &'a mut dyn Animatable<'a>) {
-        self.animations[index] = new_anim;
+    pub fn try_set_offset(
+        &mut self,
+        animation_index: usize,
+        a_type: AnimationType,
+        offset: u16,
+    ) -> Result<(), Error> {
+        self.check_slot(animation_index)?;
+        self.set_offset(animation_index, a_type, offset);
+        Ok(())
+    }
+
+    pub fn replace_animation(&mut self, index: usize, new_anim: &'a mut dyn Animatable<'a, P>) {
+        let old_anim = core::mem::replace(&mut self.animations[index], new_anim);
+        self.retire(index, old_anim);
+    }
+
+    pub fn try_replace_animation(
+        &mut self,
+        index: usize,
+        new_anim: &'a mut dyn Animatable<'a, P>,
+    ) -> Result<(), Error> {
+        self.check_slot(index)?;
+        self.replace_animation(index, new_anim);
+        Ok(())
+    }
+
+    /// Replaces the animation at `index` over `duration_ns` using the transition `style`. The
+    /// outgoing animation keeps running until the transition is finished. Starting a new transition
+    /// on a slot that is already transitioning retires the oldest animation immediately.
+    pub fn transition_animation(
+        &mut self,
+        index: usize,
+        new_anim: &'a mut dyn Animatable<'a, P>,
+        style: TransitionStyle,
+        duration_ns: u64,
+    ) {
+        let frame_count = convert_ns_to_frames(duration_ns, self.frame_rate);
+        let outgoing = core::mem::replace(&mut self.animations[index], new_anim);
+        match (style, frame_count) {
+            (TransitionStyle::Cut, _) | (_, 0) => self.retire(index, outgoing),
+            _ => {
+                if let Some(previous) = self.transitions[index].take() {
+                    self.retired[index] = Some(previous.outgoing);
+                }
+                self.transitions[index] = Some(Transition::new(outgoing, style, frame_count));
+            }
+        }
+    }
+
+    pub fn try_transition_animation(
+        &mut self,
+        index: usize,
+        new_anim: &'a mut dyn Animatable<'a, P>,
+        style: TransitionStyle,
+        duration_ns: u64,
+    ) -> Result<(), Error> {
+        self.check_slot(index)?;
+        self.transition_animation(index, new_anim, style, duration_ns);
+        Ok(())
+    }
+
+    /// Replaces every animation in the controller at once using the same transition.
+    pub fn transition_all(
+        &mut self,
+        new_anims: [&'a mut dyn Animatable<'a, P>; N_ANI],
+        style: TransitionStyle,
+        duration_ns: u64,
+    ) {
+        for (index, new_anim) in new_anims.into_iter().enumerate() {
+            self.transition_animation(index, new_anim, style, duration_ns);
+        }
+    }
+
+    /// Moves every animation to where it would be `position_ns` after it started, such as when
+    /// following a song position from an external clock.
+    pub fn seek_ns(&mut self, position_ns: u64) {
+        let frame = convert_ns_to_frames(position_ns, self.frame_rate);
+        for animation in self.animations.iter_mut() {
+            animation.seek(frame);
+        }
+    }
+
+    /// Returns true while the animation at `index` is still transitioning in.
+    pub fn is_transitioning(&self, index: usize) -> bool {
+        self.transitions[index].is_some()
+    }
+
+    /// Hands back the animation most recently swapped out of slot `index` once it is no longer
+    /// being drawn, so it can be reconfigured and transitioned back in later. Only the most recent
+    /// one is kept for each slot.
+    pub fn take_retired_animation(
+        &mut self,
+        index: usize,
+    ) -> Option<&'a mut dyn Animatable<'a, P>> {
+        self.retired[index].take()
+    }
+
+    fn check_slot(&self, index: usize) -> Result<usize, Error> {
+        error::check_index(index, N_ANI)
+    }
+
+    /// Stops drawing a slot's outgoing transition animation along with `old_anim`, keeping
+    /// `old_anim` so it can be handed back by `take_retired_animation()`.
+    fn retire(&mut self, index: usize, old_anim: &'a mut dyn Animatable<'a, P>) {
+        self.transitions[index] = None;
+        self.retired[index] = Some(old_anim);
+    }
+}
+
+/// Draws an animation's segment onto the strip through its translation array, using its layer
+/// settings and the transition weight of each led.
+fn composite<'a, P, S>(
+    logical_strip: &mut LogicalStrip<S>,
+    animation: &dyn Animatable<'a, P>,
+    weight: impl Fn(usize, usize) -> TransitionWeight,
+) where
+    P: Pixel,
+    S: Pixel + FromPixel<P>,
+{
+    let layer = animation.layer();
+    let is_transparent = animation.is_transparent();
+
+    let segment = animation.segment();
+    let translater = animation.translation_array();
+    let translated = translater.iter().zip(segment.iter()).enumerate();
+    let strip_len = logical_strip.color_buffer.len();
+
+    for (segment_index, (&index, &color)) in translated {
+        let weight = weight(segment_index, segment.len());
+        // Leds translated past the end of the strip are skipped rather than panicking mid-frame.
+        // `LightingController::validate()` finds them at setup.
+        if index >= strip_len || (is_transparent && color == P::default()) || weight.opacity == 0 {
+            continue;
+        }
+        let brightness = scale_channel(layer.brightness, weight.brightness);
+        let opacity = scale_channel(layer.opacity, weight.opacity);
+
+        let under = logical_strip.get_color_at_index(index);
+        let color = S::from_pixel(color).scaled(brightness);
+        let blended = under.blend_with(color, layer.blend_mode);
+        let mixed = under.blend_with(blended, BlendMode::Alpha(opacity));
+        logical_strip.set_color_at_index(index, mixed);
     }
 }
@@ -0,0 +1,206 @@
+//! This turns a raw MIDI byte stream, such as the bytes read from a UART at 31250 baud, into MIDI
+//! messages, and `mapping` turns those messages into actions on a LightingController.
+//!
+//! Channels are numbered 0 to 15 here, which most devices display as 1 to 16.
+
+pub mod clock;
+pub mod mapping;
+
+/// These are the messages the parser can produce. System exclusive messages are skipped.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MidiMessage {
+    /// A note on with a velocity of 0 is also reported as a note off, as most devices send them
+    /// that way to make use of running status.
+    NoteOff {
+        channel: u8,
+        note: u8,
+        velocity: u8,
+    },
+    NoteOn {
+        channel: u8,
+        note: u8,
+        velocity: u8,
+    },
+    PolyPressure {
+        channel: u8,
+        note: u8,
+        pressure: u8,
+    },
+    ControlChange {
+        channel: u8,
+        controller: u8,
+        value: u8,
+    },
+    ProgramChange {
+        channel: u8,
+        program: u8,
+    },
+    ChannelPressure {
+        channel: u8,
+        pressure: u8,
+    },
+    /// The 14 bit pitch bend value, where 8192 is centered.
+    PitchBend {
+        channel: u8,
+        value: u16,
+    },
+
+    TimeCodeQuarterFrame(u8),
+    /// The song position in MIDI beats, which are sixteenth notes.
+    SongPosition(u16),
+    SongSelect(u8),
+    TuneRequest,
+
+    TimingClock,
+    Start,
+    Continue,
+    Stop,
+    ActiveSensing,
+    Reset,
+}
+
+/// This parses MIDI one byte at a time. It supports running status, and real time messages such as
+/// the timing clock can arrive in the middle of any other message without interrupting it.
+#[derive(Default)]
+pub struct MidiParser {
+    status: Option<u8>,
+    data: [u8; 2],
+    data_len: usize,
+    in_sysex: bool,
+}
+
+impl MidiParser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feeds the next byte of the stream into the parser, returning a message when `byte`
+    /// completes one.
+    pub fn parse(&mut self, byte: u8) -> Option<MidiMessage> {
+        match byte {
+            0xF8..=0xFF => realtime_message(byte),
+            0xF0 => {
+                self.in_sysex = true;
+                self.status = None;
+                None
+            }
+            0xF7 => {
+                self.in_sysex = false;
+                None
+            }
+            0xF6 => {
+                self.in_sysex = false;
+                self.status = None;
+                Some(MidiMessage::TuneRequest)
+            }
+            // Undefined system common messages, whose data bytes are ignored:
+            0xF4 | 0xF5 => {
+                self.in_sysex = false;
+                self.status = None;
+                None
+            }
+            0x80..=0xF5 => {
+                self.in_sysex = false;
+                self.status = Some(byte);
+                self.data_len = 0;
+                None
+            }
+            _ => self.parse_data(byte),
+        }
+    }
+
+    /// Parses every byte in `bytes`, calling `on_message` for each message found.
+    pub fn parse_all(&mut self, bytes: &[u8], mut on_message: impl FnMut(MidiMessage)) {
+        for &byte in bytes {
+            if let Some(message) = self.parse(byte) {
+                on_message(message);
+            }
+        }
+    }
+
+    fn parse_data(&mut self, byte: u8) -> Option<MidiMessage> {
+        let status = self.status.filter(|_| !self.in_sysex)?;
+
+        self.data[self.data_len] = byte;
+        self.data_len += 1;
+        if self.data_len < data_len(status) {
+            return None;
+        }
+        self.data_len = 0;
+
+        // Only channel messages can use running status:
+        if status >= 0xF0 {
+            self.status = None;
+        }
+        Some(message(status, self.data))
+    }
+}
+
+fn realtime_message(byte: u8) -> Option<MidiMessage> {
+    match byte {
+        0xF8 => Some(MidiMessage::TimingClock),
+        0xFA => Some(MidiMessage::Start),
+        0xFB => Some(MidiMessage::Continue),
+        0xFC => Some(MidiMessage::Stop),
+        0xFE => Some(MidiMessage::ActiveSensing),
+        0xFF => Some(MidiMessage::Reset),
+        _ => None,
+    }
+}
+
+/// Returns how many data bytes follow a status byte.
+fn data_len(status: u8) -> usize {
+    match status {
+        0xC0..=0xDF | 0xF1 | 0xF3 => 1,
+        _ => 2,
+    }
+}
+
+fn message(status: u8, data: [u8; 2]) -> MidiMessage {
+    let channel = status & 0x0F;
+    let [first, second] = data;
+    match status & 0xF0 {
+        0x80 => MidiMessage::NoteOff {
+            channel,
+            note: first,
+            velocity: second,
+        },
+        0x90 if second == 0 => MidiMessage::NoteOff {
+            channel,
+            note: first,
+            velocity: 0,
+        },
+        0x90 => MidiMessage::NoteOn {
+            channel,
+            note: first,
+            velocity: second,
+        },
+        0xA0 => MidiMessage::PolyPressure {
+            channel,
+            note: first,
+            pressure: second,
+        },
+        0xB0 => MidiMessage::ControlChange {
+            channel,
+            controller: first,
+            value: second,
+        },
+        0xC0 => MidiMessage::ProgramChange {
+            channel,
+            program: first,
+        },
+        0xD0 => MidiMessage::ChannelPressure {
+            channel,
+            pressure: first,
+        },
+        0xE0 => MidiMessage::PitchBend {
+            channel,
+            value: first as u16 | (second as u16) << 7,
+        },
+        _ => match status {
+            0xF1 => MidiMessage::TimeCodeQuarterFrame(first),
+            0xF2 => MidiMessage::SongPosition(first as u16 | (second as u16) << 7),
+            _ => MidiMessage::SongSelect(first),
+        },
+    }
+}
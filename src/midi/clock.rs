@@ -0,0 +1,130 @@
+//! This follows an external MIDI clock, so animations stay locked to a DJ's or sequencer's tempo
+//! instead of drifting away from it. The clock drives a `Tempo`, so anything built on the tempo,
+//! such as `BeatSync` and `BeatTrigger`, follows the MIDI clock as well.
+//!
+//! - Timing clock messages set the tempo from how far apart they arrive, and move the beat
+//!   position forward by one tick each while the clock is running.
+//! - Start rewinds the song to the beginning, and restarts every animation in the controller.
+//! - Stop pauses the beat position, and Continue picks up where it stopped.
+//! - Song position pointers move the beat position and every animation to that point in the song.
+
+use crate::colors::Pixel;
+use crate::midi::MidiMessage;
+use crate::tempo::{Beats, Tempo};
+use crate::LightingController;
+use arrayvec::ArrayVec;
+
+/// MIDI clocks send 24 timing clock messages per quarter note.
+pub const TICKS_PER_BEAT: u32 = 24;
+
+/// Song position pointers count in sixteenth notes, which are 6 ticks long.
+pub const TICKS_PER_SONG_POSITION: u32 = 6;
+
+/// If timing clock messages stop for longer than this, the tempo is measured from scratch once
+/// they start again, rather than averaging the gap in. This is the tick length at 10 BPM.
+pub const TICK_TIMEOUT_NS: u64 = 250_000_000;
+
+// The tempo is averaged over a beat's worth of ticks to smooth out jitter:
+const MAX_TICK_TIMES: usize = TICKS_PER_BEAT as usize + 1;
+
+pub struct MidiClock {
+    is_running: bool,
+    position_ticks: u64,
+    tick_times: ArrayVec<u64, MAX_TICK_TIMES>,
+}
+
+impl MidiClock {
+    pub fn new() -> Self {
+        MidiClock {
+            is_running: false,
+            position_ticks: 0,
+            tick_times: ArrayVec::new(),
+        }
+    }
+
+    /// Returns true between a Start or Continue message and the next Stop message.
+    pub fn is_running(&self) -> bool {
+        self.is_running
+    }
+
+    /// Returns the position of the next tick, counted in ticks from the start of the song. The
+    /// first tick after a Start message is tick 0.
+    pub fn position_ticks(&self) -> u64 {
+        self.position_ticks
+    }
+
+    /// Handles the clock messages, Start, Stop, Continue, and song position pointers, arriving at
+    /// `now_ns`. Returns false for any other message, so it can be handed on to a `MidiMap`.
+    pub fn handle<'a, const N_ANI: usize, P: Pixel>(
+        &mut self,
+        message: &MidiMessage,
+        now_ns: u64,
+        tempo: &mut Tempo,
+        controller: &mut LightingController<'a, N_ANI, P>,
+    ) -> bool {
+        match *message {
+            MidiMessage::TimingClock => {
+                self.measure_tempo(now_ns, tempo);
+                if self.is_running {
+                    tempo.seek_ns(now_ns, self.position());
+                    self.position_ticks += 1;
+                }
+            }
+            MidiMessage::Start => {
+                self.is_running = true;
+                self.position_ticks = 0;
+                tempo.seek_ns(now_ns, self.position());
+                tempo.resume_ns(now_ns);
+                controller.seek_ns(0);
+            }
+            MidiMessage::Continue => {
+                self.is_running = true;
+                tempo.resume_ns(now_ns);
+            }
+            MidiMessage::Stop => {
+                self.is_running = false;
+                tempo.pause_ns(now_ns);
+            }
+            MidiMessage::SongPosition(sixteenths) => {
+                self.position_ticks = sixteenths as u64 * TICKS_PER_SONG_POSITION as u64;
+                tempo.seek_ns(now_ns, self.position());
+                controller.seek_ns(tempo.duration_ns(self.position()));
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    fn position(&self) -> Beats {
+        let ticks = self.position_ticks.min(u32::MAX as u64) as u32;
+        Beats::fraction(ticks, TICKS_PER_BEAT)
+    }
+
+    /// Sets the tempo from the average spacing of the most recent ticks.
+    fn measure_tempo(&mut self, now_ns: u64, tempo: &mut Tempo) {
+        let is_late = match self.tick_times.last() {
+            Some(&last) => now_ns < last || now_ns - last > TICK_TIMEOUT_NS,
+            None => false,
+        };
+        if is_late {
+            self.tick_times.clear();
+        }
+        if self.tick_times.is_full() {
+            self.tick_times.remove(0);
+        }
+        self.tick_times.push(now_ns);
+
+        if let (Some(&first), Some(&last)) = (self.tick_times.first(), self.tick_times.last()) {
+            let intervals = self.tick_times.len() as u64 - 1;
+            if let Some(beat_ns) = ((last - first) * TICKS_PER_BEAT as u64).checked_div(intervals) {
+                tempo.update_beat_ns(beat_ns);
+            }
+        }
+    }
+}
+
+impl Default for MidiClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,310 @@
+//! A MidiMap describes what each MIDI message should do to a LightingController: notes fire
+//! triggers, control changes move offsets, durations and brightness, and program changes load
+//! presets. Every mapping can be limited to a single channel, or listen on all of them.
+
+use crate::animations::{trigger, AnimationParameters, AnimationType, MAX_OFFSET};
+use crate::colors::{scale_channel, Pixel};
+use crate::midi::MidiMessage;
+use crate::LightingController;
+use rgb::RGB8;
+
+/// This is what the velocity of a note changes about its trigger.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum VelocityTarget {
+    /// The trigger is the same no matter how hard the note is played.
+    Ignore,
+
+    /// The trigger's brightness is scaled by the velocity, so softer notes make dimmer triggers.
+    /// The rest of the animation and any other triggers aren't affected.
+    Brightness,
+
+    /// The trigger's `pixels_per_pixel_group` is scaled by the velocity, so harder notes make
+    /// wider shots and pulses.
+    Width,
+}
+
+/// This fires a trigger on an animation when a note in a range is played.
+#[derive(Copy, Clone)]
+pub struct NoteMapping<P = RGB8> {
+    pub channel: Option<u8>,
+    pub low_note: u8,
+    pub high_note: u8,
+    pub slot: usize,
+    pub params: trigger::Parameters<P>,
+    pub velocity: VelocityTarget,
+}
+
+impl<P: Pixel> NoteMapping<P> {
+    /// Maps a single note on any channel to a trigger on the animation at `slot`.
+    pub fn new(note: u8, slot: usize, params: trigger::Parameters<P>) -> Self {
+        NoteMapping {
+            channel: None,
+            low_note: note,
+            high_note: note,
+            slot,
+            params,
+            velocity: VelocityTarget::Ignore,
+        }
+    }
+
+    pub fn set_channel(mut self, channel: u8) -> Self {
+        self.channel = Some(channel);
+        self
+    }
+
+    /// Makes every note from `low_note` to `high_note`, inclusive, fire the trigger.
+    pub fn set_note_range(mut self, low_note: u8, high_note: u8) -> Self {
+        self.low_note = low_note;
+        self.high_note = high_note;
+        self
+    }
+
+    pub fn set_velocity(mut self, target: VelocityTarget) -> Self {
+        self.velocity = target;
+        self
+    }
+
+    fn matches(&self, channel: u8, note: u8) -> bool {
+        channel_matches(self.channel, channel) && (self.low_note..=self.high_note).contains(&note)
+    }
+}
+
+/// This is what a control change sets. Durations are scaled linearly from `min_ns` at a value of 0
+/// to `max_ns` at a value of 127.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CcTarget {
+    /// Sets the offset of one part of the animation across its whole range.
+    Offset(AnimationType),
+    /// Sets the master brightness of the LightingController. The slot is ignored.
+    Brightness,
+    LayerBrightness,
+    LayerOpacity,
+    BgDurationNs {
+        min_ns: u64,
+        max_ns: u64,
+    },
+    FgDurationNs {
+        min_ns: u64,
+        max_ns: u64,
+    },
+    FgStepTimeNs {
+        min_ns: u64,
+        max_ns: u64,
+    },
+    TriggerDurationNs {
+        min_ns: u64,
+        max_ns: u64,
+    },
+}
+
+/// This applies a control change to the animation at `slot`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct CcMapping {
+    pub channel: Option<u8>,
+    pub controller: u8,
+    pub slot: usize,
+    pub target: CcTarget,
+}
+
+impl CcMapping {
+    /// Maps a controller number on any channel.
+    pub fn new(controller: u8, slot: usize, target: CcTarget) -> Self {
+        CcMapping {
+            channel: None,
+            controller,
+            slot,
+            target,
+        }
+    }
+
+    pub fn set_channel(mut self, channel: u8) -> Self {
+        self.channel = Some(channel);
+        self
+    }
+}
+
+/// This holds every mapping from MIDI messages to the LightingController.
+///
+/// Program changes load the preset with the same number into the animation at `preset_slot`.
+/// Programs past the end of `presets` are ignored. To step through a `Playlist` instead, match on
+/// `MidiMessage::ProgramChange` before handing messages to the map, and call `Playlist::go_to()`.
+pub struct MidiMap<'a, P = RGB8> {
+    pub notes: &'a [NoteMapping<P>],
+    pub controls: &'a [CcMapping],
+    pub presets: &'a [AnimationParameters<'a, P>],
+    pub preset_slot: usize,
+    pub preset_channel: Option<u8>,
+}
+
+impl<'a, P: Pixel> MidiMap<'a, P> {
+    /// Creates a map that doesn't respond to anything.
+    pub fn new() -> Self {
+        MidiMap {
+            notes: &[],
+            controls: &[],
+            presets: &[],
+            preset_slot: 0,
+            preset_channel: None,
+        }
+    }
+
+    pub fn set_notes(mut self, notes: &'a [NoteMapping<P>]) -> Self {
+        self.notes = notes;
+        self
+    }
+
+    pub fn set_controls(mut self, controls: &'a [CcMapping]) -> Self {
+        self.controls = controls;
+        self
+    }
+
+    pub fn set_presets(mut self, slot: usize, presets: &'a [AnimationParameters<'a, P>]) -> Self {
+        self.preset_slot = slot;
+        self.presets = presets;
+        self
+    }
+
+    pub fn set_preset_channel(mut self, channel: u8) -> Self {
+        self.preset_channel = Some(channel);
+        self
+    }
+
+    /// Applies every mapping that matches the message to the controller, returning true if any
+    /// of them did. Mappings for a slot the controller doesn't have are skipped.
+    pub fn handle<const N_ANI: usize>(
+        &self,
+        message: &MidiMessage,
+        controller: &mut LightingController<'a, N_ANI, P>,
+    ) -> bool {
+        match *message {
+            MidiMessage::NoteOn {
+                channel,
+                note,
+                velocity,
+            } => self.handle_note(channel, note, velocity, controller),
+            MidiMessage::ControlChange {
+                channel,
+                controller: number,
+                value,
+            } => self.handle_control(channel, number, value, controller),
+            MidiMessage::ProgramChange { channel, program } => {
+                let preset = self.presets.get(program as usize);
+                match preset.filter(|_| channel_matches(self.preset_channel, channel)) {
+                    Some(preset) => {
+                        let frame_rate = controller.frame_rate;
+                        match controller.animations.get_mut(self.preset_slot) {
+                            Some(animation) => {
+                                animation.update_parameters(preset, frame_rate);
+                                true
+                            }
+                            None => false,
+                        }
+                    }
+                    None => false,
+                }
+            }
+            _ => false,
+        }
+    }
+
+    fn handle_note<const N_ANI: usize>(
+        &self,
+        channel: u8,
+        note: u8,
+        velocity: u8,
+        controller: &mut LightingController<'a, N_ANI, P>,
+    ) -> bool {
+        let mut is_handled = false;
+        for mapping in self.notes.iter().filter(|m| m.matches(channel, note)) {
+            let mut params = mapping.params;
+            match mapping.velocity {
+                VelocityTarget::Ignore => {}
+                VelocityTarget::Brightness => {
+                    params.brightness = scale_channel(params.brightness, scale_to_u8(velocity));
+                }
+                VelocityTarget::Width => {
+                    let width = params.pixels_per_pixel_group * velocity as usize / 127;
+                    params.pixels_per_pixel_group = width.max(1);
+                }
+            }
+            if controller.try_trigger(mapping.slot, &params).is_ok() {
+                is_handled = true;
+            }
+        }
+        is_handled
+    }
+
+    fn handle_control<const N_ANI: usize>(
+        &self,
+        channel: u8,
+        number: u8,
+        value: u8,
+        controller: &mut LightingController<'a, N_ANI, P>,
+    ) -> bool {
+        let mut is_handled = false;
+        let matches =
+            |m: &&CcMapping| m.controller == number && channel_matches(m.channel, channel);
+        for mapping in self.controls.iter().filter(matches) {
+            let frame_rate = controller.frame_rate;
+            let duration = |min_ns: u64, max_ns: u64| {
+                let range = max_ns.saturating_sub(min_ns);
+                min_ns + (range as u128 * value as u128 / 127) as u64
+            };
+            if mapping.target == CcTarget::Brightness {
+                controller.update_brightness(scale_to_u8(value));
+                is_handled = true;
+                continue;
+            }
+            // Every other target changes the animation at the mapping's slot:
+            let Some(animation) = controller.animations.get_mut(mapping.slot) else {
+                continue;
+            };
+            let mut layer = animation.layer();
+
+            match mapping.target {
+                CcTarget::Offset(a_type) => {
+                    let offset = (MAX_OFFSET as u32 * value as u32 / 127) as u16;
+                    animation.set_offset(a_type, offset);
+                }
+                CcTarget::Brightness => {}
+                CcTarget::LayerBrightness => {
+                    layer.brightness = scale_to_u8(value);
+                    animation.update_layer(layer);
+                }
+                CcTarget::LayerOpacity => {
+                    layer.opacity = scale_to_u8(value);
+                    animation.update_layer(layer);
+                }
+                CcTarget::BgDurationNs { min_ns, max_ns } => {
+                    animation.update_bg_duration_ns(duration(min_ns, max_ns), frame_rate)
+                }
+                CcTarget::FgDurationNs { min_ns, max_ns } => {
+                    animation.update_fg_duration_ns(duration(min_ns, max_ns), frame_rate)
+                }
+                CcTarget::FgStepTimeNs { min_ns, max_ns } => {
+                    animation.update_fg_step_time_ns(duration(min_ns, max_ns), frame_rate)
+                }
+                CcTarget::TriggerDurationNs { min_ns, max_ns } => {
+                    animation.update_trig_duration_ns(duration(min_ns, max_ns), frame_rate)
+                }
+            }
+            is_handled = true;
+        }
+        is_handled
+    }
+}
+
+impl<'a, P: Pixel> Default for MidiMap<'a, P> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn channel_matches(filter: Option<u8>, channel: u8) -> bool {
+    filter.is_none_or(|c| c == channel)
+}
+
+/// Stretches a 7 bit MIDI value to the full range of a u8, so that 127 is fully on.
+fn scale_to_u8(value: u8) -> u8 {
+    (value << 1) | (value >> 6)
+}
@@ -0,0 +1,594 @@
+//! This parses Open Sound Control 1.0 packets, as sent over UDP by TouchOSC and most show control
+//! software, and `router` turns the messages into actions on a LightingController. Parsing doesn't
+//! allocate or copy: messages and their arguments borrow from the packet.
+//!
+//! A packet is either a single message, or a bundle of messages and other bundles along with a
+//! time tag saying when they should take effect. Bundles for the future can be held back until
+//! they're due with a `scheduler::OscScheduler`.
+
+pub mod router;
+pub mod scheduler;
+
+/// Bundles nested deeper than this are rejected, so a malicious packet can't overflow the stack.
+pub const MAX_BUNDLE_DEPTH: usize = 8;
+
+const BUNDLE_TAG: &[u8] = b"#bundle\0";
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum OscError {
+    /// The packet ends in the middle of something.
+    TooShort,
+    /// The packet is neither a message nor a bundle.
+    InvalidPacket,
+    /// A string isn't terminated, padded or valid UTF-8.
+    InvalidString,
+    /// The type tags don't start with a comma.
+    InvalidTypeTags,
+    /// An argument has a type tag this parser doesn't know, such as an array.
+    UnsupportedType(u8),
+    /// A bundle element's size isn't a multiple of 4, or runs past the end of the bundle.
+    InvalidBundle,
+    /// Bundles are nested more than `MAX_BUNDLE_DEPTH` deep.
+    NestedTooDeep,
+    /// The scheduler doesn't have room for another message.
+    SchedulerFull,
+    /// A message is too long for the scheduler to hold.
+    MessageTooLong,
+}
+
+/// An NTP timestamp, counting seconds since 1900 in the upper 32 bits and fractions of a second in
+/// the lower 32 bits. The time tag of 1 means "immediately".
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TimeTag(pub u64);
+
+impl TimeTag {
+    pub const IMMEDIATELY: TimeTag = TimeTag(1);
+
+    /// Converts a number of nanoseconds since the NTP epoch into a time tag.
+    pub fn from_ns(ns: u64) -> Self {
+        let seconds = ns / 1_000_000_000;
+        let fraction = ((ns % 1_000_000_000) << 32) / 1_000_000_000;
+        TimeTag(seconds << 32 | fraction)
+    }
+
+    /// Returns the number of nanoseconds since the NTP epoch, saturating at `u64::MAX`.
+    pub fn to_ns(self) -> u64 {
+        let seconds = self.0 >> 32;
+        let fraction = ((self.0 & 0xFFFF_FFFF) * 1_000_000_000) >> 32;
+        seconds
+            .saturating_mul(1_000_000_000)
+            .saturating_add(fraction)
+    }
+
+    pub fn is_immediate(self) -> bool {
+        self == Self::IMMEDIATELY
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum OscArg<'p> {
+    Int(i32),
+    Float(f32),
+    String(&'p str),
+    Blob(&'p [u8]),
+    Long(i64),
+    Double(f64),
+    TimeTag(TimeTag),
+    /// An alternate string type, sent by some implementations for names.
+    Symbol(&'p str),
+    Char(char),
+    /// A 32 bit RGBA color.
+    Color(u32),
+    /// A MIDI message: the port, status byte and two data bytes.
+    Midi([u8; 4]),
+    True,
+    False,
+    Nil,
+    Impulse,
+}
+
+impl<'p> OscArg<'p> {
+    /// Returns the argument as a float, for any of the number types.
+    pub fn as_f64(&self) -> Option<f64> {
+        match *self {
+            OscArg::Int(value) => Some(value as f64),
+            OscArg::Float(value) => Some(value as f64),
+            OscArg::Long(value) => Some(value as f64),
+            OscArg::Double(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Returns the argument as a whole number, for the integer types.
+    pub fn as_i64(&self) -> Option<i64> {
+        match *self {
+            OscArg::Int(value) => Some(value as i64),
+            OscArg::Long(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Returns the text of a string or symbol.
+    pub fn as_str(&self) -> Option<&'p str> {
+        match *self {
+            OscArg::String(value) | OscArg::Symbol(value) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum OscPacket<'p> {
+    Message(OscMessage<'p>),
+    Bundle(OscBundle<'p>),
+}
+
+impl<'p> OscPacket<'p> {
+    pub fn parse(bytes: &'p [u8]) -> Result<Self, OscError> {
+        match bytes.first() {
+            Some(b'#') => OscBundle::parse(bytes).map(OscPacket::Bundle),
+            Some(b'/') => OscMessage::parse(bytes).map(OscPacket::Message),
+            Some(_) => Err(OscError::InvalidPacket),
+            None => Err(OscError::TooShort),
+        }
+    }
+
+    /// Calls `on_message` for every message in the packet, along with the time tag of the bundle
+    /// it's in, or `None` if the packet is a lone message.
+    pub fn for_each_message(
+        &self,
+        mut on_message: impl FnMut(Option<TimeTag>, &OscMessage<'p>),
+    ) -> Result<(), OscError> {
+        self.visit(None, 0, &mut on_message)
+    }
+
+    fn visit(
+        &self,
+        time: Option<TimeTag>,
+        depth: usize,
+        on_message: &mut impl FnMut(Option<TimeTag>, &OscMessage<'p>),
+    ) -> Result<(), OscError> {
+        match self {
+            OscPacket::Message(message) => on_message(time, message),
+            OscPacket::Bundle(_) if depth >= MAX_BUNDLE_DEPTH => {
+                return Err(OscError::NestedTooDeep)
+            }
+            OscPacket::Bundle(bundle) => {
+                for element in bundle.elements() {
+                    element?.visit(Some(bundle.time), depth + 1, on_message)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct OscBundle<'p> {
+    pub time: TimeTag,
+    elements: &'p [u8],
+}
+
+impl<'p> OscBundle<'p> {
+    pub fn parse(bytes: &'p [u8]) -> Result<Self, OscError> {
+        if !bytes.starts_with(BUNDLE_TAG) {
+            return Err(OscError::InvalidPacket);
+        }
+        let time = read_u64(bytes, BUNDLE_TAG.len()).ok_or(OscError::TooShort)?;
+        let bundle = OscBundle {
+            time: TimeTag(time),
+            elements: &bytes[BUNDLE_TAG.len() + 8..],
+        };
+
+        // Check the framing of every element up front, so iterating can't go out of bounds:
+        let mut remaining = bundle.elements;
+        while !remaining.is_empty() {
+            let size = read_u32(remaining, 0).ok_or(OscError::InvalidBundle)? as usize;
+            if !size.is_multiple_of(4) || size > remaining.len() - 4 {
+                return Err(OscError::InvalidBundle);
+            }
+            remaining = &remaining[4 + size..];
+        }
+        Ok(bundle)
+    }
+
+    /// Returns the messages and bundles in this bundle, each of which is parsed as it's reached.
+    pub fn elements(&self) -> impl Iterator<Item = Result<OscPacket<'p>, OscError>> {
+        let mut remaining = self.elements;
+        core::iter::from_fn(move || {
+            let size = read_u32(remaining, 0)? as usize;
+            let element = &remaining[4..4 + size];
+            remaining = &remaining[4 + size..];
+            Some(OscPacket::parse(element))
+        })
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct OscMessage<'p> {
+    /// The address pattern the message is sent to, such as `/anim/0/bg/mode`.
+    pub address: &'p str,
+    type_tags: &'p [u8],
+    arguments: &'p [u8],
+    bytes: &'p [u8],
+}
+
+impl<'p> OscMessage<'p> {
+    pub fn parse(bytes: &'p [u8]) -> Result<Self, OscError> {
+        let (address, rest) = read_string(bytes)?;
+        if !address.starts_with('/') {
+            return Err(OscError::InvalidPacket);
+        }
+        // Some old senders leave out the type tags of messages without arguments:
+        let (type_tags, arguments) = match rest.is_empty() {
+            true => ("", rest),
+            false => {
+                let (type_tags, arguments) = read_string(rest)?;
+                let type_tags = type_tags
+                    .strip_prefix(',')
+                    .ok_or(OscError::InvalidTypeTags)?;
+                (type_tags, arguments)
+            }
+        };
+
+        let message = OscMessage {
+            address,
+            type_tags: type_tags.as_bytes(),
+            arguments,
+            bytes,
+        };
+        // Check every argument up front, so iterating over them can't fail:
+        let mut remaining = message.arguments;
+        for &tag in message.type_tags {
+            remaining = read_arg(tag, remaining)?.1;
+        }
+        Ok(message)
+    }
+
+    pub fn args(&self) -> impl Iterator<Item = OscArg<'p>> {
+        let mut remaining = self.arguments;
+        self.type_tags.iter().filter_map(move |&tag| {
+            let (arg, rest) = read_arg(tag, remaining).ok()?;
+            remaining = rest;
+            Some(arg)
+        })
+    }
+
+    pub fn arg(&self, index: usize) -> Option<OscArg<'p>> {
+        self.args().nth(index)
+    }
+
+    /// Returns the encoded message, which `parse()` turns back into the same message.
+    pub fn as_bytes(&self) -> &'p [u8] {
+        self.bytes
+    }
+}
+
+/// Patterns longer than this never match. Each part of a pattern is matched on its own, so this is
+/// far longer than any real pattern needs.
+pub const MAX_PATTERN_LEN: usize = 127;
+
+/// The set of places in a pattern that a match could have reached, as a bit for each byte offset,
+/// plus one for the end of the pattern.
+type States = u128;
+
+/// Returns true if an OSC address pattern matches an address. Patterns can use `?` for any single
+/// character, `*` for any run of characters, `[abc]` or `[a-z]` for any one of a set of characters,
+/// `[!abc]` for any character not in a set, and `{foo,bar}` for any one of a list of strings.
+///
+/// Each part of an address between slashes should be matched on its own, as `*` and `?` never
+/// match a `/`. Patterns longer than `MAX_PATTERN_LEN` never match.
+pub fn pattern_matches(pattern: &str, address: &str) -> bool {
+    matches(pattern.as_bytes(), address.as_bytes())
+}
+
+// This follows every way the pattern could match at once, one address character at a time, rather
+// than backtracking, which takes exponential time on patterns like `*a*a*a*a` or `{,}{,}{,}{,}`
+// that anyone on the network could send.
+fn matches(pattern: &[u8], address: &[u8]) -> bool {
+    if pattern.len() > MAX_PATTERN_LEN {
+        return false;
+    }
+    let choices = choice_bytes(pattern);
+    let mut states = follow_empty(pattern, choices, 1);
+    for &c in address {
+        if states == 0 {
+            return false;
+        }
+        states = follow_empty(pattern, choices, step(pattern, choices, states, c));
+    }
+    states & 1 << pattern.len() != 0
+}
+
+/// Returns the bytes of a pattern inside `{}`, including the commas and closing brace, which are
+/// literal characters rather than wildcards.
+fn choice_bytes(pattern: &[u8]) -> States {
+    let mut choices = 0;
+    let mut i = 0;
+    while i < pattern.len() {
+        let close = match pattern[i] {
+            b'[' => b']',
+            b'{' => b'}',
+            _ => {
+                i += 1;
+                continue;
+            }
+        };
+        // Nothing after an unclosed bracket or brace can match:
+        let Some(end) = find(pattern, i + 1, close) else {
+            break;
+        };
+        if close == b'}' {
+            choices |= (1 << (end + 1)) - (1 << (i + 1));
+        }
+        i = end + 1;
+    }
+    choices
+}
+
+/// Adds the states that can be reached without matching a character, such as skipping a `*`.
+fn follow_empty(pattern: &[u8], choices: States, mut states: States) -> States {
+    loop {
+        let mut next = states;
+        for p in (0..pattern.len()).filter(|&p| states & 1 << p != 0) {
+            let is_choice = choices & 1 << p != 0;
+            match pattern[p] {
+                b',' | b'}' if is_choice => {
+                    // The end of one of the choices skips the rest of them:
+                    if let Some(end) = find(pattern, p, b'}') {
+                        next |= 1 << (end + 1);
+                    }
+                }
+                b'*' if !is_choice => next |= 1 << (p + 1),
+                b'{' if !is_choice => {
+                    // Every choice starts either after the brace or after a comma:
+                    if let Some(end) = find(pattern, p, b'}') {
+                        next |= 1 << (p + 1);
+                        for comma in (p + 1..end).filter(|&q| pattern[q] == b',') {
+                            next |= 1 << (comma + 1);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        if next == states {
+            return states;
+        }
+        states = next;
+    }
+}
+
+/// Returns the states reached by matching `c` from any of `states`.
+fn step(pattern: &[u8], choices: States, states: States, c: u8) -> States {
+    let mut next = 0;
+    for p in (0..pattern.len()).filter(|&p| states & 1 << p != 0) {
+        if choices & 1 << p != 0 {
+            if !matches!(pattern[p], b',' | b'}') && pattern[p] == c {
+                next |= 1 << (p + 1);
+            }
+            continue;
+        }
+        next |= match pattern[p] {
+            b'*' if c != b'/' => 1 << p,
+            b'?' if c != b'/' => 1 << (p + 1),
+            b'[' => match find(pattern, p + 1, b']') {
+                Some(end) => {
+                    let set = &pattern[p + 1..end];
+                    let (is_negated, set) = match set.split_first() {
+                        Some((b'!', set)) => (true, set),
+                        _ => (false, set),
+                    };
+                    match set_contains(set, c) != is_negated {
+                        true => 1 << (end + 1),
+                        false => 0,
+                    }
+                }
+                None => 0,
+            },
+            b'*' | b'?' | b'{' => 0,
+            literal if literal == c => 1 << (p + 1),
+            _ => 0,
+        };
+    }
+    next
+}
+
+/// Returns the offset of the first `byte` in `pattern` at or after `from`.
+fn find(pattern: &[u8], from: usize, byte: u8) -> Option<usize> {
+    pattern[from..]
+        .iter()
+        .position(|&b| b == byte)
+        .map(|i| from + i)
+}
+
+fn set_contains(set: &[u8], c: u8) -> bool {
+    let mut i = 0;
+    while i < set.len() {
+        // A dash between two characters makes a range, anywhere else it's just a dash:
+        if i + 2 < set.len() && set[i + 1] == b'-' {
+            if (set[i]..=set[i + 2]).contains(&c) {
+                return true;
+            }
+            i += 3;
+        } else {
+            if set[i] == c {
+                return true;
+            }
+            i += 1;
+        }
+    }
+    false
+}
+
+/// Reads one argument, returning it and the bytes after it.
+fn read_arg(tag: u8, bytes: &[u8]) -> Result<(OscArg<'_>, &[u8]), OscError> {
+    let word = |len: usize| bytes.get(len..).ok_or(OscError::TooShort);
+    Ok(match tag {
+        b'i' => (
+            OscArg::Int(read_u32(bytes, 0).ok_or(OscError::TooShort)? as i32),
+            word(4)?,
+        ),
+        b'f' => {
+            let bits = read_u32(bytes, 0).ok_or(OscError::TooShort)?;
+            (OscArg::Float(f32::from_bits(bits)), word(4)?)
+        }
+        b'r' => (
+            OscArg::Color(read_u32(bytes, 0).ok_or(OscError::TooShort)?),
+            word(4)?,
+        ),
+        b'c' => {
+            let code = read_u32(bytes, 0).ok_or(OscError::TooShort)?;
+            let c = char::from_u32(code).ok_or(OscError::UnsupportedType(tag))?;
+            (OscArg::Char(c), word(4)?)
+        }
+        b'm' => {
+            let midi = bytes.get(..4).ok_or(OscError::TooShort)?;
+            (OscArg::Midi([midi[0], midi[1], midi[2], midi[3]]), word(4)?)
+        }
+        b'h' => (
+            OscArg::Long(read_u64(bytes, 0).ok_or(OscError::TooShort)? as i64),
+            word(8)?,
+        ),
+        b'd' => {
+            let bits = read_u64(bytes, 0).ok_or(OscError::TooShort)?;
+            (OscArg::Double(f64::from_bits(bits)), word(8)?)
+        }
+        b't' => (
+            OscArg::TimeTag(TimeTag(read_u64(bytes, 0).ok_or(OscError::TooShort)?)),
+            word(8)?,
+        ),
+        b's' => {
+            let (string, rest) = read_string(bytes)?;
+            (OscArg::String(string), rest)
+        }
+        b'S' => {
+            let (string, rest) = read_string(bytes)?;
+            (OscArg::Symbol(string), rest)
+        }
+        b'b' => {
+            let len = read_u32(bytes, 0).ok_or(OscError::TooShort)? as usize;
+            // The length comes from the packet, so it could be anything:
+            let end = len.checked_add(4).ok_or(OscError::TooShort)?;
+            let blob = bytes.get(4..end).ok_or(OscError::TooShort)?;
+            (OscArg::Blob(blob), word(padded(end))?)
+        }
+        b'T' => (OscArg::True, bytes),
+        b'F' => (OscArg::False, bytes),
+        b'N' => (OscArg::Nil, bytes),
+        b'I' => (OscArg::Impulse, bytes),
+        _ => return Err(OscError::UnsupportedType(tag)),
+    })
+}
+
+/// Reads a null terminated string padded to a multiple of 4 bytes, returning it and the bytes
+/// after the padding.
+fn read_string(bytes: &[u8]) -> Result<(&str, &[u8]), OscError> {
+    let len = bytes
+        .iter()
+        .position(|&b| b == 0)
+        .ok_or(OscError::InvalidString)?;
+    let rest = bytes
+        .get(padded(len + 1)..)
+        .ok_or(OscError::InvalidString)?;
+    let string = core::str::from_utf8(&bytes[..len]).map_err(|_| OscError::InvalidString)?;
+    Ok((string, rest))
+}
+
+/// Rounds a length up to the next multiple of 4.
+fn padded(len: usize) -> usize {
+    len.div_ceil(4) * 4
+}
+
+fn read_u32(bytes: &[u8], index: usize) -> Option<u32> {
+    let word = bytes.get(index..index + 4)?;
+    Some(u32::from_be_bytes([word[0], word[1], word[2], word[3]]))
+}
+
+fn read_u64(bytes: &[u8], index: usize) -> Option<u64> {
+    Some((read_u32(bytes, index)? as u64) << 32 | read_u32(bytes, index + 4)? as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Wraps a message to `/a` in `depth` bundles, building it backwards from the end of `buffer`.
+    fn nested(buffer: &mut [u8; 512], depth: usize) -> &[u8] {
+        let mut start = buffer.len() - 8;
+        buffer[start..].copy_from_slice(b"/a\0\0,\0\0\0");
+        for _ in 0..depth {
+            let size = (buffer.len() - start) as u32;
+            start -= 4;
+            buffer[start..start + 4].copy_from_slice(&size.to_be_bytes());
+            start -= 16;
+            buffer[start..start + 8].copy_from_slice(BUNDLE_TAG);
+            buffer[start + 8..start + 16].copy_from_slice(&TimeTag::IMMEDIATELY.0.to_be_bytes());
+        }
+        &buffer[start..]
+    }
+
+    #[test]
+    fn matches_wildcards() {
+        assert!(pattern_matches("/anim/?/bg/*", "/anim/0/bg/mode"));
+        assert!(!pattern_matches("/anim/*", "/anim/0/bg"));
+        assert!(pattern_matches("/anim/[0-2]", "/anim/1"));
+        assert!(pattern_matches("/{bg,fg}/mode", "/fg/mode"));
+        assert!(!pattern_matches("/{bg,fg}/mode", "/trigger/mode"));
+    }
+
+    #[test]
+    fn matches_empty_choices() {
+        assert!(pattern_matches("{,x}y", "y"));
+        assert!(pattern_matches("{,x}y", "xy"));
+        assert!(!pattern_matches("{,x}y", "xxy"));
+        assert!(!pattern_matches("{,x}y", "x"));
+    }
+
+    #[test]
+    fn matches_negated_sets() {
+        assert!(pattern_matches("[!0-2]", "3"));
+        assert!(pattern_matches("[!0-2]", "a"));
+        assert!(!pattern_matches("[!0-2]", "0"));
+        assert!(!pattern_matches("[!0-2]", "2"));
+        assert!(!pattern_matches("[!0-2]", "33"));
+    }
+
+    #[test]
+    fn gives_up_on_pathological_patterns_quickly() {
+        // Backtracking would try every way of splitting the address between the stars, which
+        // would take far longer than anyone would wait for this test:
+        let pattern = "*a*a*a*a*a*a*a*a*a*a*a*a*a*a*a*a*a*a*a*a*b";
+        let address = [b'a'; 100];
+        let address = core::str::from_utf8(&address).unwrap();
+        assert!(!pattern_matches(pattern, address));
+    }
+
+    #[test]
+    fn rejects_blobs_longer_than_the_packet() {
+        let bytes = b"/a\0\0,b\0\0\xFF\xFF\xFF\xFD\0\0\0\0";
+        assert_eq!(OscPacket::parse(bytes), Err(OscError::TooShort));
+    }
+
+    #[test]
+    fn reads_nested_bundles() {
+        let mut buffer = [0; 512];
+        let packet = OscPacket::parse(nested(&mut buffer, MAX_BUNDLE_DEPTH)).unwrap();
+        let mut count = 0;
+        let result = packet.for_each_message(|time, message| {
+            assert_eq!(time, Some(TimeTag::IMMEDIATELY));
+            assert_eq!(message.address, "/a");
+            count += 1;
+        });
+        assert_eq!(result, Ok(()));
+        assert_eq!(count, 1);
+    }
+
+    #[test]
+    fn rejects_bundles_nested_too_deep() {
+        let mut buffer = [0; 512];
+        let packet = OscPacket::parse(nested(&mut buffer, MAX_BUNDLE_DEPTH + 1)).unwrap();
+        let result = packet.for_each_message(|_, _| panic!("reached the message"));
+        assert_eq!(result, Err(OscError::NestedTooDeep));
+    }
+}
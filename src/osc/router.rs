@@ -0,0 +1,551 @@
+//! An OscRouter applies OSC messages to a LightingController. Its addresses are:
+//!
+//! ```text
+//! /brightness                       level
+//! /anim/<n>/bg/<setting>            value
+//! /anim/<n>/fg/<setting>            value
+//!     mode, direction, duration, rainbow, subdivisions, blend, offset,
+//!     step and width (fg only)
+//! /anim/<n>/trigger                 [mode]
+//! /anim/<n>/trigger/duration        time
+//! /anim/<n>/trigger/rainbow         index [backward]
+//! /anim/<n>/layer/<setting>         value
+//!     z, opacity, brightness, blend
+//! ```
+//!
+//! Messages can use OSC address patterns, so `/anim/*/trigger` fires every animation and
+//! `/anim/{0,2}/bg/mode` changes two at once.
+//!
+//! Arguments are read the way control surfaces send them:
+//!
+//! - Levels, such as brightness and opacity, are floats from 0.0 to 1.0, or integers from 0 to 255.
+//! - Offsets are floats from 0.0 to 1.0 across the whole range, or integers from 0 to 65535.
+//! - Durations are floats in seconds, or integers in milliseconds.
+//! - Modes, directions and blend modes are strings written the same way as in a scene, and
+//!   directions can also be the numbers 1, 0 and -1.
+//! - Rainbows are picked by index from the list given to `OscRouter::set_rainbows()`.
+//!
+//! A trigger fires on a message without arguments, a true or impulse argument, or a number other
+//! than 0, so a button firing on press doesn't fire again on release. A string argument picks the
+//! trigger mode, which otherwise comes from `OscRouter::set_trigger_defaults()`.
+
+use crate::animations::{
+    background, foreground, trigger, Animatable, AnimationType, Direction, RainbowDir, MAX_OFFSET,
+};
+use crate::colors::{BlendMode, Pixel};
+use crate::osc::scheduler::OscScheduler;
+use crate::osc::{pattern_matches, OscArg, OscError, OscMessage, OscPacket, TimeTag};
+use crate::preset::{custom_entry, CustomModes};
+use crate::scene::{
+    argument_of, bg_mode_named, blend_mode_named, direction_named, fg_mode_named, parse_number,
+    rainbow_dir_named, trigger_mode_named,
+};
+use crate::LightingController;
+use embedded_time::rate::Hertz;
+use rgb::RGB8;
+
+#[derive(Copy, Clone)]
+enum Method {
+    Brightness,
+    Background(Setting),
+    Foreground(Setting),
+    Trigger,
+    TriggerDuration,
+    TriggerRainbow,
+    Layer(LayerSetting),
+}
+
+#[derive(Copy, Clone)]
+enum Setting {
+    Mode,
+    Direction,
+    Duration,
+    Rainbow,
+    Subdivisions,
+    Blend,
+    Offset,
+    Step,
+    Width,
+}
+
+#[derive(Copy, Clone)]
+enum LayerSetting {
+    Z,
+    Opacity,
+    Brightness,
+    Blend,
+}
+
+// The settings under each part of `/anim/<n>`:
+const BACKGROUND: [(&str, Method); 7] = [
+    ("mode", Method::Background(Setting::Mode)),
+    ("direction", Method::Background(Setting::Direction)),
+    ("duration", Method::Background(Setting::Duration)),
+    ("rainbow", Method::Background(Setting::Rainbow)),
+    ("subdivisions", Method::Background(Setting::Subdivisions)),
+    ("blend", Method::Background(Setting::Blend)),
+    ("offset", Method::Background(Setting::Offset)),
+];
+const FOREGROUND: [(&str, Method); 9] = [
+    ("mode", Method::Foreground(Setting::Mode)),
+    ("direction", Method::Foreground(Setting::Direction)),
+    ("duration", Method::Foreground(Setting::Duration)),
+    ("rainbow", Method::Foreground(Setting::Rainbow)),
+    ("subdivisions", Method::Foreground(Setting::Subdivisions)),
+    ("blend", Method::Foreground(Setting::Blend)),
+    ("offset", Method::Foreground(Setting::Offset)),
+    ("step", Method::Foreground(Setting::Step)),
+    ("width", Method::Foreground(Setting::Width)),
+];
+const TRIGGER: [(&str, Method); 2] = [
+    ("duration", Method::TriggerDuration),
+    ("rainbow", Method::TriggerRainbow),
+];
+const LAYER: [(&str, Method); 4] = [
+    ("z", Method::Layer(LayerSetting::Z)),
+    ("opacity", Method::Layer(LayerSetting::Opacity)),
+    ("brightness", Method::Layer(LayerSetting::Brightness)),
+    ("blend", Method::Layer(LayerSetting::Blend)),
+];
+const PARTS: [(&str, &[(&str, Method)]); 4] = [
+    ("bg", &BACKGROUND),
+    ("fg", &FOREGROUND),
+    ("trigger", &TRIGGER),
+    ("layer", &LAYER),
+];
+
+pub struct OscRouter<'a, P = RGB8> {
+    rainbows: &'a [&'a [P]],
+    custom: CustomModes<'a, P>,
+    trigger: trigger::Parameters<P>,
+}
+
+impl<'a, P: Pixel> OscRouter<'a, P> {
+    /// Creates a router without any rainbows or custom modes.
+    pub fn new() -> Self {
+        OscRouter {
+            rainbows: &[],
+            custom: CustomModes::none(),
+            trigger: trigger::Parameters {
+                mode: trigger::Mode::NoTrigger,
+                direction: Direction::Positive,
+                fade_in_time_ns: 0,
+                fade_out_time_ns: 500_000_000,
+                starting_offset: 0,
+                pixels_per_pixel_group: 1,
+                brightness: u8::MAX,
+                blend_mode: BlendMode::Replace,
+            },
+        }
+    }
+
+    /// Sets the rainbows that rainbow indexes pick from.
+    pub fn set_rainbows(mut self, rainbows: &'a [&'a [P]]) -> Self {
+        self.rainbows = rainbows;
+        self
+    }
+
+    /// Lets modes be set to `custom(<index>)`, using the same registry as presets and scenes.
+    pub fn set_custom_modes(mut self, custom: CustomModes<'a, P>) -> Self {
+        self.custom = custom;
+        self
+    }
+
+    /// Sets the parameters `/anim/<n>/trigger` fires with. A mode sent with the trigger replaces
+    /// the mode set here.
+    pub fn set_trigger_defaults(mut self, params: trigger::Parameters<P>) -> Self {
+        self.trigger = params;
+        self
+    }
+
+    /// Applies every message in a packet that's due at `now`, and adds the rest to `scheduler`
+    /// to be applied by `update()`. Messages outside of a bundle, and bundles tagged
+    /// `TimeTag::IMMEDIATELY`, are always due. Returns true if anything was applied.
+    ///
+    /// `now` has to count from the same epoch as the sender's clock, which is usually NTP time.
+    pub fn handle_packet<const N_ANI: usize, const N_MESSAGES: usize, const MESSAGE_LEN: usize>(
+        &self,
+        packet: &OscPacket,
+        now: TimeTag,
+        scheduler: &mut OscScheduler<N_MESSAGES, MESSAGE_LEN>,
+        controller: &mut LightingController<'a, N_ANI, P>,
+    ) -> Result<bool, OscError> {
+        let mut is_changed = false;
+        let mut result = Ok(());
+        packet.for_each_message(|time, message| match time {
+            Some(time) if !time.is_immediate() && time > now => {
+                if let Err(error) = scheduler.schedule(time, message) {
+                    result = Err(error);
+                }
+            }
+            _ => is_changed |= self.handle(message, controller),
+        })?;
+        result.map(|_| is_changed)
+    }
+
+    /// Applies the messages in `scheduler` that are due at `now`, in the order of their time tags.
+    /// This should be called every frame. Returns true if anything was applied.
+    pub fn update<const N_ANI: usize, const N_MESSAGES: usize, const MESSAGE_LEN: usize>(
+        &self,
+        now: TimeTag,
+        scheduler: &mut OscScheduler<N_MESSAGES, MESSAGE_LEN>,
+        controller: &mut LightingController<'a, N_ANI, P>,
+    ) -> bool {
+        let mut is_changed = false;
+        scheduler.run_due(now, |message| {
+            is_changed |= self.handle(message, controller)
+        });
+        is_changed
+    }
+
+    /// Applies a message straight away, to every address its pattern matches. Returns false if it
+    /// didn't match any address, or its arguments weren't valid for the addresses it matched.
+    pub fn handle<const N_ANI: usize>(
+        &self,
+        message: &OscMessage,
+        controller: &mut LightingController<'a, N_ANI, P>,
+    ) -> bool {
+        let mut parts = message.address.split('/').skip(1);
+        let (Some(first), second, third, fourth, None) = (
+            parts.next(),
+            parts.next(),
+            parts.next(),
+            parts.next(),
+            parts.next(),
+        ) else {
+            return false;
+        };
+
+        let (Some(index), Some(part)) = (second, third) else {
+            return second.is_none()
+                && pattern_matches(first, "brightness")
+                && self.apply(Method::Brightness, 0, message, controller);
+        };
+        if !pattern_matches(first, "anim") {
+            return false;
+        }
+
+        let mut is_changed = false;
+        for slot in (0..N_ANI).filter(|&slot| index_matches(index, slot)) {
+            for (name, settings) in PARTS {
+                if !pattern_matches(part, name) {
+                    continue;
+                }
+                match fourth {
+                    // Only a trigger has an address without a setting:
+                    None if name == "trigger" => {
+                        is_changed |= self.apply(Method::Trigger, slot, message, controller)
+                    }
+                    None => {}
+                    Some(setting) => {
+                        for &(_, method) in settings
+                            .iter()
+                            .filter(|(name, _)| pattern_matches(setting, name))
+                        {
+                            is_changed |= self.apply(method, slot, message, controller);
+                        }
+                    }
+                }
+            }
+        }
+        is_changed
+    }
+
+    fn apply<const N_ANI: usize>(
+        &self,
+        method: Method,
+        index: usize,
+        message: &OscMessage,
+        controller: &mut LightingController<'a, N_ANI, P>,
+    ) -> bool {
+        let frame_rate = controller.frame_rate;
+        let first = message.arg(0);
+        match method {
+            Method::Brightness => match first.and_then(|arg| level(&arg)) {
+                Some(brightness) => {
+                    controller.update_brightness(brightness);
+                    true
+                }
+                None => false,
+            },
+            Method::Background(setting) => self.background(
+                setting,
+                message,
+                &mut *controller.animations[index],
+                frame_rate,
+            ),
+            Method::Foreground(setting) => self.foreground(
+                setting,
+                message,
+                &mut *controller.animations[index],
+                frame_rate,
+            ),
+            Method::Trigger => {
+                let mut params = self.trigger;
+                match first {
+                    None | Some(OscArg::True) | Some(OscArg::Impulse) => {}
+                    Some(arg) => match arg.as_str() {
+                        Some(name) => match self.trigger_mode(name) {
+                            Some(mode) => params.mode = mode,
+                            None => return false,
+                        },
+                        None => {
+                            if arg.as_f64().is_none_or(|value| value == 0.0) {
+                                return false;
+                            }
+                        }
+                    },
+                }
+                controller.trigger(index, &params);
+                true
+            }
+            Method::TriggerDuration => match first.and_then(|arg| duration_ns(&arg)) {
+                Some(duration) => {
+                    controller.animations[index].update_trig_duration_ns(duration, frame_rate);
+                    true
+                }
+                None => false,
+            },
+            Method::TriggerRainbow => match self.rainbow(message) {
+                Some((rainbow, rainbow_dir)) => {
+                    let animation = &mut controller.animations[index];
+                    animation.update_trig_fade_rainbow(rainbow, rainbow_dir);
+                    animation.update_trig_incremental_rainbow(rainbow, rainbow_dir);
+                    true
+                }
+                None => false,
+            },
+            Method::Layer(setting) => {
+                let Some(arg) = first else {
+                    return false;
+                };
+                let animation = &mut controller.animations[index];
+                let mut layer = animation.layer();
+                let is_valid = match setting {
+                    LayerSetting::Z => number(&arg)
+                        .and_then(|z| i8::try_from(z).ok())
+                        .map(|z| layer.z_order = z)
+                        .is_some(),
+                    LayerSetting::Opacity => level(&arg).map(|v| layer.opacity = v).is_some(),
+                    LayerSetting::Brightness => level(&arg).map(|v| layer.brightness = v).is_some(),
+                    LayerSetting::Blend => blend_mode(&arg)
+                        .map(|mode| layer.blend_mode = mode)
+                        .is_some(),
+                };
+                if is_valid {
+                    animation.update_layer(layer);
+                }
+                is_valid
+            }
+        }
+    }
+
+    fn background(
+        &self,
+        setting: Setting,
+        message: &OscMessage,
+        animation: &mut dyn Animatable<'a, P>,
+        frame_rate: Hertz,
+    ) -> bool {
+        let Some(arg) = message.arg(0) else {
+            return false;
+        };
+        match setting {
+            Setting::Mode => {
+                let Some(name) = arg.as_str() else {
+                    return false;
+                };
+                let mode = match argument_of(name, "custom") {
+                    Some(index) => custom_index(index)
+                        .and_then(|index| custom_entry(self.custom.background, index).ok())
+                        .map(background::Mode::Custom),
+                    None => bg_mode_named(name),
+                };
+                mode.map(|mode| animation.update_bg_mode(mode)).is_some()
+            }
+            Setting::Direction => direction(&arg)
+                .map(|direction| animation.update_bg_direction(direction))
+                .is_some(),
+            Setting::Duration => duration_ns(&arg)
+                .map(|duration| animation.update_bg_duration_ns(duration, frame_rate))
+                .is_some(),
+            Setting::Rainbow => self
+                .rainbow(message)
+                .map(|(rainbow, rainbow_dir)| animation.update_bg_rainbow(rainbow, rainbow_dir))
+                .is_some(),
+            Setting::Subdivisions => count(&arg)
+                .map(|value| animation.update_bg_subdivisions(value))
+                .is_some(),
+            Setting::Blend => blend_mode(&arg)
+                .map(|mode| animation.update_bg_blend_mode(mode))
+                .is_some(),
+            Setting::Offset => offset(&arg)
+                .map(|offset| animation.set_offset(AnimationType::Background, offset))
+                .is_some(),
+            // These are only part of the foreground:
+            Setting::Step | Setting::Width => false,
+        }
+    }
+
+    fn foreground(
+        &self,
+        setting: Setting,
+        message: &OscMessage,
+        animation: &mut dyn Animatable<'a, P>,
+        frame_rate: Hertz,
+    ) -> bool {
+        let Some(arg) = message.arg(0) else {
+            return false;
+        };
+        match setting {
+            Setting::Mode => {
+                let Some(name) = arg.as_str() else {
+                    return false;
+                };
+                let mode = match argument_of(name, "custom") {
+                    Some(index) => custom_index(index)
+                        .and_then(|index| custom_entry(self.custom.foreground, index).ok())
+                        .map(foreground::Mode::Custom),
+                    None => fg_mode_named(name),
+                };
+                mode.map(|mode| animation.update_fg_mode(mode)).is_some()
+            }
+            Setting::Direction => direction(&arg)
+                .map(|direction| animation.update_fg_direction(direction))
+                .is_some(),
+            Setting::Duration => duration_ns(&arg)
+                .map(|duration| animation.update_fg_duration_ns(duration, frame_rate))
+                .is_some(),
+            Setting::Rainbow => self
+                .rainbow(message)
+                .map(|(rainbow, rainbow_dir)| animation.update_fg_rainbow(rainbow, rainbow_dir))
+                .is_some(),
+            Setting::Subdivisions => count(&arg)
+                .map(|value| animation.update_fg_subdivisions(value))
+                .is_some(),
+            Setting::Blend => blend_mode(&arg)
+                .map(|mode| animation.update_fg_blend_mode(mode))
+                .is_some(),
+            Setting::Offset => offset(&arg)
+                .map(|offset| animation.set_offset(AnimationType::Foreground, offset))
+                .is_some(),
+            Setting::Step => duration_ns(&arg)
+                .map(|duration| animation.update_fg_step_time_ns(duration, frame_rate))
+                .is_some(),
+            Setting::Width => count(&arg)
+                .map(|value| animation.update_fg_pixels_per_pixel_group(value))
+                .is_some(),
+        }
+    }
+
+    fn trigger_mode(&self, name: &str) -> Option<trigger::Mode<P>> {
+        match argument_of(name, "custom") {
+            Some(index) => custom_index(index)
+                .and_then(|index| custom_entry(self.custom.trigger, index).ok())
+                .map(trigger::Mode::Custom),
+            None => trigger_mode_named(name),
+        }
+    }
+
+    /// Reads a rainbow index, and the `forward` or `backward` that can follow it.
+    fn rainbow(&self, message: &OscMessage) -> Option<(&'a [P], RainbowDir)> {
+        let index = count(&message.arg(0)?)?;
+        let rainbow = *self.rainbows.get(index)?;
+        let rainbow_dir = match message.arg(1) {
+            Some(arg) => rainbow_dir_named(arg.as_str()?)?,
+            None => RainbowDir::Forward,
+        };
+        Some((rainbow, rainbow_dir))
+    }
+}
+
+impl<P: Pixel> Default for OscRouter<'_, P> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Returns true if one part of an address pattern matches an animation index.
+fn index_matches(pattern: &str, index: usize) -> bool {
+    let mut digits = [0; 20];
+    let mut start = digits.len();
+    let mut remaining = index;
+    loop {
+        start -= 1;
+        digits[start] = b'0' + (remaining % 10) as u8;
+        remaining /= 10;
+        if remaining == 0 {
+            break;
+        }
+    }
+    // The digits are all ASCII, so this can't fail:
+    core::str::from_utf8(&digits[start..]).is_ok_and(|index| pattern_matches(pattern, index))
+}
+
+/// Reads a whole number, rounding floats.
+fn number(arg: &OscArg) -> Option<i64> {
+    match arg.as_i64() {
+        Some(value) => Some(value),
+        None => arg.as_f64().map(|value| (value + 0.5) as i64),
+    }
+}
+
+fn count(arg: &OscArg) -> Option<usize> {
+    number(arg).and_then(|value| usize::try_from(value).ok())
+}
+
+/// Reads a float from 0.0 to 1.0, or an integer from 0 to 255.
+fn level(arg: &OscArg) -> Option<u8> {
+    match arg.as_i64() {
+        Some(value) => Some(value.clamp(0, u8::MAX as i64) as u8),
+        None => arg
+            .as_f64()
+            .map(|value| (value.clamp(0.0, 1.0) * u8::MAX as f64 + 0.5) as u8),
+    }
+}
+
+/// Reads a float from 0.0 to 1.0, or an integer from 0 to `MAX_OFFSET`.
+fn offset(arg: &OscArg) -> Option<u16> {
+    match arg.as_i64() {
+        Some(value) => Some(value.clamp(0, MAX_OFFSET as i64) as u16),
+        None => arg
+            .as_f64()
+            .map(|value| (value.clamp(0.0, 1.0) * MAX_OFFSET as f64 + 0.5) as u16),
+    }
+}
+
+/// Reads a float in seconds, or an integer in milliseconds.
+fn duration_ns(arg: &OscArg) -> Option<u64> {
+    match arg.as_i64() {
+        Some(ms) => u64::try_from(ms).ok()?.checked_mul(1_000_000),
+        None => {
+            let seconds = arg.as_f64()?;
+            (seconds >= 0.0).then_some((seconds * 1_000_000_000.0) as u64)
+        }
+    }
+}
+
+fn direction(arg: &OscArg) -> Option<Direction> {
+    match arg.as_str() {
+        Some(name) => direction_named(name),
+        None => Some(match number(arg)?.signum() {
+            1 => Direction::Positive,
+            0 => Direction::Stopped,
+            _ => Direction::Negative,
+        }),
+    }
+}
+
+fn blend_mode(arg: &OscArg) -> Option<BlendMode> {
+    let name = arg.as_str()?;
+    match argument_of(name, "alpha") {
+        Some(opacity) => u8::try_from(parse_number(opacity)?)
+            .ok()
+            .map(BlendMode::Alpha),
+        None => blend_mode_named(name),
+    }
+}
+
+fn custom_index(index: &str) -> Option<u8> {
+    u8::try_from(parse_number(index)?).ok()
+}
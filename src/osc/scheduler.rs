@@ -0,0 +1,75 @@
+//! An OscScheduler holds messages from bundles tagged for the future until they're due. Packets
+//! usually arrive in a buffer that's reused for the next packet, so each message is copied into
+//! the scheduler, which has room for `N_MESSAGES` messages of up to `MESSAGE_LEN` bytes each.
+
+use crate::osc::{OscError, OscMessage, TimeTag};
+use arrayvec::ArrayVec;
+
+pub struct OscScheduler<const N_MESSAGES: usize, const MESSAGE_LEN: usize = 128> {
+    pending: ArrayVec<(TimeTag, ArrayVec<u8, MESSAGE_LEN>), N_MESSAGES>,
+}
+
+impl<const N_MESSAGES: usize, const MESSAGE_LEN: usize> OscScheduler<N_MESSAGES, MESSAGE_LEN> {
+    pub fn new() -> Self {
+        OscScheduler {
+            pending: ArrayVec::new(),
+        }
+    }
+
+    /// Holds a copy of `message` until `time`.
+    pub fn schedule(&mut self, time: TimeTag, message: &OscMessage) -> Result<(), OscError> {
+        if self.pending.is_full() {
+            return Err(OscError::SchedulerFull);
+        }
+        let bytes = ArrayVec::try_from(message.as_bytes()).map_err(|_| OscError::MessageTooLong)?;
+        self.pending.push((time, bytes));
+        Ok(())
+    }
+
+    /// Calls `on_message` for every message due at `now`, earliest first, and forgets them.
+    /// Messages with the same time tag are passed on in the order they were scheduled. Returns the
+    /// number of messages passed on.
+    pub fn run_due(&mut self, now: TimeTag, mut on_message: impl FnMut(&OscMessage)) -> usize {
+        let mut count = 0;
+        while let Some(index) = self.next_due(now) {
+            let (_, bytes) = self.pending.remove(index);
+            // The bytes came from a message that was already parsed, so this can't fail:
+            if let Ok(message) = OscMessage::parse(&bytes) {
+                on_message(&message);
+                count += 1;
+            }
+        }
+        count
+    }
+
+    /// Returns the number of messages waiting to be due.
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// Forgets every pending message, such as when the sender's clock jumps.
+    pub fn clear(&mut self) {
+        self.pending.clear();
+    }
+
+    fn next_due(&self, now: TimeTag) -> Option<usize> {
+        self.pending
+            .iter()
+            .enumerate()
+            .filter(|(_, (time, _))| *time <= now)
+            .min_by_key(|(index, (time, _))| (*time, *index))
+            .map(|(index, _)| index)
+    }
+}
+
+impl<const N_MESSAGES: usize, const MESSAGE_LEN: usize> Default
+    for OscScheduler<N_MESSAGES, MESSAGE_LEN>
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
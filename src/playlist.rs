@@ -0,0 +1,195 @@
+//! A Playlist cycles one LightingController slot through a list of presets, holding each one for a
+//! set amount of time before moving on to the next, either in order or shuffled.
+//!
+//! Entries are applied to the animation already in the slot with `Animatable::update_parameters()`,
+//! which switches over on the next frame. To transition between entries instead, give the playlist
+//! a spare animation with `set_spare_animation()`. The next entry is loaded into the spare, which
+//! is then transitioned into the slot, and the animation it replaces becomes the new spare once the
+//! LightingController retires it. If the spare isn't back yet because the previous transition is
+//! still running, the next entry is cut to instead.
+
+use crate::animations::{Animatable, AnimationParameters};
+use crate::colors::Pixel;
+use crate::transition::TransitionStyle;
+use crate::utility::{convert_ns_to_frames, get_random_offset};
+use crate::LightingController;
+use embedded_time::rate::Hertz;
+use rgb::RGB8;
+
+/// A single preset in a playlist, and how long and how it should be shown.
+#[derive(Copy, Clone)]
+pub struct PlaylistEntry<'a, P = RGB8> {
+    pub parameters: AnimationParameters<'a, P>,
+    /// Replaces the slot's translation array while this entry is showing, if set.
+    pub translation_array: Option<&'a [usize]>,
+    /// How long to show this entry for. An entry with a duration of 0 is shown until `skip()` or
+    /// `go_to()` is called.
+    pub duration_ns: u64,
+    /// How this entry replaces the one before it. This needs a spare animation to be set on the
+    /// playlist, otherwise every entry is cut to.
+    pub transition: TransitionStyle,
+    pub transition_ns: u64,
+}
+
+impl<'a, P> PlaylistEntry<'a, P> {
+    pub fn new(parameters: AnimationParameters<'a, P>, duration_ns: u64) -> Self {
+        PlaylistEntry {
+            parameters,
+            translation_array: None,
+            duration_ns,
+            transition: TransitionStyle::Cut,
+            transition_ns: 0,
+        }
+    }
+
+    pub fn set_translation_array(mut self, translation_array: &'a [usize]) -> Self {
+        self.translation_array = Some(translation_array);
+        self
+    }
+
+    pub fn set_transition(mut self, style: TransitionStyle, duration_ns: u64) -> Self {
+        self.transition = style;
+        self.transition_ns = duration_ns;
+        self
+    }
+}
+
+pub struct Playlist<'a, const N_ENTRIES: usize, P = RGB8> {
+    entries: [PlaylistEntry<'a, P>; N_ENTRIES],
+    slot: usize,
+    spare: Option<&'a mut dyn Animatable<'a, P>>,
+    current: Option<usize>,
+    frames_remaining: usize,
+    shuffle: bool,
+}
+
+impl<'a, const N_ENTRIES: usize, P: Pixel> Playlist<'a, N_ENTRIES, P> {
+    /// Creates a playlist that controls the animation at `slot` of a LightingController. Nothing is
+    /// applied until the first call to `update()`.
+    pub fn new(entries: [PlaylistEntry<'a, P>; N_ENTRIES], slot: usize) -> Self {
+        Playlist {
+            entries,
+            slot,
+            spare: None,
+            current: None,
+            frames_remaining: 0,
+            shuffle: false,
+        }
+    }
+
+    /// Picks the next entry at random, using the crate's random number generator, instead of
+    /// playing the entries in order. The same entry is never picked twice in a row.
+    pub fn set_shuffle(mut self, shuffle: bool) -> Self {
+        self.update_shuffle(shuffle);
+        self
+    }
+
+    /// Gives the playlist a second animation to load entries into, so they can be transitioned to.
+    pub fn set_spare_animation(mut self, spare: &'a mut dyn Animatable<'a, P>) -> Self {
+        self.spare = Some(spare);
+        self
+    }
+
+    pub fn update_shuffle(&mut self, shuffle: bool) {
+        self.shuffle = shuffle;
+    }
+
+    /// Returns the index of the entry that is showing, or `None` before the first update.
+    pub fn current_index(&self) -> Option<usize> {
+        self.current
+    }
+
+    pub fn entries(&self) -> &[PlaylistEntry<'a, P>] {
+        &self.entries
+    }
+
+    /// This should be called once for every frame the LightingController renders. It applies the
+    /// next entry when the current one has been shown long enough, and returns true when it does.
+    pub fn update<const N_ANI: usize>(
+        &mut self,
+        controller: &mut LightingController<'a, N_ANI, P>,
+    ) -> bool {
+        if self.spare.is_none() {
+            self.spare = controller.take_retired_animation(self.slot);
+        }
+
+        if self.current.is_some() {
+            match self.frames_remaining {
+                0 => return false,
+                1 => {}
+                _ => {
+                    self.frames_remaining -= 1;
+                    return false;
+                }
+            }
+        }
+
+        self.skip(controller);
+        true
+    }
+
+    /// Moves on to the next entry immediately.
+    pub fn skip<const N_ANI: usize>(&mut self, controller: &mut LightingController<'a, N_ANI, P>) {
+        let next = self.next_index();
+        self.go_to(next, controller);
+    }
+
+    /// Shows the entry at `index` immediately.
+    pub fn go_to<const N_ANI: usize>(
+        &mut self,
+        index: usize,
+        controller: &mut LightingController<'a, N_ANI, P>,
+    ) {
+        let Some(entry) = self.entries.get(index) else {
+            return;
+        };
+        let frame_rate = controller.frame_rate;
+
+        match (entry.transition, self.spare.take()) {
+            (TransitionStyle::Cut, spare) | (_, spare @ None) => {
+                self.spare = spare;
+                apply_entry(&mut *controller.animations[self.slot], entry, frame_rate);
+            }
+            (style, Some(spare)) => {
+                // The spare takes the place of the animation it replaces on the strip:
+                let current = &controller.animations[self.slot];
+                spare.update_layer(current.layer());
+                spare.update_translation_array(current.translation_array());
+                apply_entry(spare, entry, frame_rate);
+                controller.transition_animation(self.slot, spare, style, entry.transition_ns);
+            }
+        }
+
+        self.current = Some(index);
+        self.frames_remaining = match entry.duration_ns {
+            0 => 0,
+            duration_ns => convert_ns_to_frames(duration_ns, frame_rate).max(1),
+        };
+    }
+
+    fn next_index(&self) -> usize {
+        let Some(current) = self.current else {
+            return match self.shuffle {
+                true => get_random_offset() as usize % N_ENTRIES.max(1),
+                false => 0,
+            };
+        };
+        match (self.shuffle, N_ENTRIES) {
+            (true, 2..) => {
+                (current + 1 + get_random_offset() as usize % (N_ENTRIES - 1)) % N_ENTRIES
+            }
+            _ => (current + 1) % N_ENTRIES.max(1),
+        }
+    }
+}
+
+fn apply_entry<'a, P: Pixel>(
+    animation: &mut dyn Animatable<'a, P>,
+    entry: &PlaylistEntry<'a, P>,
+    frame_rate: Hertz,
+) {
+    animation.update_parameters(&entry.parameters, frame_rate);
+    if let Some(translation_array) = entry.translation_array {
+        animation.update_translation_array(translation_array);
+    }
+}
@@ -0,0 +1,130 @@
+//! This has a simple current model for common addressable leds, which the LightingController can use
+//! to estimate how much current a frame will draw and to dim the strip when it would pull more than
+//! the power supply can deliver.
+
+use crate::colors::{Channel, Pixel};
+
+/// This describes how much current a single led draws. The per-channel values are the current drawn
+/// by that channel at full brightness, and the idle value is drawn by the led's driver even when
+/// all channels are off. The white and amber values are only used by pixel types with those
+/// channels. All values are in milliamps.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct PowerModel {
+    pub red_ma: u16,
+    pub green_ma: u16,
+    pub blue_ma: u16,
+    pub white_ma: u16,
+    pub amber_ma: u16,
+    pub idle_ma: u16,
+}
+
+/// 5V WS2812B style leds.
+pub const WS2812B: PowerModel = PowerModel {
+    red_ma: 16,
+    green_ma: 11,
+    blue_ma: 15,
+    white_ma: 0,
+    amber_ma: 0,
+    idle_ma: 1,
+};
+
+/// 5V SK6812 style leds.
+pub const SK6812: PowerModel = PowerModel {
+    red_ma: 13,
+    green_ma: 13,
+    blue_ma: 13,
+    white_ma: 0,
+    amber_ma: 0,
+    idle_ma: 1,
+};
+
+/// 5V SK6812 RGBW style leds.
+pub const SK6812_RGBW: PowerModel = PowerModel {
+    red_ma: 13,
+    green_ma: 13,
+    blue_ma: 13,
+    white_ma: 20,
+    amber_ma: 0,
+    idle_ma: 1,
+};
+
+/// 5V APA102 (DotStar) style leds.
+pub const APA102: PowerModel = PowerModel {
+    red_ma: 20,
+    green_ma: 20,
+    blue_ma: 20,
+    white_ma: 0,
+    amber_ma: 0,
+    idle_ma: 1,
+};
+
+/// 12V WS2811 strips, where each pixel is a driver chip running a group of three leds.
+pub const WS2811_12V: PowerModel = PowerModel {
+    red_ma: 20,
+    green_ma: 20,
+    blue_ma: 20,
+    white_ma: 0,
+    amber_ma: 0,
+    idle_ma: 2,
+};
+
+impl PowerModel {
+    /// Returns the current drawn by all leds while they are off.
+    pub fn idle_draw_ma(&self, led_count: usize) -> u32 {
+        self.idle_ma as u32 * led_count as u32
+    }
+
+    /// Returns the current drawn by the color channels only, not counting the idle current.
+    pub fn color_draw_ma<P: Pixel>(&self, colors: &[P]) -> u32 {
+        let channel_sum = colors.iter().fold(0_u32, |sum, c| {
+            c.fold_channels(sum, |sum, channel, value| {
+                sum + value as u32 * self.channel_ma(channel) as u32
+            })
+        });
+        channel_sum / u8::MAX as u32
+    }
+
+    fn channel_ma(&self, channel: Channel) -> u16 {
+        match channel {
+            Channel::Red => self.red_ma,
+            Channel::Green => self.green_ma,
+            Channel::Blue => self.blue_ma,
+            Channel::White => self.white_ma,
+            Channel::Amber => self.amber_ma,
+        }
+    }
+
+    /// Returns the total estimated current for the colors.
+    pub fn estimate_draw_ma<P: Pixel>(&self, colors: &[P]) -> u32 {
+        self.idle_draw_ma(colors.len()) + self.color_draw_ma(colors)
+    }
+}
+
+/// This caps the current estimated by `model` to `budget_ma` milliamps by dimming the whole strip.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct PowerLimiter {
+    pub model: PowerModel,
+    pub budget_ma: u32,
+}
+
+impl PowerLimiter {
+    pub fn new(model: PowerModel, budget_ma: u32) -> Self {
+        PowerLimiter { model, budget_ma }
+    }
+
+    /// Returns the brightness the colors need to be scaled by to fit in the budget, where 255
+    /// means they already fit. The idle current can't be dimmed, so if it alone is over budget the
+    /// leds are turned off completely.
+    pub fn limiting_brightness<P: Pixel>(&self, colors: &[P]) -> u8 {
+        let color_draw = self.model.color_draw_ma(colors);
+        let available = self
+            .budget_ma
+            .saturating_sub(self.model.idle_draw_ma(colors.len()));
+        if color_draw <= available {
+            return u8::MAX;
+        }
+        // `colors::scale_channel` multiplies by (brightness + 1) / 256, so solve for that to stay in budget:
+        let scale = available as u64 * 256 / color_draw as u64;
+        scale.saturating_sub(1) as u8
+    }
+}
@@ -0,0 +1,909 @@
+//! This is a compact, versioned binary encoding for animation parameters, so that presets can be
+//! stored in flash or sent to a device over a serial link. Encoding and decoding never allocate:
+//! encoded presets are written into a caller-provided buffer, and decoded presets own their rainbow
+//! colors in a fixed capacity `Preset` that can lend out `AnimationParameters` that borrow from it.
+//!
+//! Every encoded record is laid out as:
+//!
+//! | bytes | contents                                                   |
+//! |-------|------------------------------------------------------------|
+//! | 2     | the magic bytes `LC`                                       |
+//! | 1     | the format version, currently `FORMAT_VERSION`             |
+//! | 1     | the record kind, 1 for animations and 2 for triggers       |
+//! | 1     | the number of color channels in each rainbow color         |
+//! | 2     | the payload length, little endian                          |
+//! | n     | the payload                                                |
+//! | 2     | a CRC-16/CCITT of everything before it, little endian      |
+//!
+//! All multi-byte values in the payload are little endian. `Custom` modes can't be encoded as a
+//! function pointer, so they are encoded as an index into the tables of a `CustomModes` registry,
+//! which must hold the same functions in the same order on the device that decodes the preset.
+
+use crate::animations::{
+    background, foreground, trigger, AnimationParameters, Direction, RainbowDir,
+};
+use crate::colors::{BlendMode, Pixel};
+use arrayvec::ArrayVec;
+use rgb::RGB8;
+
+/// This is the version of the binary format written by `encode_animation` and `encode_trigger`.
+pub const FORMAT_VERSION: u8 = 1;
+
+const MAGIC: [u8; 2] = *b"LC";
+const HEADER_LEN: usize = 7;
+const CHECKSUM_LEN: usize = 2;
+const KIND_ANIMATION: u8 = 1;
+const KIND_TRIGGER: u8 = 2;
+const CUSTOM_MODE: u8 = 0xFF;
+
+/// These are the ways encoding or decoding a preset can fail.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PresetError {
+    /// The buffer being encoded into is too small, or the data being decoded ended early.
+    BufferTooSmall,
+    /// The payload is longer than the 65535 bytes its length can describe.
+    PayloadTooLarge,
+    /// The data does not start with the preset magic bytes.
+    BadMagic,
+    /// The data was encoded with a format version this library can't read.
+    UnsupportedVersion(u8),
+    /// The data holds a different kind of record than the one being decoded.
+    WrongRecordKind,
+    /// The rainbow colors were encoded with a different number of channels than the pixel type.
+    WrongPixelType,
+    /// The checksum does not match the data, so it was corrupted in storage or transit.
+    ChecksumMismatch,
+    /// A mode, direction or blend mode has a code this version doesn't know about.
+    InvalidValue,
+    /// A rainbow has more colors than the `Preset` has room for, or more than 255 colors.
+    TooManyColors,
+    /// A custom mode function is not in the `CustomModes` registry, or an encoded index is past
+    /// the end of it.
+    UnknownCustomMode,
+}
+
+/// This is a registry of user-provided custom mode functions. Custom modes are encoded as their
+/// index in these tables.
+pub struct CustomModes<'r, P = RGB8> {
+    pub background: &'r [background::BgUpdater<P>],
+    pub foreground: &'r [foreground::FgUpdater<P>],
+    pub trigger: &'r [trigger::TriggerBehavior<P>],
+}
+
+impl<'r, P> CustomModes<'r, P> {
+    /// A registry without any custom modes.
+    pub const fn none() -> Self {
+        CustomModes {
+            background: &[],
+            foreground: &[],
+            trigger: &[],
+        }
+    }
+}
+
+/// This holds a decoded set of animation parameters along with storage for up to `MAX_COLORS` colors
+/// in each of its rainbows.
+pub struct Preset<const MAX_COLORS: usize, P: 'static = RGB8> {
+    pub(crate) bg: background::Parameters<'static, P>,
+    pub(crate) fg: foreground::Parameters<'static, P>,
+    pub(crate) trigger: trigger::GlobalParameters<'static, P>,
+    pub(crate) bg_rainbow: ArrayVec<P, MAX_COLORS>,
+    pub(crate) fg_rainbow: ArrayVec<P, MAX_COLORS>,
+    pub(crate) trigger_rainbow: ArrayVec<P, MAX_COLORS>,
+}
+
+impl<const MAX_COLORS: usize, P: Pixel> Preset<MAX_COLORS, P> {
+    /// Copies a set of animation parameters, including their rainbow colors, into a preset.
+    pub fn from_parameters(params: &AnimationParameters<P>) -> Result<Self, PresetError> {
+        let copy_rainbow = |rainbow: &[P]| -> Result<ArrayVec<P, MAX_COLORS>, PresetError> {
+            let mut colors = ArrayVec::new();
+            colors
+                .try_extend_from_slice(rainbow)
+                .map_err(|_| PresetError::TooManyColors)?;
+            Ok(colors)
+        };
+
+        Ok(Preset {
+            bg: background::Parameters {
+                rainbow: &[],
+                ..params.bg
+            },
+            fg: foreground::Parameters {
+                rainbow: &[],
+                ..params.fg
+            },
+            trigger: trigger::GlobalParameters {
+                rainbow: &[],
+                ..params.trigger
+            },
+            bg_rainbow: copy_rainbow(params.bg.rainbow)?,
+            fg_rainbow: copy_rainbow(params.fg.rainbow)?,
+            trigger_rainbow: copy_rainbow(params.trigger.rainbow)?,
+        })
+    }
+
+    /// Returns the animation parameters held by this preset, borrowing its rainbow colors.
+    pub fn parameters(&self) -> AnimationParameters<'_, P> {
+        AnimationParameters {
+            bg: background::Parameters {
+                rainbow: &self.bg_rainbow,
+                ..self.bg
+            },
+            fg: foreground::Parameters {
+                rainbow: &self.fg_rainbow,
+                ..self.fg
+            },
+            trigger: trigger::GlobalParameters {
+                rainbow: &self.trigger_rainbow,
+                ..self.trigger
+            },
+        }
+    }
+
+    /// Encodes this preset into `buffer`, returning the number of bytes written.
+    pub fn encode(&self, custom: &CustomModes<P>, buffer: &mut [u8]) -> Result<usize, PresetError> {
+        encode_animation(&self.parameters(), custom, buffer)
+    }
+
+    /// Decodes a preset that was encoded with `encode_animation` or `Preset::encode`.
+    pub fn decode(data: &[u8], custom: &CustomModes<P>) -> Result<Self, PresetError> {
+        let mut reader = Reader::open_record::<P>(data, KIND_ANIMATION)?;
+
+        let (bg_mode, direction, rainbow_dir) = (
+            reader.bg_mode(custom)?,
+            reader.direction()?,
+            reader.rainbow_dir()?,
+        );
+        let (duration_ns, subdivisions, blend_mode) =
+            (reader.u64()?, reader.u16()? as usize, reader.blend_mode()?);
+        let bg_rainbow = reader.rainbow()?;
+        let bg = background::Parameters {
+            mode: bg_mode,
+            rainbow: &[],
+            direction,
+            rainbow_dir,
+            duration_ns,
+            subdivisions,
+            blend_mode,
+        };
+
+        let (fg_mode, direction, rainbow_dir) = (
+            reader.fg_mode(custom)?,
+            reader.direction()?,
+            reader.rainbow_dir()?,
+        );
+        let (duration_ns, step_time_ns) = (reader.u64()?, reader.u64()?);
+        let (subdivisions, pixels_per_pixel_group, blend_mode) = (
+            reader.u16()? as usize,
+            reader.u16()? as usize,
+            reader.blend_mode()?,
+        );
+        let fg_rainbow = reader.rainbow()?;
+        let fg = foreground::Parameters {
+            mode: fg_mode,
+            rainbow: &[],
+            direction,
+            rainbow_dir,
+            duration_ns,
+            step_time_ns,
+            subdivisions,
+            pixels_per_pixel_group,
+            blend_mode,
+        };
+
+        let (rainbow_dir, duration_ns) = (reader.rainbow_dir()?, reader.u64()?);
+        let trigger_rainbow = reader.rainbow()?;
+        let trigger = trigger::GlobalParameters {
+            rainbow: &[],
+            rainbow_dir,
+            duration_ns,
+        };
+
+        reader.finish()?;
+        Ok(Preset {
+            bg,
+            fg,
+            trigger,
+            bg_rainbow,
+            fg_rainbow,
+            trigger_rainbow,
+        })
+    }
+}
+
+/// Encodes a full set of animation parameters into `buffer`, returning the number of bytes written.
+pub fn encode_animation<P: Pixel>(
+    params: &AnimationParameters<P>,
+    custom: &CustomModes<P>,
+    buffer: &mut [u8],
+) -> Result<usize, PresetError> {
+    let mut writer = Writer::open_record::<P>(buffer, KIND_ANIMATION)?;
+
+    let bg = &params.bg;
+    writer.bg_mode(&bg.mode, custom)?;
+    writer.direction(bg.direction)?;
+    writer.rainbow_dir(bg.rainbow_dir)?;
+    writer.u64(bg.duration_ns)?;
+    writer.usize_as_u16(bg.subdivisions)?;
+    writer.blend_mode(bg.blend_mode)?;
+    writer.rainbow(bg.rainbow)?;
+
+    let fg = &params.fg;
+    writer.fg_mode(&fg.mode, custom)?;
+    writer.direction(fg.direction)?;
+    writer.rainbow_dir(fg.rainbow_dir)?;
+    writer.u64(fg.duration_ns)?;
+    writer.u64(fg.step_time_ns)?;
+    writer.usize_as_u16(fg.subdivisions)?;
+    writer.usize_as_u16(fg.pixels_per_pixel_group)?;
+    writer.blend_mode(fg.blend_mode)?;
+    writer.rainbow(fg.rainbow)?;
+
+    let trigger = &params.trigger;
+    writer.rainbow_dir(trigger.rainbow_dir)?;
+    writer.u64(trigger.duration_ns)?;
+    writer.rainbow(trigger.rainbow)?;
+
+    writer.finish()
+}
+
+/// Encodes the parameters for a single trigger into `buffer`, returning the number of bytes written.
+pub fn encode_trigger<P: Pixel>(
+    params: &trigger::Parameters<P>,
+    custom: &CustomModes<P>,
+    buffer: &mut [u8],
+) -> Result<usize, PresetError> {
+    let mut writer = Writer::open_record::<P>(buffer, KIND_TRIGGER)?;
+    writer.trigger_mode(&params.mode, custom)?;
+    writer.direction(params.direction)?;
+    writer.u64(params.fade_in_time_ns)?;
+    writer.u64(params.fade_out_time_ns)?;
+    writer.u16(params.starting_offset)?;
+    writer.usize_as_u16(params.pixels_per_pixel_group)?;
+    writer.blend_mode(params.blend_mode)?;
+    writer.bytes(&[params.brightness])?;
+    writer.finish()
+}
+
+/// Decodes the parameters for a single trigger that were encoded with `encode_trigger`.
+pub fn decode_trigger<P: Pixel>(
+    data: &[u8],
+    custom: &CustomModes<P>,
+) -> Result<trigger::Parameters<P>, PresetError> {
+    let mut reader = Reader::open_record::<P>(data, KIND_TRIGGER)?;
+    let params = trigger::Parameters {
+        mode: reader.trigger_mode(custom)?,
+        direction: reader.direction()?,
+        fade_in_time_ns: reader.u64()?,
+        fade_out_time_ns: reader.u64()?,
+        starting_offset: reader.u16()?,
+        pixels_per_pixel_group: reader.u16()? as usize,
+        blend_mode: reader.blend_mode()?,
+        brightness: reader.u8()?,
+    };
+    reader.finish()?;
+    Ok(params)
+}
+
+/// Returns the number of color channels in a pixel type.
+fn channel_count<P: Pixel>() -> u8 {
+    P::default().fold_channels(0, |count, _, _| count + 1)
+}
+
+/// CRC-16/CCITT-FALSE, which is small enough to compute bit by bit on a microcontroller.
+fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0xFFFF_u16, |crc, &byte| {
+        let mut crc = crc ^ ((byte as u16) << 8);
+        for _ in 0..8 {
+            crc = match crc & 0x8000 {
+                0 => crc << 1,
+                _ => (crc << 1) ^ 0x1021,
+            };
+        }
+        crc
+    })
+}
+
+/// Finds the index of a custom function in a registry table.
+fn custom_index<F: Copy>(table: &[F], is_match: impl Fn(F) -> bool) -> Result<u8, PresetError> {
+    match table.iter().position(|&f| is_match(f)) {
+        Some(index) if index < CUSTOM_MODE as usize => Ok(index as u8),
+        _ => Err(PresetError::UnknownCustomMode),
+    }
+}
+
+/// Compares two optional function pointers.
+fn same_fn<F: Copy>(a: Option<F>, b: Option<F>, eq: impl Fn(F, F) -> bool) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => eq(a, b),
+        (None, None) => true,
+        _ => false,
+    }
+}
+
+pub(crate) fn custom_entry<F: Copy>(table: &[F], index: u8) -> Result<F, PresetError> {
+    table
+        .get(index as usize)
+        .copied()
+        .ok_or(PresetError::UnknownCustomMode)
+}
+
+struct Writer<'b> {
+    buffer: &'b mut [u8],
+    position: usize,
+}
+
+impl<'b> Writer<'b> {
+    fn open_record<P: Pixel>(buffer: &'b mut [u8], kind: u8) -> Result<Self, PresetError> {
+        let mut writer = Writer {
+            buffer,
+            position: 0,
+        };
+        writer.bytes(&MAGIC)?;
+        writer.bytes(&[FORMAT_VERSION, kind, channel_count::<P>()])?;
+        // The payload length is filled in by finish():
+        writer.u16(0)?;
+        Ok(writer)
+    }
+
+    fn finish(mut self) -> Result<usize, PresetError> {
+        let payload_len = self.position - HEADER_LEN;
+        let payload_len = u16::try_from(payload_len).map_err(|_| PresetError::PayloadTooLarge)?;
+        self.buffer[HEADER_LEN - 2..HEADER_LEN].copy_from_slice(&payload_len.to_le_bytes());
+
+        let checksum = crc16(&self.buffer[..self.position]);
+        self.u16(checksum)?;
+        Ok(self.position)
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) -> Result<(), PresetError> {
+        let end = self.position + bytes.len();
+        let dest = self
+            .buffer
+            .get_mut(self.position..end)
+            .ok_or(PresetError::BufferTooSmall)?;
+        dest.copy_from_slice(bytes);
+        self.position = end;
+        Ok(())
+    }
+
+    fn u16(&mut self, value: u16) -> Result<(), PresetError> {
+        self.bytes(&value.to_le_bytes())
+    }
+
+    fn u64(&mut self, value: u64) -> Result<(), PresetError> {
+        self.bytes(&value.to_le_bytes())
+    }
+
+    fn usize_as_u16(&mut self, value: usize) -> Result<(), PresetError> {
+        let value = u16::try_from(value).map_err(|_| PresetError::InvalidValue)?;
+        self.u16(value)
+    }
+
+    fn mode(&mut self, code: u8, custom_index: u8) -> Result<(), PresetError> {
+        self.bytes(&[code, custom_index])
+    }
+
+    fn bg_mode<P>(
+        &mut self,
+        mode: &background::Mode<P>,
+        custom: &CustomModes<P>,
+    ) -> Result<(), PresetError> {
+        let code = match mode {
+            background::Mode::NoBackground => 0,
+            background::Mode::Transparent => 1,
+            background::Mode::Solid => 2,
+            background::Mode::SolidFade => 3,
+            background::Mode::FillRainbow => 4,
+            background::Mode::FillRainbowRotate => 5,
+            background::Mode::Plasma => 6,
+            background::Mode::RadialRainbow => 7,
+            background::Mode::LinearGradient => 8,
+            background::Mode::AxisGradient => 9,
+            background::Mode::Custom(updater) => {
+                let index =
+                    custom_index(custom.background, |f| core::ptr::fn_addr_eq(f, *updater))?;
+                return self.mode(CUSTOM_MODE, index);
+            }
+        };
+        self.mode(code, 0)
+    }
+
+    fn fg_mode<P>(
+        &mut self,
+        mode: &foreground::Mode<P>,
+        custom: &CustomModes<P>,
+    ) -> Result<(), PresetError> {
+        let code = match mode {
+            foreground::Mode::NoForeground => 0,
+            foreground::Mode::MarqueeSolid => 1,
+            foreground::Mode::MarqueeSolidFixed => 2,
+            foreground::Mode::MarqueeFade => 3,
+            foreground::Mode::MarqueeFadeFixed => 4,
+            foreground::Mode::VUMeter => 5,
+            foreground::Mode::ScrollingBars => 6,
+            foreground::Mode::PlaneSweep => 7,
+            foreground::Mode::Custom(updater) => {
+                let index =
+                    custom_index(custom.foreground, |f| core::ptr::fn_addr_eq(f, *updater))?;
+                return self.mode(CUSTOM_MODE, index);
+            }
+        };
+        self.mode(code, 0)
+    }
+
+    fn trigger_mode<P>(
+        &mut self,
+        mode: &trigger::Mode<P>,
+        custom: &CustomModes<P>,
+    ) -> Result<(), PresetError> {
+        let code = match mode {
+            trigger::Mode::NoTrigger => 0,
+            trigger::Mode::Background => 1,
+            trigger::Mode::Foreground => 2,
+            trigger::Mode::ColorPulse => 3,
+            trigger::Mode::ColorPulseFade => 4,
+            trigger::Mode::ColorPulseRainbow => 5,
+            trigger::Mode::ColorShot => 6,
+            trigger::Mode::ColorShotFade => 7,
+            trigger::Mode::ColorShotRainbow => 8,
+            trigger::Mode::Flash => 9,
+            trigger::Mode::FlashFade => 10,
+            trigger::Mode::FlashRainbow => 11,
+            trigger::Mode::ExpandingRing => 12,
+            trigger::Mode::ExpandingRingFade => 13,
+            trigger::Mode::ExpandingRingRainbow => 14,
+            trigger::Mode::ExpandingSphere => 15,
+            trigger::Mode::ExpandingSphereFade => 16,
+            trigger::Mode::ExpandingSphereRainbow => 17,
+            trigger::Mode::Custom((init, update)) => {
+                let index = custom_index(custom.trigger, |(i, u)| {
+                    same_fn(i, *init, core::ptr::fn_addr_eq)
+                        && same_fn(u, *update, core::ptr::fn_addr_eq)
+                })?;
+                return self.mode(CUSTOM_MODE, index);
+            }
+        };
+        self.mode(code, 0)
+    }
+
+    fn direction(&mut self, direction: Direction) -> Result<(), PresetError> {
+        let code = match direction {
+            Direction::Positive => 0,
+            Direction::Stopped => 1,
+            Direction::Negative => 2,
+        };
+        self.bytes(&[code])
+    }
+
+    fn rainbow_dir(&mut self, rainbow_dir: RainbowDir) -> Result<(), PresetError> {
+        let code = match rainbow_dir {
+            RainbowDir::Forward => 0,
+            RainbowDir::Backward => 1,
+        };
+        self.bytes(&[code])
+    }
+
+    fn blend_mode(&mut self, blend_mode: BlendMode) -> Result<(), PresetError> {
+        let (code, opacity) = match blend_mode {
+            BlendMode::Replace => (0, 0),
+            BlendMode::Additive => (1, 0),
+            BlendMode::Multiply => (2, 0),
+            BlendMode::Screen => (3, 0),
+            BlendMode::Lighten => (4, 0),
+            BlendMode::Alpha(opacity) => (5, opacity),
+        };
+        self.bytes(&[code, opacity])
+    }
+
+    fn rainbow<P: Pixel>(&mut self, rainbow: &[P]) -> Result<(), PresetError> {
+        let len = u8::try_from(rainbow.len()).map_err(|_| PresetError::TooManyColors)?;
+        self.bytes(&[len])?;
+        for color in rainbow {
+            color.fold_channels(Ok(()), |result, _, value| {
+                result.and_then(|_| self.bytes(&[value]))
+            })?;
+        }
+        Ok(())
+    }
+}
+
+struct Reader<'b> {
+    data: &'b [u8],
+    position: usize,
+}
+
+impl<'b> Reader<'b> {
+    /// Checks the header and checksum of a record, and returns a reader over its payload.
+    fn open_record<P: Pixel>(data: &'b [u8], kind: u8) -> Result<Self, PresetError> {
+        let header = data.get(..HEADER_LEN).ok_or(PresetError::BufferTooSmall)?;
+        if header[..2] != MAGIC {
+            return Err(PresetError::BadMagic);
+        }
+        if header[2] != FORMAT_VERSION {
+            return Err(PresetError::UnsupportedVersion(header[2]));
+        }
+
+        let payload_len = u16::from_le_bytes([header[5], header[6]]) as usize;
+        let record_len = HEADER_LEN + payload_len;
+        let checksum = data
+            .get(record_len..record_len + CHECKSUM_LEN)
+            .ok_or(PresetError::BufferTooSmall)?;
+        if crc16(&data[..record_len]) != u16::from_le_bytes([checksum[0], checksum[1]]) {
+            return Err(PresetError::ChecksumMismatch);
+        }
+
+        if header[3] != kind {
+            return Err(PresetError::WrongRecordKind);
+        }
+        if header[4] != channel_count::<P>() {
+            return Err(PresetError::WrongPixelType);
+        }
+
+        Ok(Reader {
+            data: &data[..record_len],
+            position: HEADER_LEN,
+        })
+    }
+
+    /// Makes sure the whole payload was used, since leftover data means it was encoded differently.
+    fn finish(self) -> Result<(), PresetError> {
+        match self.position == self.data.len() {
+            true => Ok(()),
+            false => Err(PresetError::InvalidValue),
+        }
+    }
+
+    fn bytes<const N: usize>(&mut self) -> Result<[u8; N], PresetError> {
+        let end = self.position + N;
+        let bytes = self
+            .data
+            .get(self.position..end)
+            .ok_or(PresetError::BufferTooSmall)?;
+        self.position = end;
+        let mut result = [0; N];
+        result.copy_from_slice(bytes);
+        Ok(result)
+    }
+
+    fn u8(&mut self) -> Result<u8, PresetError> {
+        Ok(self.bytes::<1>()?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, PresetError> {
+        Ok(u16::from_le_bytes(self.bytes()?))
+    }
+
+    fn u64(&mut self) -> Result<u64, PresetError> {
+        Ok(u64::from_le_bytes(self.bytes()?))
+    }
+
+    fn bg_mode<P: Pixel>(
+        &mut self,
+        custom: &CustomModes<P>,
+    ) -> Result<background::Mode<P>, PresetError> {
+        let [code, index] = self.bytes()?;
+        Ok(match code {
+            0 => background::Mode::NoBackground,
+            1 => background::Mode::Transparent,
+            2 => background::Mode::Solid,
+            3 => background::Mode::SolidFade,
+            4 => background::Mode::FillRainbow,
+            5 => background::Mode::FillRainbowRotate,
+            6 => background::Mode::Plasma,
+            7 => background::Mode::RadialRainbow,
+            8 => background::Mode::LinearGradient,
+            9 => background::Mode::AxisGradient,
+            CUSTOM_MODE => background::Mode::Custom(custom_entry(custom.background, index)?),
+            _ => return Err(PresetError::InvalidValue),
+        })
+    }
+
+    fn fg_mode<P: Pixel>(
+        &mut self,
+        custom: &CustomModes<P>,
+    ) -> Result<foreground::Mode<P>, PresetError> {
+        let [code, index] = self.bytes()?;
+        Ok(match code {
+            0 => foreground::Mode::NoForeground,
+            1 => foreground::Mode::MarqueeSolid,
+            2 => foreground::Mode::MarqueeSolidFixed,
+            3 => foreground::Mode::MarqueeFade,
+            4 => foreground::Mode::MarqueeFadeFixed,
+            5 => foreground::Mode::VUMeter,
+            6 => foreground::Mode::ScrollingBars,
+            7 => foreground::Mode::PlaneSweep,
+            CUSTOM_MODE => foreground::Mode::Custom(custom_entry(custom.foreground, index)?),
+            _ => return Err(PresetError::InvalidValue),
+        })
+    }
+
+    fn trigger_mode<P: Pixel>(
+        &mut self,
+        custom: &CustomModes<P>,
+    ) -> Result<trigger::Mode<P>, PresetError> {
+        let [code, index] = self.bytes()?;
+        Ok(match code {
+            0 => trigger::Mode::NoTrigger,
+            1 => trigger::Mode::Background,
+            2 => trigger::Mode::Foreground,
+            3 => trigger::Mode::ColorPulse,
+            4 => trigger::Mode::ColorPulseFade,
+            5 => trigger::Mode::ColorPulseRainbow,
+            6 => trigger::Mode::ColorShot,
+            7 => trigger::Mode::ColorShotFade,
+            8 => trigger::Mode::ColorShotRainbow,
+            9 => trigger::Mode::Flash,
+            10 => trigger::Mode::FlashFade,
+            11 => trigger::Mode::FlashRainbow,
+            12 => trigger::Mode::ExpandingRing,
+            13 => trigger::Mode::ExpandingRingFade,
+            14 => trigger::Mode::ExpandingRingRainbow,
+            15 => trigger::Mode::ExpandingSphere,
+            16 => trigger::Mode::ExpandingSphereFade,
+            17 => trigger::Mode::ExpandingSphereRainbow,
+            CUSTOM_MODE => trigger::Mode::Custom(custom_entry(custom.trigger, index)?),
+            _ => return Err(PresetError::InvalidValue),
+        })
+    }
+
+    fn direction(&mut self) -> Result<Direction, PresetError> {
+        match self.u8()? {
+            0 => Ok(Direction::Positive),
+            1 => Ok(Direction::Stopped),
+            2 => Ok(Direction::Negative),
+            _ => Err(PresetError::InvalidValue),
+        }
+    }
+
+    fn rainbow_dir(&mut self) -> Result<RainbowDir, PresetError> {
+        match self.u8()? {
+            0 => Ok(RainbowDir::Forward),
+            1 => Ok(RainbowDir::Backward),
+            _ => Err(PresetError::InvalidValue),
+        }
+    }
+
+    fn blend_mode(&mut self) -> Result<BlendMode, PresetError> {
+        let [code, opacity] = self.bytes()?;
+        match code {
+            0 => Ok(BlendMode::Replace),
+            1 => Ok(BlendMode::Additive),
+            2 => Ok(BlendMode::Multiply),
+            3 => Ok(BlendMode::Screen),
+            4 => Ok(BlendMode::Lighten),
+            5 => Ok(BlendMode::Alpha(opacity)),
+            _ => Err(PresetError::InvalidValue),
+        }
+    }
+
+    fn rainbow<P: Pixel, const N: usize>(&mut self) -> Result<ArrayVec<P, N>, PresetError> {
+        let len = self.u8()? as usize;
+        let mut rainbow = ArrayVec::new();
+        for _ in 0..len {
+            let mut result = Ok(());
+            let color = P::default().map_channels(|_, _| match self.u8() {
+                Ok(value) => value,
+                Err(e) => {
+                    result = Err(e);
+                    0
+                }
+            });
+            result?;
+            rainbow
+                .try_push(color)
+                .map_err(|_| PresetError::TooManyColors)?;
+        }
+        Ok(rainbow)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::animations::background::Background;
+    use crate::animations::foreground::Foreground;
+    use crate::animations::trigger::Trigger;
+    use crate::default_animations::{ANI_DEFAULT, BG_DEFAULT, FG_DEFAULT};
+
+    fn custom_bg(_: &mut Background<RGB8>, _: &mut [RGB8]) {}
+    fn custom_fg(_: &mut Foreground<RGB8>, _: &mut [RGB8]) {}
+    fn custom_trigger(_: &mut Trigger<RGB8>, _: &mut [RGB8]) {}
+
+    const CUSTOM: CustomModes<'static> = CustomModes {
+        background: &[custom_bg],
+        foreground: &[custom_fg],
+        trigger: &[(None, Some(custom_trigger))],
+    };
+
+    const TRIGGER: trigger::Parameters = trigger::Parameters {
+        mode: trigger::Mode::ColorShotFade,
+        direction: Direction::Negative,
+        fade_in_time_ns: 250_000_000,
+        fade_out_time_ns: 1_500_000_000,
+        starting_offset: 1234,
+        pixels_per_pixel_group: 3,
+        blend_mode: BlendMode::Alpha(128),
+        brightness: 200,
+    };
+
+    type Record = ([u8; 256], usize);
+
+    /// Encodes `params`, decodes them, and checks that they encode to the same bytes again.
+    fn round_trip_animation(params: &AnimationParameters) -> Record {
+        let mut buffer = [0; 256];
+        let len = encode_animation(params, &CUSTOM, &mut buffer).unwrap();
+        let preset = Preset::<8>::decode(&buffer[..len], &CUSTOM).unwrap();
+        let mut again = [0; 256];
+        assert_eq!(preset.encode(&CUSTOM, &mut again), Ok(len));
+        assert_eq!(buffer[..len], again[..len]);
+        (buffer, len)
+    }
+
+    fn round_trip_trigger(params: &trigger::Parameters) -> Record {
+        let mut buffer = [0; 256];
+        let len = encode_trigger(params, &CUSTOM, &mut buffer).unwrap();
+        let decoded = decode_trigger(&buffer[..len], &CUSTOM).unwrap();
+        let mut again = [0; 256];
+        assert_eq!(encode_trigger(&decoded, &CUSTOM, &mut again), Ok(len));
+        assert_eq!(buffer[..len], again[..len]);
+        (buffer, len)
+    }
+
+    /// Checks that no two modes were encoded the same way.
+    fn assert_distinct(records: &[Record]) {
+        for (i, (a, a_len)) in records.iter().enumerate() {
+            for (b, b_len) in &records[i + 1..] {
+                assert_ne!(
+                    a[..*a_len],
+                    b[..*b_len],
+                    "mode {} is encoded like another",
+                    i
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn round_trips_every_background_mode() {
+        let modes = [
+            background::Mode::NoBackground,
+            background::Mode::Transparent,
+            background::Mode::Solid,
+            background::Mode::SolidFade,
+            background::Mode::FillRainbow,
+            background::Mode::FillRainbowRotate,
+            background::Mode::Plasma,
+            background::Mode::RadialRainbow,
+            background::Mode::LinearGradient,
+            background::Mode::AxisGradient,
+            background::Mode::Custom(custom_bg),
+        ];
+        let records = modes.map(|mode| {
+            round_trip_animation(&AnimationParameters {
+                bg: background::Parameters { mode, ..BG_DEFAULT },
+                ..ANI_DEFAULT
+            })
+        });
+        assert_distinct(&records);
+    }
+
+    #[test]
+    fn round_trips_every_foreground_mode() {
+        let modes = [
+            foreground::Mode::NoForeground,
+            foreground::Mode::MarqueeSolid,
+            foreground::Mode::MarqueeSolidFixed,
+            foreground::Mode::MarqueeFade,
+            foreground::Mode::MarqueeFadeFixed,
+            foreground::Mode::VUMeter,
+            foreground::Mode::ScrollingBars,
+            foreground::Mode::PlaneSweep,
+            foreground::Mode::Custom(custom_fg),
+        ];
+        let records = modes.map(|mode| {
+            round_trip_animation(&AnimationParameters {
+                fg: foreground::Parameters { mode, ..FG_DEFAULT },
+                ..ANI_DEFAULT
+            })
+        });
+        assert_distinct(&records);
+    }
+
+    #[test]
+    fn round_trips_every_trigger_mode() {
+        let modes = [
+            trigger::Mode::NoTrigger,
+            trigger::Mode::Background,
+            trigger::Mode::Foreground,
+            trigger::Mode::ColorPulse,
+            trigger::Mode::ColorPulseFade,
+            trigger::Mode::ColorPulseRainbow,
+            trigger::Mode::ColorShot,
+            trigger::Mode::ColorShotFade,
+            trigger::Mode::ColorShotRainbow,
+            trigger::Mode::Flash,
+            trigger::Mode::FlashFade,
+            trigger::Mode::FlashRainbow,
+            trigger::Mode::ExpandingRing,
+            trigger::Mode::ExpandingRingFade,
+            trigger::Mode::ExpandingRingRainbow,
+            trigger::Mode::ExpandingSphere,
+            trigger::Mode::ExpandingSphereFade,
+            trigger::Mode::ExpandingSphereRainbow,
+            trigger::Mode::Custom((None, Some(custom_trigger))),
+        ];
+        let records =
+            modes.map(|mode| round_trip_trigger(&trigger::Parameters { mode, ..TRIGGER }));
+        assert_distinct(&records);
+    }
+
+    #[test]
+    fn decodes_an_encoded_animation() {
+        let mut buffer = [0; 256];
+        let len = encode_animation(&ANI_DEFAULT, &CUSTOM, &mut buffer).unwrap();
+        let preset = Preset::<8>::decode(&buffer[..len], &CUSTOM).unwrap();
+        let params = preset.parameters();
+
+        assert!(matches!(
+            params.bg.mode,
+            background::Mode::FillRainbowRotate
+        ));
+        assert_eq!(params.bg.rainbow, BG_DEFAULT.rainbow);
+        assert_eq!(params.bg.duration_ns, BG_DEFAULT.duration_ns);
+        assert!(matches!(params.fg.mode, foreground::Mode::NoForeground));
+        assert_eq!(params.fg.step_time_ns, FG_DEFAULT.step_time_ns);
+        assert_eq!(params.fg.subdivisions, FG_DEFAULT.subdivisions);
+        assert_eq!(params.trigger.rainbow, ANI_DEFAULT.trigger.rainbow);
+        assert_eq!(params.trigger.duration_ns, ANI_DEFAULT.trigger.duration_ns);
+    }
+
+    #[test]
+    fn decodes_an_encoded_trigger() {
+        let mut buffer = [0; 64];
+        let len = encode_trigger(&TRIGGER, &CUSTOM, &mut buffer).unwrap();
+        let params = decode_trigger(&buffer[..len], &CUSTOM).unwrap();
+        assert!(matches!(params.mode, trigger::Mode::ColorShotFade));
+        assert!(matches!(params.direction, Direction::Negative));
+        assert_eq!(params.fade_out_time_ns, TRIGGER.fade_out_time_ns);
+        assert_eq!(params.starting_offset, TRIGGER.starting_offset);
+        assert_eq!(params.blend_mode, BlendMode::Alpha(128));
+        assert_eq!(params.brightness, 200);
+    }
+
+    #[test]
+    fn rejects_corrupted_data() {
+        let mut buffer = [0; 64];
+        let len = encode_trigger(&TRIGGER, &CUSTOM, &mut buffer).unwrap();
+        buffer[HEADER_LEN + 4] ^= 0x01;
+        let result = decode_trigger(&buffer[..len], &CUSTOM);
+        assert_eq!(result.err(), Some(PresetError::ChecksumMismatch));
+    }
+
+    #[test]
+    fn rejects_unknown_versions() {
+        let mut buffer = [0; 64];
+        let len = encode_trigger(&TRIGGER, &CUSTOM, &mut buffer).unwrap();
+        buffer[2] = FORMAT_VERSION + 1;
+        let result = decode_trigger(&buffer[..len], &CUSTOM);
+        assert_eq!(
+            result.err(),
+            Some(PresetError::UnsupportedVersion(FORMAT_VERSION + 1))
+        );
+    }
+
+    #[test]
+    fn rejects_truncated_data() {
+        let mut buffer = [0; 256];
+        let len = encode_animation(&ANI_DEFAULT, &CUSTOM, &mut buffer).unwrap();
+        for end in [0, HEADER_LEN - 1, HEADER_LEN, len - 1] {
+            let result = Preset::<8>::decode(&buffer[..end], &CUSTOM);
+            assert_eq!(
+                result.err(),
+                Some(PresetError::BufferTooSmall),
+                "{} bytes",
+                end
+            );
+        }
+    }
+}
@@ -0,0 +1,584 @@
+//! This is a small line-oriented text format for animation parameters, so scenes can be edited by
+//! hand and loaded at runtime instead of being compiled into the firmware. A scene looks like:
+//!
+//! ```text
+//! # A slow rainbow with a white marquee running over it
+//! bg.mode = fill_rainbow_rotate
+//! bg.rainbow = #ff0000 #00ff00 #0000ff
+//! bg.duration_ns = 15s
+//! fg.mode = marquee_solid
+//! fg.rainbow = #ffffff
+//! fg.step_time_ns = 250ms
+//! fg.blend_mode = alpha(128)
+//! ```
+//!
+//! Each line is a single `key = value` pair, and blank lines and lines starting with `#` are
+//! ignored. Any key that isn't in the scene keeps the value from the base parameters passed to the
+//! parser, so a scene only needs to list what it changes.
+//!
+//! - Modes, directions and blend modes are written in snake_case, like `fill_rainbow_rotate`,
+//!   `negative` or `additive`. Alpha blending takes an opacity: `alpha(128)`. Custom modes are
+//!   written as their index in the `CustomModes` registry: `custom(0)`.
+//! - Durations are nanoseconds by default, but can be given with an `ns`, `us`, `ms` or `s` suffix.
+//!   Underscores can be used to group digits: `1_500ms`.
+//! - Colors are written as hex. `#rrggbb` works for every pixel type, and a pixel type with extra
+//!   channels can also have every channel written out in order, like `#rrggbbww` for RGBW.
+//!
+//! Parsing doesn't allocate. Rainbows are copied into the `Preset` returned by the parser.
+
+use crate::animations::{
+    background, foreground, trigger, AnimationParameters, Direction, RainbowDir,
+};
+use crate::colors::{BlendMode, Pixel};
+use crate::preset::{custom_entry, CustomModes, Preset};
+use arrayvec::ArrayVec;
+use rgb::RGB8;
+
+/// This reports where in a scene parsing failed. Lines and columns both start at 1, and columns
+/// count bytes from the start of the line. An error on line 0 means one of the rainbows in the base
+/// parameters didn't fit in the `Preset`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SceneError {
+    pub line: usize,
+    pub column: usize,
+    pub kind: SceneErrorKind,
+}
+
+/// These are the ways a line of a scene can be invalid.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SceneErrorKind {
+    /// The line isn't a comment, but doesn't have an `=` in it.
+    MissingEquals,
+    /// The key before the `=` isn't one of the parameters that can be set.
+    UnknownKey,
+    /// There is nothing after the `=`.
+    MissingValue,
+    /// The value isn't one of the names allowed for this key.
+    InvalidValue,
+    /// The value isn't a number, has an unknown unit, or is too large.
+    InvalidNumber,
+    /// A color isn't a `#` followed by the right number of hex digits.
+    InvalidColor,
+    /// A rainbow has more colors than the `Preset` has room for.
+    TooManyColors,
+    /// A custom mode index is past the end of the `CustomModes` registry.
+    UnknownCustomMode,
+}
+
+/// Parses a scene into a preset, starting from the values in `base`.
+pub fn parse_animation<const MAX_COLORS: usize, P: Pixel>(
+    text: &str,
+    base: &AnimationParameters<P>,
+    custom: &CustomModes<P>,
+) -> Result<Preset<MAX_COLORS, P>, SceneError> {
+    let mut preset = Preset::from_parameters(base).map_err(|_| SceneError {
+        line: 0,
+        column: 0,
+        kind: SceneErrorKind::TooManyColors,
+    })?;
+
+    for statement in statements(text) {
+        let statement = statement?;
+        let value = statement.value;
+        match statement.key {
+            "bg.mode" => preset.bg.mode = statement.bg_mode(custom)?,
+            "bg.rainbow" => preset.bg_rainbow = statement.rainbow()?,
+            "bg.direction" => preset.bg.direction = statement.direction()?,
+            "bg.rainbow_dir" => preset.bg.rainbow_dir = statement.rainbow_dir()?,
+            "bg.duration_ns" => preset.bg.duration_ns = statement.duration_ns()?,
+            "bg.subdivisions" => preset.bg.subdivisions = statement.number_as(value)?,
+            "bg.blend_mode" => preset.bg.blend_mode = statement.blend_mode()?,
+
+            "fg.mode" => preset.fg.mode = statement.fg_mode(custom)?,
+            "fg.rainbow" => preset.fg_rainbow = statement.rainbow()?,
+            "fg.direction" => preset.fg.direction = statement.direction()?,
+            "fg.rainbow_dir" => preset.fg.rainbow_dir = statement.rainbow_dir()?,
+            "fg.duration_ns" => preset.fg.duration_ns = statement.duration_ns()?,
+            "fg.step_time_ns" => preset.fg.step_time_ns = statement.duration_ns()?,
+            "fg.subdivisions" => preset.fg.subdivisions = statement.number_as(value)?,
+            "fg.pixels_per_pixel_group" => {
+                preset.fg.pixels_per_pixel_group = statement.number_as(value)?
+            }
+            "fg.blend_mode" => preset.fg.blend_mode = statement.blend_mode()?,
+
+            "trigger.rainbow" => preset.trigger_rainbow = statement.rainbow()?,
+            "trigger.rainbow_dir" => preset.trigger.rainbow_dir = statement.rainbow_dir()?,
+            "trigger.duration_ns" => preset.trigger.duration_ns = statement.duration_ns()?,
+
+            _ => return Err(statement.error(statement.key, SceneErrorKind::UnknownKey)),
+        }
+    }
+
+    Ok(preset)
+}
+
+/// Parses the parameters for a single trigger, starting from the values in `base`. Trigger keys
+/// don't have a prefix:
+///
+/// ```text
+/// mode = color_shot_fade
+/// direction = negative
+/// fade_out_time_ns = 400ms
+/// ```
+pub fn parse_trigger<P: Pixel>(
+    text: &str,
+    base: &trigger::Parameters<P>,
+    custom: &CustomModes<P>,
+) -> Result<trigger::Parameters<P>, SceneError> {
+    let mut params = *base;
+
+    for statement in statements(text) {
+        let statement = statement?;
+        let value = statement.value;
+        match statement.key {
+            "mode" => params.mode = statement.trigger_mode(custom)?,
+            "direction" => params.direction = statement.direction()?,
+            "fade_in_time_ns" => params.fade_in_time_ns = statement.duration_ns()?,
+            "fade_out_time_ns" => params.fade_out_time_ns = statement.duration_ns()?,
+            "starting_offset" => params.starting_offset = statement.number_as(value)?,
+            "pixels_per_pixel_group" => {
+                params.pixels_per_pixel_group = statement.number_as(value)?
+            }
+            "blend_mode" => params.blend_mode = statement.blend_mode()?,
+            "brightness" => params.brightness = statement.number_as(value)?,
+            _ => return Err(statement.error(statement.key, SceneErrorKind::UnknownKey)),
+        }
+    }
+
+    Ok(params)
+}
+
+/// Splits a scene into its `key = value` statements, skipping blank lines and comments.
+fn statements(text: &str) -> impl Iterator<Item = Result<Statement<'_>, SceneError>> {
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| {
+            let line = line.trim();
+            !line.is_empty() && !line.starts_with('#')
+        })
+        .map(|(index, line)| {
+            let line_number = index + 1;
+            let (key, value) = line.split_once('=').ok_or(SceneError {
+                line: line_number,
+                column: line.len() - line.trim_start().len() + 1,
+                kind: SceneErrorKind::MissingEquals,
+            })?;
+            let statement = Statement {
+                line,
+                line_number,
+                key: key.trim(),
+                value: value.trim(),
+            };
+            match statement.value.is_empty() {
+                true => Err(statement.error(value, SceneErrorKind::MissingValue)),
+                false => Ok(statement),
+            }
+        })
+}
+
+/// A single `key = value` line, which knows where it came from so it can report errors.
+struct Statement<'t> {
+    line: &'t str,
+    line_number: usize,
+    key: &'t str,
+    value: &'t str,
+}
+
+impl<'t> Statement<'t> {
+    /// Builds an error pointing at `token`, which must be a slice of this statement's line.
+    fn error(&self, token: &str, kind: SceneErrorKind) -> SceneError {
+        SceneError {
+            line: self.line_number,
+            column: token.as_ptr() as usize - self.line.as_ptr() as usize + 1,
+            kind,
+        }
+    }
+
+    fn invalid(&self) -> SceneError {
+        self.error(self.value, SceneErrorKind::InvalidValue)
+    }
+
+    fn number(&self, token: &str) -> Result<u64, SceneError> {
+        parse_number(token).ok_or(self.error(token, SceneErrorKind::InvalidNumber))
+    }
+
+    /// Parses a number that has to fit in a smaller type, such as a `u8` or a `usize`.
+    fn number_as<T: TryFrom<u64>>(&self, token: &str) -> Result<T, SceneError> {
+        T::try_from(self.number(token)?)
+            .map_err(|_| self.error(token, SceneErrorKind::InvalidNumber))
+    }
+
+    fn duration_ns(&self) -> Result<u64, SceneError> {
+        let (number, unit) = split_duration(self.value);
+        let scale = duration_scale(unit).ok_or(self.error(unit, SceneErrorKind::InvalidNumber))?;
+        self.number(number)?
+            .checked_mul(scale)
+            .ok_or(self.error(self.value, SceneErrorKind::InvalidNumber))
+    }
+
+    fn argument_of(&self, name: &str) -> Option<&'t str> {
+        argument_of(self.value, name)
+    }
+
+    /// Returns `Ok(Some(index))` if the value is a valid `custom(index)`.
+    fn custom_index(&self) -> Result<Option<u8>, SceneError> {
+        match self.argument_of("custom") {
+            Some(index) => u8::try_from(self.number(index)?)
+                .map(Some)
+                .map_err(|_| self.error(index, SceneErrorKind::UnknownCustomMode)),
+            None => Ok(None),
+        }
+    }
+
+    fn custom_entry<F: Copy>(&self, table: &[F], index: u8) -> Result<F, SceneError> {
+        custom_entry(table, index)
+            .map_err(|_| self.error(self.value, SceneErrorKind::UnknownCustomMode))
+    }
+
+    fn bg_mode<P: Pixel>(
+        &self,
+        custom: &CustomModes<P>,
+    ) -> Result<background::Mode<P>, SceneError> {
+        if let Some(index) = self.custom_index()? {
+            return Ok(background::Mode::Custom(
+                self.custom_entry(custom.background, index)?,
+            ));
+        }
+        bg_mode_named(self.value).ok_or(self.invalid())
+    }
+
+    fn fg_mode<P: Pixel>(
+        &self,
+        custom: &CustomModes<P>,
+    ) -> Result<foreground::Mode<P>, SceneError> {
+        if let Some(index) = self.custom_index()? {
+            return Ok(foreground::Mode::Custom(
+                self.custom_entry(custom.foreground, index)?,
+            ));
+        }
+        fg_mode_named(self.value).ok_or(self.invalid())
+    }
+
+    fn trigger_mode<P: Pixel>(
+        &self,
+        custom: &CustomModes<P>,
+    ) -> Result<trigger::Mode<P>, SceneError> {
+        if let Some(index) = self.custom_index()? {
+            return Ok(trigger::Mode::Custom(
+                self.custom_entry(custom.trigger, index)?,
+            ));
+        }
+        trigger_mode_named(self.value).ok_or(self.invalid())
+    }
+
+    fn direction(&self) -> Result<Direction, SceneError> {
+        direction_named(self.value).ok_or(self.invalid())
+    }
+
+    fn rainbow_dir(&self) -> Result<RainbowDir, SceneError> {
+        rainbow_dir_named(self.value).ok_or(self.invalid())
+    }
+
+    fn blend_mode(&self) -> Result<BlendMode, SceneError> {
+        if let Some(opacity) = self.argument_of("alpha") {
+            return self.number_as(opacity).map(BlendMode::Alpha);
+        }
+        blend_mode_named(self.value).ok_or(self.invalid())
+    }
+
+    fn rainbow<P: Pixel, const N: usize>(&self) -> Result<ArrayVec<P, N>, SceneError> {
+        let mut rainbow = ArrayVec::new();
+        for token in self.value.split_whitespace() {
+            let color = self.color(token)?;
+            rainbow
+                .try_push(color)
+                .map_err(|_| self.error(token, SceneErrorKind::TooManyColors))?;
+        }
+        Ok(rainbow)
+    }
+
+    /// Parses `#rrggbb`, or a hex digit pair for every channel of the pixel type.
+    fn color<P: Pixel>(&self, token: &str) -> Result<P, SceneError> {
+        let error = self.error(token, SceneErrorKind::InvalidColor);
+        let hex = token.strip_prefix('#').ok_or(error)?;
+        if !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(error);
+        }
+        let mut bytes = hex.as_bytes().chunks(2).map(|pair| {
+            pair.iter()
+                .fold(0, |byte, &digit| (byte << 4) | hex_value(digit))
+        });
+
+        let channels = P::default().fold_channels(0, |count, _, _| count + 1);
+        match hex.len() {
+            6 => Ok(P::from_rgb(RGB8::new(
+                bytes.next().unwrap_or(0),
+                bytes.next().unwrap_or(0),
+                bytes.next().unwrap_or(0),
+            ))),
+            len if len == channels * 2 => {
+                Ok(P::default().map_channels(|_, _| bytes.next().unwrap_or(0)))
+            }
+            _ => Err(error),
+        }
+    }
+}
+
+fn hex_value(digit: u8) -> u8 {
+    match digit {
+        b'0'..=b'9' => digit - b'0',
+        b'a'..=b'f' => digit - b'a' + 10,
+        _ => digit - b'A' + 10,
+    }
+}
+
+// The names and numbers below are shared with the serial shell, so both accept the same words:
+
+/// Parses a whole number, which may have underscores between its digits.
+pub(crate) fn parse_number(token: &str) -> Option<u64> {
+    if !token.starts_with(|c: char| c.is_ascii_digit()) {
+        return None;
+    }
+    token
+        .chars()
+        .filter(|&c| c != '_')
+        .try_fold(0_u64, |total, c| {
+            total.checked_mul(10)?.checked_add(c.to_digit(10)? as u64)
+        })
+}
+
+/// Splits a duration like `250ms` into its number and its unit.
+pub(crate) fn split_duration(token: &str) -> (&str, &str) {
+    let split = token
+        .find(|c: char| !c.is_ascii_digit() && c != '_')
+        .unwrap_or(token.len());
+    token.split_at(split)
+}
+
+/// Returns the number of nanoseconds in a duration unit. Durations without a unit are nanoseconds.
+pub(crate) fn duration_scale(unit: &str) -> Option<u64> {
+    match unit {
+        "" | "ns" => Some(1),
+        "us" => Some(1_000),
+        "ms" => Some(1_000_000),
+        "s" => Some(1_000_000_000),
+        _ => None,
+    }
+}
+
+/// Parses a value like `name(argument)`, returning the argument.
+pub(crate) fn argument_of<'t>(value: &'t str, name: &str) -> Option<&'t str> {
+    value
+        .strip_prefix(name)?
+        .strip_prefix('(')?
+        .strip_suffix(')')
+        .map(str::trim)
+}
+
+pub(crate) fn bg_mode_named<P>(name: &str) -> Option<background::Mode<P>> {
+    Some(match name {
+        "no_background" => background::Mode::NoBackground,
+        "transparent" => background::Mode::Transparent,
+        "solid" => background::Mode::Solid,
+        "solid_fade" => background::Mode::SolidFade,
+        "fill_rainbow" => background::Mode::FillRainbow,
+        "fill_rainbow_rotate" => background::Mode::FillRainbowRotate,
+        "plasma" => background::Mode::Plasma,
+        "radial_rainbow" => background::Mode::RadialRainbow,
+        "linear_gradient" => background::Mode::LinearGradient,
+        "axis_gradient" => background::Mode::AxisGradient,
+        _ => return None,
+    })
+}
+
+pub(crate) fn fg_mode_named<P>(name: &str) -> Option<foreground::Mode<P>> {
+    Some(match name {
+        "no_foreground" => foreground::Mode::NoForeground,
+        "marquee_solid" => foreground::Mode::MarqueeSolid,
+        "marquee_solid_fixed" => foreground::Mode::MarqueeSolidFixed,
+        "marquee_fade" => foreground::Mode::MarqueeFade,
+        "marquee_fade_fixed" => foreground::Mode::MarqueeFadeFixed,
+        "vu_meter" => foreground::Mode::VUMeter,
+        "scrolling_bars" => foreground::Mode::ScrollingBars,
+        "plane_sweep" => foreground::Mode::PlaneSweep,
+        _ => return None,
+    })
+}
+
+pub(crate) fn trigger_mode_named<P>(name: &str) -> Option<trigger::Mode<P>> {
+    Some(match name {
+        "no_trigger" => trigger::Mode::NoTrigger,
+        "background" => trigger::Mode::Background,
+        "foreground" => trigger::Mode::Foreground,
+        "color_pulse" => trigger::Mode::ColorPulse,
+        "color_pulse_fade" => trigger::Mode::ColorPulseFade,
+        "color_pulse_rainbow" => trigger::Mode::ColorPulseRainbow,
+        "color_shot" => trigger::Mode::ColorShot,
+        "color_shot_fade" => trigger::Mode::ColorShotFade,
+        "color_shot_rainbow" => trigger::Mode::ColorShotRainbow,
+        "flash" => trigger::Mode::Flash,
+        "flash_fade" => trigger::Mode::FlashFade,
+        "flash_rainbow" => trigger::Mode::FlashRainbow,
+        "expanding_ring" => trigger::Mode::ExpandingRing,
+        "expanding_ring_fade" => trigger::Mode::ExpandingRingFade,
+        "expanding_ring_rainbow" => trigger::Mode::ExpandingRingRainbow,
+        "expanding_sphere" => trigger::Mode::ExpandingSphere,
+        "expanding_sphere_fade" => trigger::Mode::ExpandingSphereFade,
+        "expanding_sphere_rainbow" => trigger::Mode::ExpandingSphereRainbow,
+        _ => return None,
+    })
+}
+
+pub(crate) fn direction_named(name: &str) -> Option<Direction> {
+    match name {
+        "positive" => Some(Direction::Positive),
+        "stopped" => Some(Direction::Stopped),
+        "negative" => Some(Direction::Negative),
+        _ => None,
+    }
+}
+
+pub(crate) fn rainbow_dir_named(name: &str) -> Option<RainbowDir> {
+    match name {
+        "forward" => Some(RainbowDir::Forward),
+        "backward" => Some(RainbowDir::Backward),
+        _ => None,
+    }
+}
+
+/// Looks up every blend mode except `alpha(opacity)`, which takes an argument.
+pub(crate) fn blend_mode_named(name: &str) -> Option<BlendMode> {
+    match name {
+        "replace" => Some(BlendMode::Replace),
+        "additive" => Some(BlendMode::Additive),
+        "multiply" => Some(BlendMode::Multiply),
+        "screen" => Some(BlendMode::Screen),
+        "lighten" => Some(BlendMode::Lighten),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::default_animations::ANI_DEFAULT;
+
+    const TRIGGER: trigger::Parameters = trigger::Parameters {
+        mode: trigger::Mode::ColorPulse,
+        direction: Direction::Positive,
+        fade_in_time_ns: 0,
+        fade_out_time_ns: 0,
+        starting_offset: 0,
+        pixels_per_pixel_group: 1,
+        blend_mode: BlendMode::Replace,
+        brightness: u8::MAX,
+    };
+
+    fn animation_error(text: &str) -> SceneError {
+        match parse_animation::<8, RGB8>(text, &ANI_DEFAULT, &CustomModes::none()) {
+            Ok(_) => panic!("parsed {:?}", text),
+            Err(error) => error,
+        }
+    }
+
+    fn trigger_error(text: &str) -> SceneError {
+        match parse_trigger::<RGB8>(text, &TRIGGER, &CustomModes::none()) {
+            Ok(_) => panic!("parsed {:?}", text),
+            Err(error) => error,
+        }
+    }
+
+    fn at(line: usize, column: usize, kind: SceneErrorKind) -> SceneError {
+        SceneError { line, column, kind }
+    }
+
+    #[test]
+    fn parses_a_scene() {
+        let text = "# comment\n\nbg.mode = plasma\nbg.rainbow = #ff0000 #00ff00\n\
+                    bg.duration_ns = 1_500ms\nfg.blend_mode = alpha(128)\nfg.subdivisions = 4\n";
+        let preset = parse_animation::<8, RGB8>(text, &ANI_DEFAULT, &CustomModes::none()).unwrap();
+        let params = preset.parameters();
+        assert!(matches!(params.bg.mode, background::Mode::Plasma));
+        assert_eq!(
+            params.bg.rainbow,
+            [RGB8::new(255, 0, 0), RGB8::new(0, 255, 0)]
+        );
+        assert_eq!(params.bg.duration_ns, 1_500_000_000);
+        assert_eq!(params.fg.blend_mode, BlendMode::Alpha(128));
+        assert_eq!(params.fg.subdivisions, 4);
+    }
+
+    #[test]
+    fn points_at_a_bad_key() {
+        let text = "bg.mode = plasma\n  bg.colour = #ffffff";
+        assert_eq!(animation_error(text), at(2, 3, SceneErrorKind::UnknownKey));
+    }
+
+    #[test]
+    fn points_at_a_bad_value() {
+        let text = "# comment\nfg.mode =  sideways";
+        assert_eq!(
+            animation_error(text),
+            at(2, 12, SceneErrorKind::InvalidValue)
+        );
+    }
+
+    #[test]
+    fn points_at_a_bad_color() {
+        let text = "bg.rainbow = #ff0000 #00ff0";
+        assert_eq!(
+            animation_error(text),
+            at(1, 22, SceneErrorKind::InvalidColor)
+        );
+    }
+
+    #[test]
+    fn points_at_an_unterminated_argument() {
+        let text = "fg.blend_mode = alpha(128";
+        assert_eq!(
+            animation_error(text),
+            at(1, 17, SceneErrorKind::InvalidValue)
+        );
+    }
+
+    #[test]
+    fn points_at_a_line_without_equals() {
+        let text = "bg.mode = plasma\n\n   fg.mode plasma";
+        assert_eq!(
+            animation_error(text),
+            at(3, 4, SceneErrorKind::MissingEquals)
+        );
+    }
+
+    #[test]
+    fn rejects_numbers_out_of_range() {
+        assert_eq!(
+            trigger_error("brightness = 256"),
+            at(1, 14, SceneErrorKind::InvalidNumber)
+        );
+        assert_eq!(
+            trigger_error("starting_offset = 65_536"),
+            at(1, 19, SceneErrorKind::InvalidNumber)
+        );
+        assert_eq!(
+            trigger_error("blend_mode = alpha( 300 )"),
+            at(1, 21, SceneErrorKind::InvalidNumber)
+        );
+        assert_eq!(
+            animation_error("fg.subdivisions = 99999999999999999999"),
+            at(1, 19, SceneErrorKind::InvalidNumber)
+        );
+    }
+
+    #[test]
+    fn rejects_negative_numbers() {
+        assert_eq!(
+            animation_error("bg.subdivisions = -1"),
+            at(1, 19, SceneErrorKind::InvalidNumber)
+        );
+        assert_eq!(
+            trigger_error("pixels_per_pixel_group = -4"),
+            at(1, 26, SceneErrorKind::InvalidNumber)
+        );
+    }
+}
@@ -0,0 +1,14 @@
+//! These parse the protocols ambient lighting software on a PC uses to stream frames to a strip
+//! over USB serial. Each parser takes the stream a byte at a time, writes the colors straight into
+//! a slice of pixels such as a LogicalStrip's color buffer, and reports when a frame is complete so
+//! the strip can be written out to the leds.
+//!
+//! - `adalight` is used by Prismatik, Hyperion, HyperHDR and most other ambient light software.
+//! - `tpm2` is used by Jinx!, Glediator and other matrix software.
+//!
+//! If a frame turns out to be corrupted, the parsers skip ahead to the start of the next one. The
+//! pixels may have already been written with part of the bad frame by then, but the frame isn't
+//! reported as complete, and the next good frame replaces it.
+
+pub mod adalight;
+pub mod tpm2;
@@ -0,0 +1,163 @@
+//! Adalight frames are the bytes "Ada", the number of leds minus one as a big endian u16, a
+//! checksum of the two count bytes, and then the red, green and blue of each led in order.
+//!
+//! Most software waits to hear `HELLO` from the device before it starts sending, so it should be
+//! written to the serial port about once a second until the first frame arrives.
+
+use crate::colors::Pixel;
+use rgb::RGB8;
+
+/// The greeting a device sends to tell the software it's ready for frames.
+pub const HELLO: &[u8] = b"Ada\n";
+
+const MAGIC: &[u8] = b"Ada";
+const CHECKSUM_KEY: u8 = 0x55;
+
+#[derive(Copy, Clone)]
+enum State {
+    /// Looking for the magic word, with this many bytes of it found so far.
+    Magic(usize),
+    CountHigh,
+    CountLow(u8),
+    Checksum(u8, u8),
+    Colors,
+}
+
+pub struct AdalightParser {
+    state: State,
+    led_count: usize,
+    position: usize,
+    color: [u8; 3],
+    corrupted_frames: u32,
+}
+
+impl AdalightParser {
+    pub fn new() -> Self {
+        AdalightParser {
+            state: State::Magic(0),
+            led_count: 0,
+            position: 0,
+            color: [0; 3],
+            corrupted_frames: 0,
+        }
+    }
+
+    /// Returns the number of leds in the last frame header received.
+    pub fn led_count(&self) -> usize {
+        self.led_count
+    }
+
+    /// Returns the number of frame headers that have failed their checksum.
+    pub fn corrupted_frames(&self) -> u32 {
+        self.corrupted_frames
+    }
+
+    /// Goes back to waiting for the start of a frame. Adalight has no way to notice a frame being
+    /// cut short, so this is useful when the stream has gone quiet in the middle of a frame.
+    pub fn reset(&mut self) {
+        self.state = State::Magic(0);
+    }
+
+    /// Feeds the next byte of the stream into the parser, returning true when `byte` completes a
+    /// frame. Leds past the end of `pixels` are ignored.
+    pub fn parse<P: Pixel>(&mut self, byte: u8, pixels: &mut [P]) -> bool {
+        self.state = match self.state {
+            State::Magic(found) if byte == MAGIC[found] => match found + 1 {
+                3 => State::CountHigh,
+                found => State::Magic(found),
+            },
+            // A mismatch might still be the start of the next magic word:
+            State::Magic(_) => State::Magic((byte == MAGIC[0]) as usize),
+            State::CountHigh => State::CountLow(byte),
+            State::CountLow(high) => State::Checksum(high, byte),
+            State::Checksum(high, low) if byte == high ^ low ^ CHECKSUM_KEY => {
+                self.led_count = u16::from_be_bytes([high, low]) as usize + 1;
+                self.position = 0;
+                State::Colors
+            }
+            State::Checksum(..) => {
+                self.corrupted_frames = self.corrupted_frames.wrapping_add(1);
+                State::Magic((byte == MAGIC[0]) as usize)
+            }
+            State::Colors => {
+                let (led, channel) = (self.position / 3, self.position % 3);
+                self.color[channel] = byte;
+                self.position += 1;
+                if let (2, Some(pixel)) = (channel, pixels.get_mut(led)) {
+                    let [r, g, b] = self.color;
+                    *pixel = P::from_rgb(RGB8::new(r, g, b));
+                }
+                if self.position == self.led_count * 3 {
+                    self.state = State::Magic(0);
+                    return true;
+                }
+                State::Colors
+            }
+        };
+        false
+    }
+
+    /// Parses every byte in `bytes`, returning true if at least one frame was completed.
+    pub fn parse_all<P: Pixel>(&mut self, bytes: &[u8], pixels: &mut [P]) -> bool {
+        let mut is_complete = false;
+        for &byte in bytes {
+            is_complete |= self.parse(byte, pixels);
+        }
+        is_complete
+    }
+}
+
+impl Default for AdalightParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Two leds: the count minus one is 1, and its checksum is 0x00 ^ 0x01 ^ 0x55.
+    const FRAME: &[u8] = b"Ada\x00\x01\x54\xFF\x00\x00\x00\x80\x40";
+    const PIXELS: [RGB8; 2] = [RGB8::new(255, 0, 0), RGB8::new(0, 128, 64)];
+
+    #[test]
+    fn reads_a_frame() {
+        let mut parser = AdalightParser::new();
+        let mut pixels = [RGB8::default(); 2];
+        assert!(parser.parse_all(FRAME, &mut pixels));
+        assert_eq!(parser.led_count(), 2);
+        assert_eq!(pixels, PIXELS);
+    }
+
+    #[test]
+    fn skips_garbage_before_a_frame() {
+        let mut parser = AdalightParser::new();
+        let mut pixels = [RGB8::default(); 2];
+        assert!(!parser.parse_all(b"\x00AdAAd\xFFA", &mut pixels));
+        assert!(parser.parse_all(FRAME, &mut pixels));
+        assert_eq!(pixels, PIXELS);
+        assert_eq!(parser.corrupted_frames(), 0);
+    }
+
+    #[test]
+    fn skips_headers_with_a_bad_checksum() {
+        let mut parser = AdalightParser::new();
+        let mut pixels = [RGB8::default(); 2];
+        assert!(!parser.parse_all(b"Ada\x00\x01\x00\xFF\xFF\xFF", &mut pixels));
+        assert_eq!(parser.corrupted_frames(), 1);
+        assert!(parser.parse_all(FRAME, &mut pixels));
+        assert_eq!(pixels, PIXELS);
+    }
+
+    #[test]
+    fn finds_a_frame_starting_in_a_bad_checksum() {
+        let mut parser = AdalightParser::new();
+        let mut pixels = [RGB8::default(); 2];
+        // The bad checksum is the "A" of the next frame:
+        assert!(!parser.parse_all(b"Ada\x00\x01", &mut pixels));
+        assert!(parser.parse_all(FRAME, &mut pixels));
+        assert_eq!(pixels, PIXELS);
+        assert_eq!(parser.corrupted_frames(), 1);
+    }
+}
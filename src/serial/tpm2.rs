@@ -0,0 +1,178 @@
+//! TPM2 packets are a start byte, a packet type, the size of the payload as a big endian u16, the
+//! payload, and an end byte. Data frames carry the channels of each led in order, as set by the
+//! parser's color order. Command and response packets are skipped.
+
+use crate::colors::{order, ColorOrder, Pixel};
+
+const START_BYTE: u8 = 0xC9;
+const END_BYTE: u8 = 0x36;
+const DATA_FRAME: u8 = 0xDA;
+
+#[derive(Copy, Clone)]
+enum State {
+    Start,
+    PacketType,
+    SizeHigh,
+    SizeLow(u8),
+    Payload,
+    End,
+}
+
+pub struct Tpm2Parser {
+    pub color_order: ColorOrder,
+    state: State,
+    packet_type: u8,
+    size: usize,
+    position: usize,
+    corrupted_frames: u32,
+}
+
+impl Tpm2Parser {
+    pub fn new() -> Self {
+        Tpm2Parser {
+            color_order: order::RGB,
+            state: State::Start,
+            packet_type: 0,
+            size: 0,
+            position: 0,
+            corrupted_frames: 0,
+        }
+    }
+
+    pub fn set_color_order(mut self, color_order: ColorOrder) -> Self {
+        self.color_order = color_order;
+        self
+    }
+
+    /// Returns the number of frames that didn't end with the end byte.
+    pub fn corrupted_frames(&self) -> u32 {
+        self.corrupted_frames
+    }
+
+    /// Goes back to waiting for the start of a packet.
+    pub fn reset(&mut self) {
+        self.state = State::Start;
+    }
+
+    /// Feeds the next byte of the stream into the parser, returning true when `byte` completes a
+    /// data frame. Channels past the end of `pixels` are ignored.
+    pub fn parse<P: Pixel>(&mut self, byte: u8, pixels: &mut [P]) -> bool {
+        self.state = match self.state {
+            State::Start if byte == START_BYTE => State::PacketType,
+            State::Start => State::Start,
+            State::PacketType => {
+                self.packet_type = byte;
+                State::SizeHigh
+            }
+            State::SizeHigh => State::SizeLow(byte),
+            State::SizeLow(high) => {
+                self.size = u16::from_be_bytes([high, byte]) as usize;
+                self.position = 0;
+                match self.size {
+                    0 => State::End,
+                    _ => State::Payload,
+                }
+            }
+            State::Payload => {
+                if self.packet_type == DATA_FRAME {
+                    self.write_channel(byte, pixels);
+                }
+                self.position += 1;
+                match self.position == self.size {
+                    true => State::End,
+                    false => State::Payload,
+                }
+            }
+            State::End if byte == END_BYTE => {
+                self.state = State::Start;
+                return self.packet_type == DATA_FRAME;
+            }
+            State::End => {
+                self.corrupted_frames = self.corrupted_frames.wrapping_add(1);
+                // The missing end byte might have been dropped, leaving this as the next start:
+                match byte {
+                    START_BYTE => State::PacketType,
+                    _ => State::Start,
+                }
+            }
+        };
+        false
+    }
+
+    /// Parses every byte in `bytes`, returning true if at least one data frame was completed.
+    pub fn parse_all<P: Pixel>(&mut self, bytes: &[u8], pixels: &mut [P]) -> bool {
+        let mut is_complete = false;
+        for &byte in bytes {
+            is_complete |= self.parse(byte, pixels);
+        }
+        is_complete
+    }
+
+    fn write_channel<P: Pixel>(&self, value: u8, pixels: &mut [P]) {
+        let channels_per_pixel = self.color_order.len();
+        if channels_per_pixel == 0 {
+            return;
+        }
+        if let Some(pixel) = pixels.get_mut(self.position / channels_per_pixel) {
+            let channel = self.color_order[self.position % channels_per_pixel];
+            *pixel = pixel.with_channel(channel, value);
+        }
+    }
+}
+
+impl Default for Tpm2Parser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rgb::RGB8;
+
+    const FRAME: &[u8] = &[
+        START_BYTE, DATA_FRAME, 0, 6, 255, 0, 0, 0, 128, 64, END_BYTE,
+    ];
+    const PIXELS: [RGB8; 2] = [RGB8::new(255, 0, 0), RGB8::new(0, 128, 64)];
+
+    #[test]
+    fn reads_a_frame() {
+        let mut parser = Tpm2Parser::new();
+        let mut pixels = [RGB8::default(); 2];
+        assert!(parser.parse_all(FRAME, &mut pixels));
+        assert_eq!(pixels, PIXELS);
+    }
+
+    #[test]
+    fn skips_garbage_before_a_frame() {
+        let mut parser = Tpm2Parser::new();
+        let mut pixels = [RGB8::default(); 2];
+        assert!(!parser.parse_all(&[0x00, 0x36, 0xFF, 0xDA], &mut pixels));
+        assert!(parser.parse_all(FRAME, &mut pixels));
+        assert_eq!(pixels, PIXELS);
+        assert_eq!(parser.corrupted_frames(), 0);
+    }
+
+    #[test]
+    fn skips_command_packets() {
+        let mut parser = Tpm2Parser::new();
+        let mut pixels = [RGB8::default(); 2];
+        let command = [START_BYTE, 0xC0, 0, 3, 1, 2, 3, END_BYTE];
+        assert!(!parser.parse_all(&command, &mut pixels));
+        assert_eq!(pixels, [RGB8::default(); 2]);
+        assert!(parser.parse_all(FRAME, &mut pixels));
+        assert_eq!(pixels, PIXELS);
+    }
+
+    #[test]
+    fn reads_the_next_frame_after_a_missing_end_byte() {
+        let mut parser = Tpm2Parser::new();
+        let mut pixels = [RGB8::default(); 2];
+        let cut_short = &[START_BYTE, DATA_FRAME, 0, 3, 1, 2, 3];
+        assert!(!parser.parse_all(cut_short, &mut pixels));
+        assert!(parser.parse_all(FRAME, &mut pixels));
+        assert_eq!(pixels, PIXELS);
+        assert_eq!(parser.corrupted_frames(), 1);
+    }
+}
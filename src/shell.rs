@@ -0,0 +1,611 @@
+//! This is a small command interpreter for changing a LightingController while it runs, such as
+//! from a serial terminal during an installation, instead of reflashing for every tweak. Commands
+//! are a line of words separated by spaces:
+//!
+//! ```text
+//! brightness 80
+//! anim 0 bg mode solid_fade duration 5s
+//! anim 0 fg rainbow 14 backward step 100ms
+//! anim 1 trig color_shot dir + width 3
+//! anim 1 layer opacity 128 blend additive
+//! ```
+//!
+//! Modes, directions, blend modes and durations are written the same way as in a scene, and `help`
+//! lists every command. Rainbows can't be typed in, so they're picked by index from the list given
+//! to `Shell::set_rainbows()`, such as `colors::RAINBOW_ARRAY`.
+//!
+//! Serial bytes can be collected into lines with a `LineBuffer`. Nothing is echoed back, so
+//! terminals should have local echo turned on.
+
+use crate::animations::{
+    background, foreground, trigger, Animatable, AnimationType, Direction, RainbowDir,
+};
+use crate::colors::{BlendMode, Pixel};
+use crate::preset::{custom_entry, CustomModes};
+use crate::scene::{
+    argument_of, bg_mode_named, blend_mode_named, direction_named, duration_scale, fg_mode_named,
+    parse_number, rainbow_dir_named, split_duration, trigger_mode_named,
+};
+use crate::LightingController;
+use arrayvec::ArrayVec;
+use core::fmt::{self, Write};
+use core::str::SplitWhitespace;
+use embedded_time::fixed_point::FixedPoint;
+use embedded_time::rate::Hertz;
+use rgb::RGB8;
+
+/// The text printed by the `help` command.
+pub const HELP: &str = "\
+commands:
+  help                          show this list
+  status                        show the frame rate, brightness and power draw
+  brightness [0-255]            show or set the master brightness
+  anim <n>                      show an animation's layer
+  anim <n> bg <setting> <value> ...
+  anim <n> fg <setting> <value> ...
+      mode <name>               direction <+|0|->
+      duration <time>           rainbow <index> [backward]
+      subdivisions <count>      blend <mode>
+      offset <0-65535>
+      step <time> (fg only)     width <leds> (fg only)
+  anim <n> trig <mode> [dir <+|0|->] [width <leds>] [offset <0-65535>]
+                [fade_in <time>] [fade_out <time>] [blend <mode>] [brightness <0-255>]
+  anim <n> trig duration <time>
+  anim <n> trig rainbow <index> [backward]
+  anim <n> layer [z <-128-127>] [opacity <0-255>] [brightness <0-255>] [blend <mode>]
+times: 500ms, 2s, 100us or plain nanoseconds
+";
+
+/// This reports what was wrong with a command. The column counts bytes from the start of the line,
+/// starting at 1, and points at the word that caused the error.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ShellError {
+    pub column: usize,
+    pub kind: ShellErrorKind,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ShellErrorKind {
+    UnknownCommand,
+    /// The line ended before a value the command needs.
+    MissingArgument,
+    /// There are more words than the command takes.
+    UnexpectedArgument,
+    /// The word isn't one of the names allowed here.
+    InvalidValue,
+    /// The word isn't a number, has an unknown unit, or is out of range.
+    InvalidNumber,
+    NoSuchAnimation,
+    NoSuchRainbow,
+    UnknownCustomMode,
+    /// A line was longer than the `LineBuffer` holding it, so it was thrown away.
+    LineTooLong,
+    /// Writing the output failed.
+    Output,
+}
+
+impl fmt::Display for ShellError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let message = match self.kind {
+            ShellErrorKind::UnknownCommand => "unknown command, type `help` for a list",
+            ShellErrorKind::MissingArgument => "missing a value",
+            ShellErrorKind::UnexpectedArgument => "too many values",
+            ShellErrorKind::InvalidValue => "not a valid name here",
+            ShellErrorKind::InvalidNumber => "not a number, or out of range",
+            ShellErrorKind::NoSuchAnimation => "no animation with that number",
+            ShellErrorKind::NoSuchRainbow => "no rainbow with that number",
+            ShellErrorKind::UnknownCustomMode => "no custom mode with that number",
+            ShellErrorKind::LineTooLong => "line too long",
+            ShellErrorKind::Output => "output failed",
+        };
+        write!(f, "column {}: {}", self.column, message)
+    }
+}
+
+impl From<fmt::Error> for ShellError {
+    fn from(_: fmt::Error) -> Self {
+        ShellError {
+            column: 0,
+            kind: ShellErrorKind::Output,
+        }
+    }
+}
+
+/// This collects bytes from a serial port into lines of up to `N` bytes. Backspace and delete
+/// remove the last byte, and either a carriage return or a line feed ends the line.
+pub struct LineBuffer<const N: usize> {
+    buffer: ArrayVec<u8, N>,
+    is_complete: bool,
+    is_too_long: bool,
+}
+
+impl<const N: usize> LineBuffer<N> {
+    pub fn new() -> Self {
+        LineBuffer {
+            buffer: ArrayVec::new(),
+            is_complete: false,
+            is_too_long: false,
+        }
+    }
+
+    /// Adds the next byte, returning the line when `byte` ends it. Empty lines and lines that
+    /// aren't valid UTF-8 are skipped.
+    pub fn push(&mut self, byte: u8) -> Option<Result<&str, ShellError>> {
+        if self.is_complete {
+            self.buffer.clear();
+            self.is_complete = false;
+            self.is_too_long = false;
+        }
+        match byte {
+            b'\r' | b'\n' => {
+                self.is_complete = true;
+                if self.is_too_long {
+                    return Some(Err(ShellError {
+                        column: N + 1,
+                        kind: ShellErrorKind::LineTooLong,
+                    }));
+                }
+                match core::str::from_utf8(&self.buffer) {
+                    Ok(line) if !line.trim().is_empty() => Some(Ok(line)),
+                    _ => None,
+                }
+            }
+            0x08 | 0x7F => {
+                self.buffer.pop();
+                None
+            }
+            _ => {
+                self.is_too_long |= self.buffer.try_push(byte).is_err();
+                None
+            }
+        }
+    }
+}
+
+impl<const N: usize> Default for LineBuffer<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct Shell<'a, P = RGB8> {
+    rainbows: &'a [&'a [P]],
+    custom: CustomModes<'a, P>,
+    trigger: trigger::Parameters<P>,
+}
+
+impl<'a, P: Pixel> Shell<'a, P> {
+    /// Creates a shell without any rainbows or custom modes.
+    pub fn new() -> Self {
+        Shell {
+            rainbows: &[],
+            custom: CustomModes::none(),
+            trigger: trigger::Parameters {
+                mode: trigger::Mode::NoTrigger,
+                direction: Direction::Positive,
+                fade_in_time_ns: 0,
+                fade_out_time_ns: 500_000_000,
+                starting_offset: 0,
+                pixels_per_pixel_group: 1,
+                brightness: u8::MAX,
+                blend_mode: BlendMode::Replace,
+            },
+        }
+    }
+
+    /// Sets the rainbows that `rainbow <index>` picks from.
+    pub fn set_rainbows(mut self, rainbows: &'a [&'a [P]]) -> Self {
+        self.rainbows = rainbows;
+        self
+    }
+
+    /// Lets modes be set to `custom(<index>)`, using the same registry as presets and scenes.
+    pub fn set_custom_modes(mut self, custom: CustomModes<'a, P>) -> Self {
+        self.custom = custom;
+        self
+    }
+
+    /// Sets the parameters `anim <n> trig` starts from before applying its options.
+    pub fn set_trigger_defaults(mut self, params: trigger::Parameters<P>) -> Self {
+        self.trigger = params;
+        self
+    }
+
+    /// Runs a command, writing any errors to `out` as well as any output.
+    pub fn run<const N_ANI: usize>(
+        &self,
+        line: &str,
+        controller: &mut LightingController<'a, N_ANI, P>,
+        out: &mut impl Write,
+    ) -> Result<(), ShellError> {
+        let result = self.execute(line, controller, out);
+        if let Err(error) = result {
+            writeln!(out, "error: {}", error)?;
+        }
+        result
+    }
+
+    /// Runs a single command on the controller, writing the output of queries to `out`.
+    pub fn execute<const N_ANI: usize>(
+        &self,
+        line: &str,
+        controller: &mut LightingController<'a, N_ANI, P>,
+        out: &mut impl Write,
+    ) -> Result<(), ShellError> {
+        let mut args = Args::new(line);
+        let Some(command) = args.next() else {
+            return Ok(());
+        };
+        match command {
+            "help" => {
+                args.finish()?;
+                out.write_str(HELP)?;
+            }
+            "status" => {
+                args.finish()?;
+                writeln!(out, "frame rate: {} Hz", controller.frame_rate.integer())?;
+                writeln!(out, "animations: {}", N_ANI)?;
+                writeln!(out, "brightness: {}", controller.brightness())?;
+                writeln!(out, "dropped frames: {}", controller.dropped_frames())?;
+                writeln!(
+                    out,
+                    "draw: {} mA requested, {} mA estimated",
+                    controller.requested_draw_ma(),
+                    controller.estimated_draw_ma()
+                )?;
+            }
+            "brightness" => match args.next() {
+                Some(token) => {
+                    let brightness = args.number(token)?;
+                    args.finish()?;
+                    controller.update_brightness(brightness);
+                }
+                None => writeln!(out, "brightness: {}", controller.brightness())?,
+            },
+            "anim" => self.animation(&mut args, controller, out)?,
+            _ => return Err(args.error(command, ShellErrorKind::UnknownCommand)),
+        }
+        Ok(())
+    }
+
+    fn animation<const N_ANI: usize>(
+        &self,
+        args: &mut Args,
+        controller: &mut LightingController<'a, N_ANI, P>,
+        out: &mut impl Write,
+    ) -> Result<(), ShellError> {
+        let token = args.expect()?;
+        let index: usize = args.number(token)?;
+        if index >= N_ANI {
+            return Err(args.error(token, ShellErrorKind::NoSuchAnimation));
+        }
+
+        let frame_rate = controller.frame_rate;
+        let Some(part) = args.next() else {
+            let animation = &controller.animations[index];
+            let layer = animation.layer();
+            writeln!(
+                out,
+                "anim {}: {} leds, z {}, opacity {}, brightness {}, blend {}{}{}",
+                index,
+                animation.segment().len(),
+                layer.z_order,
+                layer.opacity,
+                layer.brightness,
+                BlendName(layer.blend_mode),
+                if animation.is_transparent() {
+                    ", transparent"
+                } else {
+                    ""
+                },
+                if controller.is_transitioning(index) {
+                    ", transitioning"
+                } else {
+                    ""
+                },
+            )?;
+            return Ok(());
+        };
+
+        match part {
+            "bg" => self.background(args, &mut *controller.animations[index], frame_rate),
+            "fg" => self.foreground(args, &mut *controller.animations[index], frame_rate),
+            "trig" => self.trigger(args, index, controller),
+            "layer" => {
+                let animation = &mut controller.animations[index];
+                let mut layer = animation.layer();
+                while let Some(setting) = args.next() {
+                    let value = args.expect()?;
+                    match setting {
+                        "z" => layer.z_order = args.signed(value)?,
+                        "opacity" => layer.opacity = args.number(value)?,
+                        "brightness" => layer.brightness = args.number(value)?,
+                        "blend" => layer.blend_mode = args.blend_mode(value)?,
+                        _ => return Err(args.error(setting, ShellErrorKind::InvalidValue)),
+                    }
+                }
+                animation.update_layer(layer);
+                Ok(())
+            }
+            _ => Err(args.error(part, ShellErrorKind::InvalidValue)),
+        }
+    }
+
+    fn background(
+        &self,
+        args: &mut Args,
+        animation: &mut dyn Animatable<'a, P>,
+        frame_rate: Hertz,
+    ) -> Result<(), ShellError> {
+        args.expect_more()?;
+        while let Some(setting) = args.next() {
+            let value = args.expect()?;
+            match setting {
+                "mode" => {
+                    let mode = match args.custom_index(value)? {
+                        Some(index) => background::Mode::Custom(args.custom_entry(
+                            value,
+                            self.custom.background,
+                            index,
+                        )?),
+                        None => bg_mode_named(value).ok_or(args.invalid(value))?,
+                    };
+                    animation.update_bg_mode(mode);
+                }
+                "direction" | "dir" => animation.update_bg_direction(args.direction(value)?),
+                "duration" => animation.update_bg_duration_ns(args.duration_ns(value)?, frame_rate),
+                "rainbow" => {
+                    let (rainbow, rainbow_dir) = self.rainbow(args, value)?;
+                    animation.update_bg_rainbow(rainbow, rainbow_dir);
+                }
+                "subdivisions" => animation.update_bg_subdivisions(args.number(value)?),
+                "blend" => animation.update_bg_blend_mode(args.blend_mode(value)?),
+                "offset" => animation.set_offset(AnimationType::Background, args.number(value)?),
+                _ => return Err(args.error(setting, ShellErrorKind::InvalidValue)),
+            }
+        }
+        Ok(())
+    }
+
+    fn foreground(
+        &self,
+        args: &mut Args,
+        animation: &mut dyn Animatable<'a, P>,
+        frame_rate: Hertz,
+    ) -> Result<(), ShellError> {
+        args.expect_more()?;
+        while let Some(setting) = args.next() {
+            let value = args.expect()?;
+            match setting {
+                "mode" => {
+                    let mode = match args.custom_index(value)? {
+                        Some(index) => foreground::Mode::Custom(args.custom_entry(
+                            value,
+                            self.custom.foreground,
+                            index,
+                        )?),
+                        None => fg_mode_named(value).ok_or(args.invalid(value))?,
+                    };
+                    animation.update_fg_mode(mode);
+                }
+                "direction" | "dir" => animation.update_fg_direction(args.direction(value)?),
+                "duration" => animation.update_fg_duration_ns(args.duration_ns(value)?, frame_rate),
+                "rainbow" => {
+                    let (rainbow, rainbow_dir) = self.rainbow(args, value)?;
+                    animation.update_fg_rainbow(rainbow, rainbow_dir);
+                }
+                "subdivisions" => animation.update_fg_subdivisions(args.number(value)?),
+                "blend" => animation.update_fg_blend_mode(args.blend_mode(value)?),
+                "offset" => animation.set_offset(AnimationType::Foreground, args.number(value)?),
+                "step" => animation.update_fg_step_time_ns(args.duration_ns(value)?, frame_rate),
+                "width" => animation.update_fg_pixels_per_pixel_group(args.number(value)?),
+                _ => return Err(args.error(setting, ShellErrorKind::InvalidValue)),
+            }
+        }
+        Ok(())
+    }
+
+    fn trigger<const N_ANI: usize>(
+        &self,
+        args: &mut Args,
+        index: usize,
+        controller: &mut LightingController<'a, N_ANI, P>,
+    ) -> Result<(), ShellError> {
+        let frame_rate = controller.frame_rate;
+        let animation = &mut controller.animations[index];
+        let first = args.expect()?;
+        match first {
+            "duration" => {
+                let value = args.expect()?;
+                animation.update_trig_duration_ns(args.duration_ns(value)?, frame_rate);
+                return args.finish();
+            }
+            "rainbow" => {
+                let value = args.expect()?;
+                let (rainbow, rainbow_dir) = self.rainbow(args, value)?;
+                args.finish()?;
+                animation.update_trig_fade_rainbow(rainbow, rainbow_dir);
+                animation.update_trig_incremental_rainbow(rainbow, rainbow_dir);
+                return Ok(());
+            }
+            _ => {}
+        }
+
+        let mut params = self.trigger;
+        params.mode = match args.custom_index(first)? {
+            Some(index) => {
+                trigger::Mode::Custom(args.custom_entry(first, self.custom.trigger, index)?)
+            }
+            None => trigger_mode_named(first).ok_or(args.invalid(first))?,
+        };
+        while let Some(setting) = args.next() {
+            let value = args.expect()?;
+            match setting {
+                "direction" | "dir" => params.direction = args.direction(value)?,
+                "width" => params.pixels_per_pixel_group = args.number(value)?,
+                "offset" => params.starting_offset = args.number(value)?,
+                "fade_in" => params.fade_in_time_ns = args.duration_ns(value)?,
+                "fade_out" => params.fade_out_time_ns = args.duration_ns(value)?,
+                "blend" => params.blend_mode = args.blend_mode(value)?,
+                "brightness" => params.brightness = args.number(value)?,
+                _ => return Err(args.error(setting, ShellErrorKind::InvalidValue)),
+            }
+        }
+        controller.trigger(index, &params);
+        Ok(())
+    }
+
+    /// Parses a rainbow index, and the `forward` or `backward` that can follow it.
+    fn rainbow(&self, args: &mut Args, value: &str) -> Result<(&'a [P], RainbowDir), ShellError> {
+        let index: usize = args.number(value)?;
+        let rainbow = *self
+            .rainbows
+            .get(index)
+            .ok_or(args.error(value, ShellErrorKind::NoSuchRainbow))?;
+        let rainbow_dir = match args.peek().and_then(rainbow_dir_named) {
+            Some(rainbow_dir) => {
+                args.next();
+                rainbow_dir
+            }
+            None => RainbowDir::Forward,
+        };
+        Ok((rainbow, rainbow_dir))
+    }
+}
+
+impl<'a, P: Pixel> Default for Shell<'a, P> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The words of a command, which know where they came from so they can report errors.
+struct Args<'l> {
+    line: &'l str,
+    words: SplitWhitespace<'l>,
+}
+
+impl<'l> Args<'l> {
+    fn new(line: &'l str) -> Self {
+        Args {
+            line,
+            words: line.split_whitespace(),
+        }
+    }
+
+    fn next(&mut self) -> Option<&'l str> {
+        self.words.next()
+    }
+
+    fn peek(&self) -> Option<&'l str> {
+        self.words.clone().next()
+    }
+
+    /// Returns the next word, which the command can't do without.
+    fn expect(&mut self) -> Result<&'l str, ShellError> {
+        self.words.next().ok_or(self.missing())
+    }
+
+    /// Checks there's at least one more word, without using it up.
+    fn expect_more(&self) -> Result<(), ShellError> {
+        self.peek().map(|_| ()).ok_or(self.missing())
+    }
+
+    /// Checks that every word has been used.
+    fn finish(&mut self) -> Result<(), ShellError> {
+        match self.words.next() {
+            Some(word) => Err(self.error(word, ShellErrorKind::UnexpectedArgument)),
+            None => Ok(()),
+        }
+    }
+
+    /// Builds an error pointing at `word`, which must be a slice of the line.
+    fn error(&self, word: &str, kind: ShellErrorKind) -> ShellError {
+        ShellError {
+            column: word.as_ptr() as usize - self.line.as_ptr() as usize + 1,
+            kind,
+        }
+    }
+
+    fn invalid(&self, word: &str) -> ShellError {
+        self.error(word, ShellErrorKind::InvalidValue)
+    }
+
+    /// Builds an error pointing just past the end of the line.
+    fn missing(&self) -> ShellError {
+        ShellError {
+            column: self.line.trim_end().len() + 1,
+            kind: ShellErrorKind::MissingArgument,
+        }
+    }
+
+    fn number<T: TryFrom<u64>>(&self, word: &str) -> Result<T, ShellError> {
+        parse_number(word)
+            .and_then(|number| T::try_from(number).ok())
+            .ok_or(self.error(word, ShellErrorKind::InvalidNumber))
+    }
+
+    fn signed(&self, word: &str) -> Result<i8, ShellError> {
+        let error = self.error(word, ShellErrorKind::InvalidNumber);
+        match word.strip_prefix('-') {
+            Some(magnitude) => {
+                let magnitude = parse_number(magnitude).ok_or(error)?;
+                i8::try_from(-i64::try_from(magnitude).map_err(|_| error)?).map_err(|_| error)
+            }
+            None => self.number(word),
+        }
+    }
+
+    fn duration_ns(&self, word: &str) -> Result<u64, ShellError> {
+        let (number, unit) = split_duration(word);
+        let scale = duration_scale(unit).ok_or(self.error(unit, ShellErrorKind::InvalidNumber))?;
+        self.number::<u64>(number)?
+            .checked_mul(scale)
+            .ok_or(self.error(word, ShellErrorKind::InvalidNumber))
+    }
+
+    /// Accepts `+`, `0` and `-` as well as the full names of the directions.
+    fn direction(&self, word: &str) -> Result<Direction, ShellError> {
+        match word {
+            "+" => Ok(Direction::Positive),
+            "0" => Ok(Direction::Stopped),
+            "-" => Ok(Direction::Negative),
+            _ => direction_named(word).ok_or(self.invalid(word)),
+        }
+    }
+
+    fn blend_mode(&self, word: &str) -> Result<BlendMode, ShellError> {
+        match argument_of(word, "alpha") {
+            Some(opacity) => self.number(opacity).map(BlendMode::Alpha),
+            None => blend_mode_named(word).ok_or(self.invalid(word)),
+        }
+    }
+
+    /// Returns `Ok(Some(index))` if the word is a valid `custom(index)`.
+    fn custom_index(&self, word: &str) -> Result<Option<u8>, ShellError> {
+        match argument_of(word, "custom") {
+            Some(index) => self.number(index).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn custom_entry<F: Copy>(&self, word: &str, table: &[F], index: u8) -> Result<F, ShellError> {
+        custom_entry(table, index).map_err(|_| self.error(word, ShellErrorKind::UnknownCustomMode))
+    }
+}
+
+/// Writes a blend mode the way it's typed in.
+struct BlendName(BlendMode);
+
+impl fmt::Display for BlendName {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            BlendMode::Replace => f.write_str("replace"),
+            BlendMode::Additive => f.write_str("additive"),
+            BlendMode::Multiply => f.write_str("multiply"),
+            BlendMode::Screen => f.write_str("screen"),
+            BlendMode::Lighten => f.write_str("lighten"),
+            BlendMode::Alpha(opacity) => write!(f, "alpha({})", opacity),
+        }
+    }
+}
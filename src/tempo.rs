@@ -0,0 +1,445 @@
+//! This keeps track of musical time so animations can follow a song instead of a stopwatch. A
+//! `Tempo` is set to a fixed BPM or tapped in, and is updated with the current time once per frame.
+//! After each update it can report whether a beat, or any fraction or multiple of a beat, started
+//! since the update before it.
+//!
+//! `BeatSync` uses that to drive an animation's durations in beats, and `BeatTrigger` fires a
+//! trigger on a regular beat interval.
+
+use crate::animations::trigger;
+use crate::colors::Pixel;
+use crate::utility::convert_instant_to_ns;
+use crate::LightingController;
+use arrayvec::ArrayVec;
+use embedded_time::{Clock, Instant};
+use rgb::RGB8;
+
+/// This is how many of the most recent taps are averaged by `Tempo::tap()`.
+pub const MAX_TAPS: usize = 8;
+
+/// Taps further apart than this start a new tap tempo measurement instead of being averaged in.
+pub const TAP_TIMEOUT_NS: u64 = 2_000_000_000;
+
+pub const DEFAULT_BEATS_PER_BAR: u8 = 4;
+
+const NS_PER_MINUTE: u64 = 60_000_000_000;
+
+// Beat positions are fixed point numbers with this many fractional bits:
+const BEAT_FRACTION_BITS: u32 = 16;
+const ONE_BEAT: u64 = 1 << BEAT_FRACTION_BITS;
+
+/// A length of musical time, as a fraction of a beat. `Beats::new(4)` is four beats, and
+/// `Beats::fraction(1, 2)` is an eighth note when a beat is a quarter note.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Beats {
+    pub numerator: u32,
+    pub denominator: u32,
+}
+
+impl Beats {
+    pub const ZERO: Beats = Beats::new(0);
+
+    pub const fn new(beats: u32) -> Self {
+        Beats::fraction(beats, 1)
+    }
+
+    pub const fn fraction(numerator: u32, denominator: u32) -> Self {
+        Beats {
+            numerator,
+            denominator,
+        }
+    }
+
+    /// Converts to a fixed point beat position.
+    fn to_fixed(self) -> u64 {
+        match self.denominator {
+            0 => 0,
+            denominator => (self.numerator as u64 * ONE_BEAT) / denominator as u64,
+        }
+    }
+}
+
+/// Where the tempo is at in the song.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct BeatPosition {
+    /// The number of whole bars since the tempo started.
+    pub bar: u32,
+    /// The beat within the bar, starting at 0 for the downbeat.
+    pub beat: u8,
+    /// How far through the beat it is, from 0 on the beat to 255 just before the next one.
+    pub phase: u8,
+}
+
+pub struct Tempo {
+    beat_ns: u64,
+    beats_per_bar: u8,
+    // The beat position at `anchor_ns`. Positions at other times are measured from here:
+    anchor_ns: u64,
+    anchor_beats: u64,
+    is_anchored: bool,
+    is_running: bool,
+    previous_ns: Option<u64>,
+    current_ns: Option<u64>,
+    previous_beat_ns: u64,
+    updated_beat_ns: u64,
+    taps: ArrayVec<u64, MAX_TAPS>,
+}
+
+impl Tempo {
+    pub fn new(bpm: u16) -> Self {
+        let beat_ns = bpm_to_beat_ns(bpm);
+        Tempo {
+            beat_ns,
+            beats_per_bar: DEFAULT_BEATS_PER_BAR,
+            anchor_ns: 0,
+            anchor_beats: 0,
+            is_anchored: false,
+            is_running: true,
+            previous_ns: None,
+            current_ns: None,
+            previous_beat_ns: beat_ns,
+            updated_beat_ns: beat_ns,
+            taps: ArrayVec::new(),
+        }
+    }
+
+    pub fn set_beats_per_bar(mut self, beats_per_bar: u8) -> Self {
+        self.update_beats_per_bar(beats_per_bar);
+        self
+    }
+
+    pub fn update_beats_per_bar(&mut self, beats_per_bar: u8) {
+        self.beats_per_bar = beats_per_bar.max(1);
+    }
+
+    /// Changes the tempo without jumping: the current beat position is kept, and only the speed
+    /// from here on changes.
+    pub fn update_bpm(&mut self, bpm: u16) {
+        self.update_beat_ns(bpm_to_beat_ns(bpm));
+    }
+
+    /// Changes the length of a beat, in the same way as `update_bpm()`.
+    pub fn update_beat_ns(&mut self, beat_ns: u64) {
+        if let Some(current_ns) = self.current_ns {
+            self.move_anchor(current_ns);
+        }
+        self.beat_ns = beat_ns.max(1);
+    }
+
+    /// Returns the tempo rounded to the nearest beat per minute.
+    pub fn bpm(&self) -> u16 {
+        ((NS_PER_MINUTE + self.beat_ns / 2) / self.beat_ns).min(u16::MAX as u64) as u16
+    }
+
+    pub fn beat_ns(&self) -> u64 {
+        self.beat_ns
+    }
+
+    pub fn beats_per_bar(&self) -> u8 {
+        self.beats_per_bar
+    }
+
+    /// Returns the length of `beats` at the current tempo.
+    pub fn duration_ns(&self, beats: Beats) -> u64 {
+        match beats.denominator {
+            0 => 0,
+            denominator => {
+                (self.beat_ns as u128 * beats.numerator as u128 / denominator as u128) as u64
+            }
+        }
+    }
+
+    /// Registers a tap on the beat. After two or more taps in a row the tempo is set to their
+    /// average spacing. Every tap also moves the beat to line up with it.
+    pub fn tap<C>(&mut self, now: Instant<C>)
+    where
+        C: Clock,
+        u64: TryFrom<C::T>,
+    {
+        if let Some(now_ns) = convert_instant_to_ns(now) {
+            self.tap_ns(now_ns);
+        }
+    }
+
+    /// Same as `tap()`, with the time given in nanoseconds.
+    pub fn tap_ns(&mut self, now_ns: u64) {
+        self.anchor_if_unstarted(now_ns);
+        let is_new_measurement = match self.taps.last() {
+            Some(&last) => now_ns < last || now_ns - last > TAP_TIMEOUT_NS,
+            None => true,
+        };
+        if is_new_measurement {
+            self.taps.clear();
+        }
+        if self.taps.is_full() {
+            self.taps.remove(0);
+        }
+        self.taps.push(now_ns);
+
+        if let (Some(&first), Some(&last)) = (self.taps.first(), self.taps.last()) {
+            if self.taps.len() >= 2 {
+                self.move_anchor(now_ns);
+                self.beat_ns = ((last - first) / (self.taps.len() as u64 - 1)).max(1);
+            }
+        }
+
+        // Snap the beat position to the nearest whole beat at the tap:
+        let beats = self.beats_at(now_ns);
+        self.anchor_ns = now_ns;
+        self.anchor_beats = (beats + ONE_BEAT / 2) & !(ONE_BEAT - 1);
+    }
+
+    /// Marks `now` as the first beat of a bar, keeping the tempo the same.
+    pub fn sync_downbeat<C>(&mut self, now: Instant<C>)
+    where
+        C: Clock,
+        u64: TryFrom<C::T>,
+    {
+        if let Some(now_ns) = convert_instant_to_ns(now) {
+            self.sync_downbeat_ns(now_ns);
+        }
+    }
+
+    /// Same as `sync_downbeat()`, with the time given in nanoseconds.
+    pub fn sync_downbeat_ns(&mut self, now_ns: u64) {
+        self.anchor_if_unstarted(now_ns);
+        let bar = ONE_BEAT * self.beats_per_bar as u64;
+        let beats = self.beats_at(now_ns);
+        self.anchor_ns = now_ns;
+        self.anchor_beats = (beats + bar / 2) / bar * bar;
+    }
+
+    /// Sets the beat position at `now_ns`, such as when following the song position of an external
+    /// clock. The tempo is unchanged.
+    pub fn seek_ns(&mut self, now_ns: u64, position: Beats) {
+        self.anchor_ns = now_ns;
+        self.anchor_beats = position.to_fixed();
+        self.is_anchored = true;
+    }
+
+    /// Holds the beat position where it is at `now_ns` until `resume_ns()` is called. No intervals
+    /// are crossed while the tempo is paused.
+    pub fn pause_ns(&mut self, now_ns: u64) {
+        if self.is_running {
+            self.anchor_if_unstarted(now_ns);
+            self.move_anchor(now_ns);
+            self.is_running = false;
+        }
+    }
+
+    /// Starts the beat position moving again from where it was paused.
+    pub fn resume_ns(&mut self, now_ns: u64) {
+        if !self.is_running {
+            self.anchor_ns = now_ns;
+            self.is_anchored = true;
+            self.is_running = true;
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.is_running
+    }
+
+    /// Moves the tempo forward to `now`. This should be called once per frame, before using
+    /// `crossed()` or anything that depends on it. The first beat is at the first update, unless
+    /// the tempo was tapped or synced before then.
+    pub fn update<C>(&mut self, now: Instant<C>)
+    where
+        C: Clock,
+        u64: TryFrom<C::T>,
+    {
+        if let Some(now_ns) = convert_instant_to_ns(now) {
+            self.update_ns(now_ns);
+        }
+    }
+
+    /// Same as `update()`, with the time given in nanoseconds.
+    pub fn update_ns(&mut self, now_ns: u64) {
+        // Unless it was tapped or synced first, the first update is the first beat:
+        self.anchor_if_unstarted(now_ns);
+        self.previous_ns = self.current_ns;
+        self.current_ns = Some(now_ns);
+        self.previous_beat_ns = self.updated_beat_ns;
+        self.updated_beat_ns = self.beat_ns;
+    }
+
+    /// Returns true if a new interval of `beats` started between the last two updates, counting
+    /// from the first beat. The first update starts every interval.
+    pub fn crossed(&self, beats: Beats) -> bool {
+        self.crossed_with_offset(beats, Beats::ZERO)
+    }
+
+    /// Same as `crossed()`, with the intervals shifted later by `offset`. An interval of one beat
+    /// with an offset of half a beat is the offbeat.
+    pub fn crossed_with_offset(&self, beats: Beats, offset: Beats) -> bool {
+        let interval = beats.to_fixed();
+        let (Some(previous_ns), Some(current_ns)) = (self.previous_ns, self.current_ns) else {
+            return self.current_ns.is_some() && offset.to_fixed() == 0;
+        };
+        if interval == 0 {
+            return false;
+        }
+
+        let offset = offset.to_fixed();
+        let interval_index = |ns| {
+            self.beats_at(ns)
+                .checked_sub(offset)
+                .map(|beats| beats / interval)
+        };
+        let current = interval_index(current_ns);
+        current.is_some() && current != interval_index(previous_ns)
+    }
+
+    /// Returns true if the tempo was changed between the last two updates.
+    pub fn tempo_changed(&self) -> bool {
+        self.previous_beat_ns != self.updated_beat_ns
+    }
+
+    /// Returns the beat position as of the last update.
+    pub fn position(&self) -> BeatPosition {
+        let beats = self.beats_at(self.current_ns.unwrap_or(self.anchor_ns));
+        let whole_beats = beats >> BEAT_FRACTION_BITS;
+        let beats_per_bar = self.beats_per_bar as u64;
+        BeatPosition {
+            bar: (whole_beats / beats_per_bar).min(u32::MAX as u64) as u32,
+            beat: (whole_beats % beats_per_bar) as u8,
+            phase: ((beats & (ONE_BEAT - 1)) >> (BEAT_FRACTION_BITS - 8)) as u8,
+        }
+    }
+
+    /// Returns the fixed point beat position at a time, based on the current tempo.
+    fn beats_at(&self, ns: u64) -> u64 {
+        if !self.is_running {
+            return self.anchor_beats;
+        }
+        let elapsed = (ns.abs_diff(self.anchor_ns) as u128 * ONE_BEAT as u128
+            / self.beat_ns as u128)
+            .min(u64::MAX as u128) as u64;
+        match ns >= self.anchor_ns {
+            true => self.anchor_beats.saturating_add(elapsed),
+            false => self.anchor_beats.saturating_sub(elapsed),
+        }
+    }
+
+    fn anchor_if_unstarted(&mut self, ns: u64) {
+        if !self.is_anchored {
+            self.anchor_ns = ns;
+            self.is_anchored = true;
+        }
+    }
+
+    /// Measures beat positions from `ns` from now on, so the tempo can be changed without the
+    /// beat position jumping.
+    fn move_anchor(&mut self, ns: u64) {
+        self.anchor_beats = self.beats_at(ns);
+        self.anchor_ns = ns;
+    }
+}
+
+fn bpm_to_beat_ns(bpm: u16) -> u64 {
+    NS_PER_MINUTE / bpm.max(1) as u64
+}
+
+/// This sets the durations of an animation in beats instead of nanoseconds. Each duration is
+/// reapplied at the start of every one of its cycles, which keeps the animation lined up with the
+/// beat and picks up any tempo changes. Durations that are `None` are left alone.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct BeatSync {
+    pub slot: usize,
+    pub bg_duration: Option<Beats>,
+    pub fg_duration: Option<Beats>,
+    pub fg_step_time: Option<Beats>,
+    pub trigger_duration: Option<Beats>,
+}
+
+impl BeatSync {
+    pub fn new(slot: usize) -> Self {
+        BeatSync {
+            slot,
+            ..Default::default()
+        }
+    }
+
+    pub fn set_bg_duration(mut self, beats: Beats) -> Self {
+        self.bg_duration = Some(beats);
+        self
+    }
+
+    pub fn set_fg_duration(mut self, beats: Beats) -> Self {
+        self.fg_duration = Some(beats);
+        self
+    }
+
+    pub fn set_fg_step_time(mut self, beats: Beats) -> Self {
+        self.fg_step_time = Some(beats);
+        self
+    }
+
+    pub fn set_trigger_duration(mut self, beats: Beats) -> Self {
+        self.trigger_duration = Some(beats);
+        self
+    }
+
+    /// This should be called once per frame, after `Tempo::update()`.
+    pub fn update<'a, const N_ANI: usize, P: Pixel>(
+        &self,
+        tempo: &Tempo,
+        controller: &mut LightingController<'a, N_ANI, P>,
+    ) {
+        let frame_rate = controller.frame_rate;
+        let animation = &mut controller.animations[self.slot];
+        let due = |beats: Option<Beats>| beats.filter(|&b| tempo.crossed(b));
+
+        if let Some(beats) = due(self.bg_duration) {
+            animation.update_bg_duration_ns(tempo.duration_ns(beats), frame_rate);
+        }
+        if let Some(beats) = due(self.fg_duration) {
+            animation.update_fg_duration_ns(tempo.duration_ns(beats), frame_rate);
+        }
+        if let Some(beats) = due(self.fg_step_time) {
+            animation.update_fg_step_time_ns(tempo.duration_ns(beats), frame_rate);
+        }
+        if let Some(beats) = due(self.trigger_duration) {
+            animation.update_trig_duration_ns(tempo.duration_ns(beats), frame_rate);
+        }
+    }
+}
+
+/// This fires a trigger on an animation at a regular beat interval.
+#[derive(Copy, Clone)]
+pub struct BeatTrigger<P = RGB8> {
+    pub slot: usize,
+    pub every: Beats,
+    pub offset: Beats,
+    pub params: trigger::Parameters<P>,
+}
+
+impl<P: Pixel> BeatTrigger<P> {
+    pub fn new(slot: usize, every: Beats, params: trigger::Parameters<P>) -> Self {
+        BeatTrigger {
+            slot,
+            every,
+            offset: Beats::ZERO,
+            params,
+        }
+    }
+
+    pub fn set_offset(mut self, offset: Beats) -> Self {
+        self.offset = offset;
+        self
+    }
+
+    /// This should be called once per frame, after `Tempo::update()`. Returns true if the trigger
+    /// was fired.
+    pub fn update<const N_ANI: usize>(
+        &self,
+        tempo: &Tempo,
+        controller: &mut LightingController<'_, N_ANI, P>,
+    ) -> bool {
+        let is_due = tempo.crossed_with_offset(self.every, self.offset);
+        if is_due {
+            controller.trigger(self.slot, &self.params);
+        }
+        is_due
+    }
+}
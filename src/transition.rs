@@ -0,0 +1,139 @@
+//! Transitions let the LightingController switch an animation slot over to a new animation
+//! gradually instead of with a hard cut. The outgoing animation keeps running and is drawn
+//! underneath the incoming one until the transition is finished, at which point it is dropped.
+
+use crate::animations::{Animatable, Direction};
+use crate::utility::Progression;
+
+/// These are the ways an outgoing animation can be replaced by an incoming one.
+#[derive(Copy, Clone)]
+pub enum TransitionStyle {
+    /// The incoming animation replaces the outgoing one immediately.
+    Cut,
+
+    /// The incoming animation fades in over the top of the outgoing animation.
+    Crossfade,
+
+    /// The incoming animation is revealed one led at a time, starting from the first led of the
+    /// segment when `Positive` and from the last led when `Negative`. A `Stopped` wipe behaves the
+    /// same as a crossfade.
+    Wipe(Direction),
+
+    /// The outgoing animation fades out to black for the first half of the transition, and then the
+    /// incoming animation fades in from black for the second half.
+    FadeThroughBlack,
+}
+
+/// This is how strongly an animation should be drawn for the current frame of a transition.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct TransitionWeight {
+    pub brightness: u8,
+    pub opacity: u8,
+}
+
+impl TransitionWeight {
+    pub const FULL: TransitionWeight = TransitionWeight {
+        brightness: u8::MAX,
+        opacity: u8::MAX,
+    };
+
+    pub const HIDDEN: TransitionWeight = TransitionWeight {
+        brightness: u8::MAX,
+        opacity: 0,
+    };
+}
+
+/// This holds the outgoing animation of a slot and tracks how far along the transition is.
+pub struct Transition<'a, P> {
+    pub outgoing: &'a mut dyn Animatable<'a, P>,
+    pub style: TransitionStyle,
+    frames: Progression,
+}
+
+impl<'a, P> Transition<'a, P> {
+    pub fn new(
+        outgoing: &'a mut dyn Animatable<'a, P>,
+        style: TransitionStyle,
+        frame_count: usize,
+    ) -> Self {
+        Transition {
+            outgoing,
+            style,
+            frames: Progression::new(frame_count),
+        }
+    }
+
+    /// Returns how far along the transition is, from 0 at the start to 255 on the last frame.
+    pub fn progress(&self) -> u8 {
+        if self.frames.total == 0 {
+            return u8::MAX;
+        }
+        ((self.frames.get_current() + 1) * u8::MAX as usize / self.frames.total) as u8
+    }
+
+    /// Moves the transition forward one frame, and returns true once it is finished.
+    pub fn advance(&mut self) -> bool {
+        self.frames.total <= 1 || self.frames.checked_increment()
+    }
+
+    /// Returns how the outgoing animation should be drawn this frame.
+    pub fn outgoing_weight(&self) -> TransitionWeight {
+        match self.style {
+            TransitionStyle::Cut => TransitionWeight::HIDDEN,
+            TransitionStyle::FadeThroughBlack => match fade_through_black(self.progress()) {
+                FadeHalf::Out(brightness) => TransitionWeight {
+                    brightness,
+                    opacity: u8::MAX,
+                },
+                FadeHalf::In(_) => TransitionWeight::HIDDEN,
+            },
+            _ => TransitionWeight::FULL,
+        }
+    }
+
+    /// Returns how the led at `index` of a segment `len` leds long in the incoming animation
+    /// should be drawn this frame.
+    pub fn incoming_weight(&self, index: usize, len: usize) -> TransitionWeight {
+        let progress = self.progress();
+        let crossfade = TransitionWeight {
+            brightness: u8::MAX,
+            opacity: progress,
+        };
+        match self.style {
+            TransitionStyle::Cut => TransitionWeight::FULL,
+            TransitionStyle::Crossfade => crossfade,
+            TransitionStyle::Wipe(direction) => {
+                let position = match direction {
+                    Direction::Positive => index,
+                    Direction::Negative => len - 1 - index,
+                    Direction::Stopped => return crossfade,
+                };
+                let is_revealed = position * (u8::MAX as usize) < progress as usize * len;
+                match is_revealed {
+                    true => TransitionWeight::FULL,
+                    false => TransitionWeight::HIDDEN,
+                }
+            }
+            TransitionStyle::FadeThroughBlack => match fade_through_black(progress) {
+                FadeHalf::Out(_) => TransitionWeight::HIDDEN,
+                FadeHalf::In(brightness) => TransitionWeight {
+                    brightness,
+                    opacity: u8::MAX,
+                },
+            },
+        }
+    }
+}
+
+enum FadeHalf {
+    Out(u8),
+    In(u8),
+}
+
+fn fade_through_black(progress: u8) -> FadeHalf {
+    let half = u8::MAX / 2;
+    match progress <= half {
+        true => FadeHalf::Out((half - progress) * 2),
+        false => FadeHalf::In((progress - half - 1) * 2 + 1),
+    }
+}
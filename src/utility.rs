@@ -1,21 +1,35 @@
-use crate::colors::ManipulatableColor;
+use crate::colors::{ManipulatableColor, Pixel};
 use crate::{
     animations::{Direction, RainbowDir, MAX_OFFSET},
     colors::Rainbow,
 };
 use core::ops::Index;
+use embedded_time::duration::Nanoseconds;
+use embedded_time::fixed_point::FixedPoint;
 use embedded_time::rate::*;
+use embedded_time::{Clock, Instant};
 use fastrand::Rng;
-use rgb::RGB8;
 
 static mut RNG_CELL: Option<Rng> = None;
 
 pub fn convert_ns_to_frames(nanos: u64, frame_rate: Hertz) -> usize {
-    (nanos * frame_rate.integer() as u64 / 1_000_000_000_u64) as usize
+    // Multiplying as u128 keeps very long durations from overflowing:
+    (nanos as u128 * frame_rate.integer() as u128 / 1_000_000_000_u128) as usize
 }
 
 pub fn convert_ms_to_frames(millis: u64, frame_rate: Hertz) -> usize {
-    (millis * frame_rate.integer() as u64 / 1_000_u64) as usize
+    (millis as u128 * frame_rate.integer() as u128 / 1_000_u128) as usize
+}
+
+/// Returns the time since the clock's epoch in nanoseconds, or None if it doesn't fit in a u64.
+pub fn convert_instant_to_ns<C>(instant: Instant<C>) -> Option<u64>
+where
+    C: Clock,
+    u64: TryFrom<C::T>,
+{
+    Nanoseconds::<u64>::try_from(instant.duration_since_epoch())
+        .ok()
+        .map(|ns| ns.integer())
 }
 
 /// Returns a translation array beginning with index `start_at` and
@@ -36,11 +50,12 @@ pub fn set_random_seed(seed: u64) {
 
 pub fn get_random_offset() -> u16 {
     unsafe {
-        if RNG_CELL == None {
+        // This abomination brought to you by: https://doc.rust-lang.org/edition-guide/rust-2024/static-mut-references.html#safe-references
+        let rng_cell = &raw mut RNG_CELL;
+        if (*rng_cell).is_none() {
             set_random_seed(42);
         }
-        // This abomination brought to you by: https://doc.rust-lang.org/edition-guide/rust-2024/static-mut-references.html#safe-references
-        match &mut *&raw mut RNG_CELL {
+        match &mut *rng_cell {
             Some(rng) => rng.u16(..),
             _ => 42,
         }
@@ -61,12 +76,76 @@ pub fn shift_offset(starting_offset: u16, frames: Progression, direction: Direct
     (starting_offset + offset_shift) as u16
 }
 
-pub struct ReversibleRainbow<'a> {
-    backer: Rainbow<'a>,
+/// The columns and rows that the 2D modes draw a segment across, where the segment is filled row
+/// by row from the top left. A width of 0, or one wider than the segment, lays the whole segment
+/// out as a single row, so 2D modes still draw something sensible on a strip.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Plane {
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Plane {
+    pub fn new(width: usize, led_count: usize) -> Self {
+        let width = if width == 0 || width > led_count {
+            led_count.max(1)
+        } else {
+            width
+        };
+        Plane {
+            width,
+            height: led_count.div_ceil(width).max(1),
+        }
+    }
+
+    /// Returns the column and row of the pixel at `index` in the segment.
+    pub fn position(&self, index: usize) -> (usize, usize) {
+        (index % self.width, index / self.width)
+    }
+
+    /// Returns how far the pixel at `index` is from the center of the plane, in half pixels so
+    /// that the center of a plane with an even size falls between pixels.
+    pub fn from_center(&self, index: usize) -> (i32, i32) {
+        let (x, y) = self.position(index);
+        (
+            2 * x as i32 - (self.width as i32 - 1),
+            2 * y as i32 - (self.height as i32 - 1),
+        )
+    }
+
+    /// Returns how far the corner pixels are from the center of the plane, in half pixels. This is
+    /// never less than 1.
+    pub fn radius(&self) -> u32 {
+        let (x, y) = (self.width as u32 - 1, self.height as u32 - 1);
+        (x * x + y * y).isqrt().max(1)
+    }
+}
+
+/// Returns the sine of `angle` scaled to `-i16::MAX..=i16::MAX`, where a whole turn is
+/// `MAX_OFFSET + 1`, so offsets can be used as angles. This uses Bhaskara's approximation, which is
+/// within 0.2% of the real thing without needing floating point.
+pub fn sine(angle: u16) -> i32 {
+    const HALF_TURN: i64 = 1 << 15;
+    let (x, sign) = match angle as i64 {
+        a if a < HALF_TURN => (a, 1),
+        a => (a - HALF_TURN, -1),
+    };
+    let p = x * (HALF_TURN - x);
+    let value = i16::MAX as i64 * 16 * p / (5 * HALF_TURN * HALF_TURN - 4 * p);
+    sign * value as i32
+}
+
+/// Returns the cosine of `angle`, like `sine()`.
+pub fn cosine(angle: u16) -> i32 {
+    sine(angle.wrapping_add(1 << 14))
+}
+
+pub struct ReversibleRainbow<'a, P> {
+    backer: Rainbow<'a, P>,
     rainbow_dir: RainbowDir,
 }
 
-impl<'a> ReversibleRainbow<'a> {
+impl<'a, P> ReversibleRainbow<'a, P> {
     pub fn len(&self) -> usize {
         self.backer.len()
     }
@@ -76,8 +155,8 @@ impl<'a> ReversibleRainbow<'a> {
     }
 }
 
-impl<'a> Index<usize> for ReversibleRainbow<'a> {
-    type Output = RGB8;
+impl<'a, P> Index<usize> for ReversibleRainbow<'a, P> {
+    type Output = P;
 
     fn index(&self, index: usize) -> &Self::Output {
         match self.rainbow_dir {
@@ -87,11 +166,11 @@ impl<'a> Index<usize> for ReversibleRainbow<'a> {
     }
 }
 
-pub trait FadeRainbow {
-    fn rainbow(&self) -> &StatefulRainbow<'_>;
+pub trait FadeRainbow<P: Pixel> {
+    fn rainbow(&self) -> &StatefulRainbow<'_, P>;
     fn frames(&self) -> &Progression;
 
-    fn calculate_fade_color(&self) -> RGB8 {
+    fn calculate_fade_color(&self) -> P {
         let (rainbow, frames) = (self.rainbow(), self.frames());
 
         let current_color = rainbow.current_color();
@@ -102,22 +181,22 @@ pub trait FadeRainbow {
         current_color.lerp_with(next_color, *frames)
     }
 
-    fn current_fade_color(&self) -> RGB8 {
+    fn current_fade_color(&self) -> P {
         self.rainbow().current_color()
     }
 }
 
-pub trait MarchingRainbow {
-    fn rainbow(&self) -> &StatefulRainbow<'_>;
+pub trait MarchingRainbow<P: Pixel> {
+    fn rainbow(&self) -> &StatefulRainbow<'_, P>;
     fn frames(&self) -> &Progression;
 
-    fn current_rainbow_color(&self) -> RGB8 {
+    fn current_rainbow_color(&self) -> P {
         self.rainbow().current_color()
     }
 }
 
-pub trait MarchingRainbowMut<'a> {
-    fn rainbow_mut(&mut self) -> &mut StatefulRainbow<'a>;
+pub trait MarchingRainbowMut<'a, P: Pixel> {
+    fn rainbow_mut(&mut self) -> &mut StatefulRainbow<'a, P>;
     fn frames_mut(&mut self) -> &mut Progression;
 
     /// Advances the rainbow color and resets the frame count
@@ -127,14 +206,14 @@ pub trait MarchingRainbowMut<'a> {
     }
 }
 
-pub struct TimedRainbows<'a, 'b> {
-    pub fade_rainbow: &'b mut StatefulRainbow<'a>,
-    pub incremental_rainbow: &'b mut StatefulRainbow<'a>,
+pub struct TimedRainbows<'a, 'b, P> {
+    pub fade_rainbow: &'b mut StatefulRainbow<'a, P>,
+    pub incremental_rainbow: &'b mut StatefulRainbow<'a, P>,
     pub frames: &'b mut Progression,
 }
 
-impl<'a, 'b> FadeRainbow for TimedRainbows<'a, 'b> {
-    fn rainbow(&self) -> &StatefulRainbow<'_> {
+impl<'a, 'b, P: Pixel> FadeRainbow<P> for TimedRainbows<'a, 'b, P> {
+    fn rainbow(&self) -> &StatefulRainbow<'_, P> {
         self.fade_rainbow
     }
     fn frames(&self) -> &Progression {
@@ -142,8 +221,8 @@ impl<'a, 'b> FadeRainbow for TimedRainbows<'a, 'b> {
     }
 }
 
-impl<'a, 'b> MarchingRainbow for TimedRainbows<'a, 'b> {
-    fn rainbow(&self) -> &StatefulRainbow<'_> {
+impl<'a, 'b, P: Pixel> MarchingRainbow<P> for TimedRainbows<'a, 'b, P> {
+    fn rainbow(&self) -> &StatefulRainbow<'_, P> {
         self.incremental_rainbow
     }
     fn frames(&self) -> &Progression {
@@ -151,8 +230,8 @@ impl<'a, 'b> MarchingRainbow for TimedRainbows<'a, 'b> {
     }
 }
 
-impl<'a, 'b> MarchingRainbowMut<'a> for TimedRainbows<'a, 'b> {
-    fn rainbow_mut(&mut self) -> &mut StatefulRainbow<'a> {
+impl<'a, 'b, P: Pixel> MarchingRainbowMut<'a, P> for TimedRainbows<'a, 'b, P> {
+    fn rainbow_mut(&mut self) -> &mut StatefulRainbow<'a, P> {
         self.incremental_rainbow
     }
     fn frames_mut(&mut self) -> &mut Progression {
@@ -160,13 +239,13 @@ impl<'a, 'b> MarchingRainbowMut<'a> for TimedRainbows<'a, 'b> {
     }
 }
 
-pub struct StatefulRainbow<'a> {
-    pub backer: ReversibleRainbow<'a>,
+pub struct StatefulRainbow<'a, P> {
+    pub backer: ReversibleRainbow<'a, P>,
     pub position: Progression,
 }
 
-impl<'a> StatefulRainbow<'a> {
-    pub fn new(rainbow: &'a [RGB8], rainbow_dir: RainbowDir) -> StatefulRainbow<'a> {
+impl<'a, P: Pixel> StatefulRainbow<'a, P> {
+    pub fn new(rainbow: &'a [P], rainbow_dir: RainbowDir) -> StatefulRainbow<'a, P> {
         let position = Progression::new(rainbow.len());
         let backer = ReversibleRainbow {
             backer: rainbow,
@@ -175,8 +254,9 @@ impl<'a> StatefulRainbow<'a> {
         Self { backer, position }
     }
 
-    pub fn current_color(&self) -> RGB8 {
-        self.backer[self.position.get_current() as usize]
+    /// Returns the current color, or an unlit pixel if the rainbow has no colors.
+    pub fn current_color(&self) -> P {
+        self.color_at(self.position.get_current())
     }
 
     pub fn decrement(&mut self) {
@@ -187,12 +267,19 @@ impl<'a> StatefulRainbow<'a> {
         self.position.increment();
     }
 
-    pub fn peek_next_color(&self) -> RGB8 {
-        self.backer[self.position.peek_next() as usize]
+    pub fn peek_next_color(&self) -> P {
+        self.color_at(self.position.peek_next())
     }
 
-    pub fn peek_last_color(&self) -> RGB8 {
-        self.backer[self.position.peek_prev() as usize]
+    pub fn peek_last_color(&self) -> P {
+        self.color_at(self.position.peek_prev())
+    }
+
+    fn color_at(&self, index: usize) -> P {
+        match self.backer.is_empty() {
+            true => P::default(),
+            false => self.backer[index],
+        }
     }
 
     pub fn reset(&mut self) {
@@ -0,0 +1,143 @@
+//! This receives the realtime UDP protocols used by WLED, so desktop tools that can drive a WLED
+//! device can take over the strip. Every packet starts with the protocol and a timeout:
+//!
+//! - WARLS sends an index and a color for each led it changes, for up to 255 leds.
+//! - DRGB sends the colors of the leds in order, starting from the first.
+//! - DRGBW is DRGB with a white channel.
+//! - DNRGB is DRGB starting from an index, so longer strips can be split across packets.
+//!
+//! The timeout is the number of seconds the sender keeps control of the strip after its last packet.
+//! A timeout of 255 keeps control until a packet with a timeout of 0 hands it back. While a sender
+//! has control, the main loop should stop ticking the LightingController, so the sender's colors
+//! aren't drawn over, and the local animations pick up where they left off once it's done:
+//!
+//! ```ignore
+//! if let Some(payload) = socket.receive() {
+//!     realtime.receive(payload, now_ns, logical_strip.color_buffer).ok();
+//! }
+//! if realtime.is_active(now_ns) {
+//!     controller.reset_frame_timer();
+//! } else {
+//!     controller.tick(now, &mut logical_strip);
+//! }
+//! ```
+//!
+//! The colors are written as they are received, without the controller's brightness or gamma.
+
+use crate::colors::{Channel, Pixel};
+use rgb::RGB8;
+
+/// The UDP port WLED listens on for realtime data.
+pub const PORT: u16 = 21324;
+
+/// A packet timeout that keeps the sender in control until it hands control back.
+pub const NO_TIMEOUT: u8 = u8::MAX;
+
+const WARLS: u8 = 1;
+const DRGB: u8 = 2;
+const DRGBW: u8 = 3;
+const DNRGB: u8 = 4;
+
+const HEADER_LEN: usize = 2;
+const DNRGB_HEADER_LEN: usize = 4;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum WledError {
+    /// The packet ends before its header does.
+    TooShort,
+    /// The first byte isn't one of the realtime protocols. WLED's sync notifications, which use
+    /// protocol 0, are reported this way too.
+    UnknownProtocol(u8),
+}
+
+/// This tracks whether a realtime sender is in control of the strip.
+#[derive(Default)]
+pub struct WledRealtime {
+    // The time control returns to the local animations, or `None` if it already has:
+    release_ns: Option<u64>,
+}
+
+impl WledRealtime {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns true while a sender is in control of the strip at `now_ns`.
+    pub fn is_active(&self, now_ns: u64) -> bool {
+        self.release_ns
+            .is_some_and(|release_ns| now_ns < release_ns)
+    }
+
+    /// Hands control back to the local animations straight away.
+    pub fn release(&mut self) {
+        self.release_ns = None;
+    }
+
+    /// Writes the colors in a packet arriving at `now_ns` into `pixels`, and gives the sender
+    /// control for the packet's timeout. Leds past the end of `pixels` are ignored. Colors are
+    /// converted with `Pixel::from_rgb()`, so pixels with a white led get one from RGB packets too.
+    pub fn receive<P: Pixel>(
+        &mut self,
+        packet: &[u8],
+        now_ns: u64,
+        pixels: &mut [P],
+    ) -> Result<(), WledError> {
+        let [protocol, timeout, ..] = *packet else {
+            return Err(WledError::TooShort);
+        };
+        if !(WARLS..=DNRGB).contains(&protocol) {
+            return Err(WledError::UnknownProtocol(protocol));
+        }
+        if protocol == DNRGB && packet.len() < DNRGB_HEADER_LEN {
+            return Err(WledError::TooShort);
+        }
+
+        self.release_ns = match timeout {
+            0 => None,
+            NO_TIMEOUT => Some(u64::MAX),
+            seconds => Some(now_ns.saturating_add(seconds as u64 * 1_000_000_000)),
+        };
+        // A timeout of 0 only hands control back, so its colors aren't shown:
+        if self.release_ns.is_none() {
+            return Ok(());
+        }
+
+        let data = &packet[HEADER_LEN..];
+        match protocol {
+            WARLS => {
+                for led in data.chunks_exact(4) {
+                    if let Some(pixel) = pixels.get_mut(led[0] as usize) {
+                        *pixel = P::from_rgb(RGB8::new(led[1], led[2], led[3]));
+                    }
+                }
+            }
+            DRGB => write_rgb(data, pixels),
+            DRGBW => {
+                let has_white = P::default().channel(Channel::White).is_some();
+                for (led, pixel) in data.chunks_exact(4).zip(pixels.iter_mut()) {
+                    let color = RGB8::new(led[0], led[1], led[2]);
+                    *pixel = match has_white {
+                        true => P::default()
+                            .with_channel(Channel::Red, led[0])
+                            .with_channel(Channel::Green, led[1])
+                            .with_channel(Channel::Blue, led[2])
+                            .with_channel(Channel::White, led[3]),
+                        // Mix the white into the other colors instead:
+                        false => P::from_rgb(color.map_channels(|_, c| c.saturating_add(led[3]))),
+                    };
+                }
+            }
+            _ => {
+                let start = u16::from_be_bytes([data[0], data[1]]) as usize;
+                write_rgb(&data[2..], pixels.get_mut(start..).unwrap_or(&mut []));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn write_rgb<P: Pixel>(data: &[u8], pixels: &mut [P]) {
+    for (led, pixel) in data.chunks_exact(3).zip(pixels.iter_mut()) {
+        *pixel = P::from_rgb(RGB8::new(led[0], led[1], led[2]));
+    }
+}